@@ -7,6 +7,7 @@ mod error;
 use error::Error;
 use proc_macro2::Span;
 
+const ATTR_NAME: &str = "query_endpoint";
 const FIELD_NAME: &str = "features";
 
 /// Returns field names of the given struct.
@@ -17,6 +18,39 @@ fn fields(data: &syn::DataStruct) -> Vec<String> {
         .collect()
 }
 
+/// Returns whether the struct is marked with `#[query_endpoint(idempotent)]`.
+fn idempotent(attrs: &[syn::Attribute]) -> Result<bool, Error> {
+    let mut result = false;
+    for attr in attrs.iter().filter(|a| a.path.is_ident(ATTR_NAME)) {
+        let meta = attr.parse_meta().map_err(Error::from)?;
+        let list = match meta {
+            syn::Meta::List(l) => l,
+            _ => {
+                return Err(Error::new(
+                    attr.bracket_token.span,
+                    "Expected a list of options, e.g. #[query_endpoint(idempotent)]",
+                ))
+            }
+        };
+
+        for nested in list.nested.iter() {
+            match nested {
+                syn::NestedMeta::Meta(syn::Meta::Path(p)) if p.is_ident("idempotent") => {
+                    result = true
+                }
+                _ => {
+                    return Err(Error::new(
+                        attr.bracket_token.span,
+                        "Unknown option, expected `idempotent`",
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok(result)
+}
+
 fn endpoint_derive(s: synstructure::Structure) -> proc_macro2::TokenStream {
     // Validate the required field exists
     if let syn::Data::Struct(data) = &s.ast().data {
@@ -29,6 +63,11 @@ fn endpoint_derive(s: synstructure::Structure) -> proc_macro2::TokenStream {
         return Error::new(Span::call_site(), "May only be used on with structs").into_tokens();
     }
 
+    let idempotent = match idempotent(&s.ast().attrs) {
+        Ok(i) => i,
+        Err(e) => return e.into_tokens(),
+    };
+
     s.gen_impl(quote! {
         use crate::api::features::{FeaturedEndpoint, Features};
 
@@ -36,8 +75,12 @@ fn endpoint_derive(s: synstructure::Structure) -> proc_macro2::TokenStream {
             fn features(&self) -> Option<Features> {
                 self.features.clone()
             }
+
+            fn idempotent(&self) -> bool {
+                #idempotent
+            }
         }
     })
 }
 
-synstructure::decl_derive!([QueryEndpoint] => endpoint_derive);
+synstructure::decl_derive!([QueryEndpoint, attributes(query_endpoint)] => endpoint_derive);
@@ -100,7 +100,7 @@ impl Node {
     /// index change, otherwise this request will return immediately. The HTTP
     /// request will hang until a change in the key is detected or the given
     /// timeout is reached.
-    pub async fn watch(&self, index: u64, timeout: &str) {
+    pub async fn watch(&self, index: u64, timeout: Duration) {
         kv::read(
             &self.client,
             &self.key,
@@ -203,8 +203,8 @@ fn main() {
 
                         // Watching is done through using the blocking feature
                         // of the KV endpoint.
-                        let index = res.index.unwrap().parse::<u64>().unwrap();
-                        node.watch(index, "5s").await;
+                        let index = res.index.unwrap();
+                        node.watch(index, Duration::from_secs(5)).await;
 
                         // In our example we can assume that if we reached this
                         // point the leader has dropped its lock. Real use-cases
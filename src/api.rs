@@ -1,42 +1,61 @@
-use std::str::FromStr;
+use std::{convert::Infallible, str::FromStr, time::Duration};
 
 use crate::api::features::FeaturedEndpoint;
 use crate::client::Client;
 use crate::error::ClientError;
+use crate::transport::{self, StreamTransport, Transport};
 use derive_builder::Builder;
+use rustify::client::HTTP_SUCCESS_CODES;
 use rustify::endpoint::{Endpoint, EndpointResult, MiddleWare};
-use rustify::errors::ClientError as RestClientError;
+use rustify::enums::RequestMethod;
 use serde::de::DeserializeOwned;
 
+pub use crate::api::duration::GoDuration;
 pub use crate::api::features::Features;
 
 pub mod catalog;
 pub mod check;
 pub mod connect;
+pub mod duration;
 pub mod features;
 pub mod health;
 pub mod kv;
 pub mod service;
 pub mod session;
 pub mod snapshot;
+pub mod txn;
 
+/// The response from executing an API call along with the metadata Consul
+/// returns in the response headers.
 #[derive(Builder, Debug)]
 #[builder(pattern = "owned")]
 pub struct ApiResponse<T> {
+    /// How long the result has been in the agent cache (`Age`)
     #[builder(setter(into, strip_option), default)]
-    pub cache: Option<String>,
+    pub age: Option<Duration>,
+    /// Whether the result was served from the agent cache (`X-Cache`)
+    #[builder(setter(into, strip_option), default)]
+    pub cache: Option<CacheStatus>,
+    /// The hash of the response body used by hash-based blocking queries
+    /// (`X-Consul-ContentHash`)
     #[builder(setter(into, strip_option), default)]
     pub content_hash: Option<String>,
+    /// The default ACL policy of the cluster (`X-Consul-Default-ACL-Policy`)
     #[builder(setter(into, strip_option), default)]
     pub default_acl_policy: Option<String>,
+    /// The index to use for a subsequent blocking query (`X-Consul-Index`)
     #[builder(setter(into, strip_option), default)]
-    pub index: Option<String>,
+    pub index: Option<u64>,
+    /// Whether the cluster had a known leader (`X-Consul-KnownLeader`)
     #[builder(setter(into, strip_option), default)]
-    pub known_leader: Option<String>,
+    pub known_leader: Option<bool>,
+    /// The time since the server last had contact with the leader
+    /// (`X-Consul-LastContact`)
     #[builder(setter(into, strip_option), default)]
-    pub last_contact: Option<String>,
+    pub last_contact: Option<Duration>,
+    /// The backend used to serve a blocking query (`X-Consul-Query-Backend`)
     #[builder(setter(into, strip_option), default)]
-    pub query_backend: Option<String>,
+    pub query_backend: Option<QueryBackend>,
     pub response: T,
 }
 
@@ -46,55 +65,132 @@ impl<T> ApiResponse<T> {
     }
 }
 
+/// Whether a response was served from the agent cache.
+///
+/// Values which aren't known to this crate are kept as [CacheStatus::Other].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+    Other(String),
+}
+
+impl FromStr for CacheStatus {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HIT" => Ok(CacheStatus::Hit),
+            "MISS" => Ok(CacheStatus::Miss),
+            _ => Ok(CacheStatus::Other(s.to_string())),
+        }
+    }
+}
+
+/// The backend used by the server to serve a blocking query.
+///
+/// Values which aren't known to this crate are kept as [QueryBackend::Other].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QueryBackend {
+    BlockingQuery,
+    Streaming,
+    Other(String),
+}
+
+impl FromStr for QueryBackend {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blocking-query" => Ok(QueryBackend::BlockingQuery),
+            "streaming" => Ok(QueryBackend::Streaming),
+            _ => Ok(QueryBackend::Other(s.to_string())),
+        }
+    }
+}
+
 /// A [MiddleWare] for adding version and token information to all requests.
 ///
 /// Implements [MiddleWare] to provide support for prepending API version
 /// information to all requests and adding an ACL token to the header of all
-/// requests. Additionally, any API features specified in the endpoint are
-/// appended to the request. This is passed by the API functions when an
-/// endpoint is executed.
+/// requests. If a default datacenter or namespace is configured it's added as
+/// a query parameter, unless the endpoint already specified its own.
+/// Additionally, any API features specified in the endpoint are appended to
+/// the request. This is passed by the API functions when an endpoint is
+/// executed.
 #[derive(Debug, Clone)]
 pub struct EndpointMiddleware {
+    pub datacenter: Option<String>,
     pub features: Option<Features>,
+    pub namespace: Option<String>,
     pub token: Option<String>,
     pub version: String,
 }
-impl MiddleWare for EndpointMiddleware {
+
+impl EndpointMiddleware {
+    /// Applies this middleware to the given [Request][http::Request].
+    ///
+    /// Returns an error if the URL of the request can't be modified, the ACL
+    /// token isn't a valid header value or one of the [Features] fails to be
+    /// applied.
     #[instrument(skip(self, req), err)]
-    fn request<E: Endpoint>(
-        &self,
-        _: &E,
-        req: &mut http::Request<Vec<u8>>,
-    ) -> Result<(), rustify::errors::ClientError> {
+    pub fn apply(&self, req: &mut http::Request<Vec<u8>>) -> Result<(), ClientError> {
         // Prepend API version to all requests
         debug!(
             "Middleware: prepending {} version to URL",
             self.version.as_str()
         );
-        let url = url::Url::parse(req.uri().to_string().as_str()).unwrap();
-        let mut url_c = url.clone();
-        let mut segs: Vec<&str> = url.path_segments().unwrap().collect();
-        segs.insert(0, self.version.as_str());
-        url_c.path_segments_mut().unwrap().clear().extend(segs);
-        *req.uri_mut() = http::Uri::from_str(url_c.as_str()).unwrap();
+        let uri = req.uri().to_string();
+        let invalid_url = || ClientError::InvalidUrlError { url: uri.clone() };
+        let mut url_c = url::Url::parse(uri.as_str()).map_err(|_| invalid_url())?;
+        if url_c.cannot_be_a_base() {
+            return Err(invalid_url());
+        }
+        // The path is already percent-encoded, so it's prefixed as-is rather
+        // than being split into segments which would be encoded again
+        let path = format!("/{}{}", self.version.as_str(), url_c.path());
+        url_c.set_path(&path);
+
+        // Add default datacenter and namespace if the endpoint didn't
+        let defaults = [("dc", &self.datacenter), ("ns", &self.namespace)];
+        for (key, value) in defaults.iter() {
+            if let Some(v) = value {
+                if !url_c.query_pairs().any(|(k, _)| k == *key) {
+                    debug!("Middleware: adding default {} query parameter", key);
+                    url_c.query_pairs_mut().append_pair(key, v);
+                }
+            }
+        }
+
+        *req.uri_mut() = http::Uri::from_str(url_c.as_str()).map_err(|_| invalid_url())?;
         debug!("Middleware: final URL is {}", url_c.as_str());
 
         // Add ACL token to header if present
         if let Some(token) = &self.token {
             debug!("Middleware: adding ACL token to header");
-            req.headers_mut().append(
-                "X-Consul-Token",
-                http::HeaderValue::from_str(token).unwrap(),
-            );
+            let value = http::HeaderValue::from_str(token)
+                .map_err(|e| ClientError::InvalidTokenError { source: e })?;
+            req.headers_mut().append("X-Consul-Token", value);
         }
 
         // Add optional API features
         if let Some(f) = &self.features {
-            f.process(req);
+            f.process(req)?;
         }
 
         Ok(())
     }
+}
+
+impl MiddleWare for EndpointMiddleware {
+    fn request<E: Endpoint>(
+        &self,
+        _: &E,
+        req: &mut http::Request<Vec<u8>>,
+    ) -> Result<(), rustify::errors::ClientError> {
+        self.apply(req)
+            .map_err(|e| rustify::errors::ClientError::GenericError { source: e.into() })
+    }
 
     fn response<E: Endpoint>(
         &self,
@@ -117,13 +213,7 @@ where
     E: Endpoint<Response = ()> + FeaturedEndpoint,
 {
     info!("Executing {} and expecting no response", endpoint.path());
-    let features = endpoint.features();
-    endpoint
-        .with_middleware(&client.middle(features))
-        .exec(client.http())
-        .await
-        .map_err(parse_err)
-        .map(parse_empty)?
+    exec(client, endpoint).await.map(parse_empty)?
 }
 
 /// Executes an [Endpoint] and returns the raw response body.
@@ -138,13 +228,7 @@ where
     E: Endpoint + FeaturedEndpoint,
 {
     info!("Executing {} and expecting a response", endpoint.path());
-    let features = endpoint.features();
-    endpoint
-        .with_middleware(&client.middle(features))
-        .exec(client.http())
-        .await
-        .map_err(parse_err)
-        .map(parse_raw)?
+    exec(client, endpoint).await.map(parse_raw)?
 }
 
 /// Executes an [Endpoint] and returns the result.
@@ -152,7 +236,8 @@ where
 /// The result from the executed endpoint has a few operations performed on it:
 ///
 /// * Any potential API error responses from the execution are searched for and,
-///   if found, converted to a [ClientError::APIError]
+///   if found, converted to a [ClientError] variant matching the error (i.e.
+///   [ClientError::NotFound]), falling back to [ClientError::APIError]
 /// * All other errors are mapped from [rustify::errors::ClientError] to
 ///   [ClientError::RestClientError]
 pub async fn exec_with_result<E>(
@@ -163,13 +248,110 @@ where
     E: Endpoint + FeaturedEndpoint,
 {
     info!("Executing {} and expecting a response", endpoint.path());
-    let features = endpoint.features();
-    endpoint
-        .with_middleware(&client.middle(features))
-        .exec(client.http())
+    exec(client, endpoint).await.map(parse)?
+}
+
+/// Executes an [Endpoint] and returns the response body without reading it.
+///
+/// This is used for transferring payloads which are too large to buffer in
+/// memory, such as snapshots. When a `body` is given, it's streamed in place
+/// of the body generated by the endpoint. Since a streamed body can only be
+/// sent once, the request is never retried. The client's `read_timeout` only
+/// applies to receiving the response headers.
+///
+/// Error responses are read in full and converted in the same way as
+/// [exec_with_result].
+pub async fn exec_with_stream<C, E>(
+    client: &C,
+    endpoint: E,
+    body: Option<hyper::Body>,
+) -> Result<ApiResponse<hyper::Body>, ClientError>
+where
+    C: Client,
+    C::Http: StreamTransport,
+    E: Endpoint + FeaturedEndpoint,
+{
+    info!("Executing {} and streaming the response", endpoint.path());
+    let middle = client.middle(endpoint.features())?;
+    let mut req = endpoint.request(client.http().base())?;
+    middle.apply(&mut req)?;
+    debug!("Streaming {} request to {}", req.method(), req.uri());
+
+    let (parts, buffered) = req.into_parts();
+    let req = http::Request::from_parts(parts, body.unwrap_or_else(|| buffered.into()));
+    let timeout = client.settings().read_timeout;
+    let res = tokio::time::timeout(timeout, client.http().send_stream(req))
         .await
-        .map_err(parse_err)
-        .map(parse)?
+        .map_err(|_| ClientError::TimeoutError { timeout })??;
+    debug!("Received {} response", res.status().as_u16());
+    if !HTTP_SUCCESS_CODES.contains(&res.status().as_u16()) {
+        return Err(parse_err(&transport::buffer(res).await?));
+    }
+
+    let (parts, body) = res.into_parts();
+    parse_headers(&parts.headers)?
+        .response(body)
+        .build()
+        .map_err(ClientError::from_builder)
+}
+
+/// Executes an [Endpoint], retrying it according to the client's
+/// [RetryPolicy][crate::client::RetryPolicy].
+///
+/// Only `GET` requests and endpoints marked as idempotent are retried. If all
+/// attempts fail with a retryable error, the last error is wrapped in a
+/// [ClientError::RetriesExhaustedError] which records the number of attempts.
+async fn exec<E>(
+    client: &impl Client,
+    endpoint: E,
+) -> Result<EndpointResult<E::Response>, ClientError>
+where
+    E: Endpoint + FeaturedEndpoint,
+{
+    let policy = &client.settings().retry;
+    let retryable = matches!(endpoint.method(), RequestMethod::GET) || endpoint.idempotent();
+    let features = endpoint.features();
+
+    let mut attempt = 1;
+    loop {
+        // Built per attempt so that a refreshed token is picked up on retries
+        let middle = client.middle(features.clone())?;
+        let err = match send(client, &endpoint, &middle).await {
+            Ok(r) => return Ok(r),
+            Err(e) => e,
+        };
+
+        if !retryable || !policy.is_retryable(&err) {
+            return Err(err);
+        }
+        if attempt >= policy.max_attempts {
+            return match attempt {
+                1 => Err(err),
+                _ => Err(ClientError::RetriesExhaustedError {
+                    attempts: attempt,
+                    source: Box::new(err),
+                }),
+            };
+        }
+
+        // Honor the server's requested delay when being rate limited
+        let delay = match &err {
+            ClientError::RateLimited {
+                retry_after: Some(d),
+            } => policy.backoff(attempt).max(*d),
+            _ => policy.backoff(attempt),
+        };
+        event!(
+            tracing::Level::WARN,
+            attempt,
+            delay_ms = delay.as_millis() as u64,
+            error = %err,
+            "Request to {} failed, retrying",
+            endpoint.path()
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
 }
 
 /// Parses an [EndpointResult], turning it into an [ApiResponse].
@@ -177,19 +359,19 @@ fn parse<T>(result: EndpointResult<T>) -> Result<ApiResponse<T>, ClientError>
 where
     T: DeserializeOwned + Send + Sync,
 {
-    let mut builder = parse_headers(result.response.headers());
+    let mut builder = parse_headers(result.response.headers())?;
 
     let response = result.parse().map_err(ClientError::from)?;
     builder = builder.response(response);
-    Ok(builder.build().unwrap())
+    builder.build().map_err(ClientError::from_builder)
 }
 
 /// Parses an [EndpointResult], turning it into an [ApiResponse].
 fn parse_empty(result: EndpointResult<()>) -> Result<ApiResponse<()>, ClientError> {
-    let mut builder = parse_headers(result.response.headers());
+    let mut builder = parse_headers(result.response.headers())?;
 
     builder = builder.response(());
-    Ok(builder.build().unwrap())
+    builder.build().map_err(ClientError::from_builder)
 }
 
 /// Parses an [EndpointResult], turning it into an [ApiResponse].
@@ -197,54 +379,146 @@ fn parse_raw<T>(result: EndpointResult<T>) -> Result<ApiResponse<Vec<u8>>, Clien
 where
     T: DeserializeOwned + Send + Sync,
 {
-    let mut builder = parse_headers(result.response.headers());
+    let mut builder = parse_headers(result.response.headers())?;
 
     let response = result.raw();
     builder = builder.response(response);
-    Ok(builder.build().unwrap())
+    builder.build().map_err(ClientError::from_builder)
 }
 
 /// Parses commonly found header fields out of response headers.
-fn parse_headers<T>(headers: &http::HeaderMap) -> ApiResponseBuilder<T>
-where
-    T: DeserializeOwned + Send + Sync,
-{
+///
+/// Returns a [ClientError::HeaderDecodeError] if a header isn't valid UTF-8
+/// and a [ClientError::HeaderParseError] if it can't be parsed into the
+/// expected type.
+fn parse_headers<T>(headers: &http::HeaderMap) -> Result<ApiResponseBuilder<T>, ClientError> {
     let mut builder = ApiResponse::builder();
 
-    if headers.contains_key("X-Cache") {
-        builder = builder.cache(headers["X-Cache"].to_str().unwrap());
+    if let Some(v) = header(headers, "Age")? {
+        builder = builder.age(Duration::from_secs(parse_header("Age", v)?));
+    }
+    if let Some(v) = header(headers, "X-Cache")? {
+        builder = builder.cache(parse_header::<CacheStatus>("X-Cache", v)?);
+    }
+    if let Some(v) = header(headers, "X-Consul-ContentHash")? {
+        builder = builder.content_hash(v);
     }
-    if headers.contains_key("X-Consul-ContentHash") {
-        builder = builder.content_hash(headers["X-Consul-ContentHash"].to_str().unwrap())
+    if let Some(v) = header(headers, "X-Consul-Default-ACL-Policy")? {
+        builder = builder.default_acl_policy(v);
     }
-    if headers.contains_key("X-Consul-Default-ACL-Policy") {
-        builder =
-            builder.default_acl_policy(headers["X-Consul-Default-ACL-Policy"].to_str().unwrap())
+    if let Some(v) = header(headers, "X-Consul-Index")? {
+        builder = builder.index(parse_header::<u64>("X-Consul-Index", v)?);
     }
-    if headers.contains_key("X-Consul-Index") {
-        builder = builder.index(headers["X-Consul-Index"].to_str().unwrap())
+    if let Some(v) = header(headers, "X-Consul-KnownLeader")? {
+        builder = builder.known_leader(parse_header::<bool>("X-Consul-KnownLeader", v)?);
     }
-    if headers.contains_key("X-Consul-KnownLeader") {
-        builder = builder.known_leader(headers["X-Consul-KnownLeader"].to_str().unwrap())
+    if let Some(v) = header(headers, "X-Consul-LastContact")? {
+        let millis = parse_header("X-Consul-LastContact", v)?;
+        builder = builder.last_contact(Duration::from_millis(millis));
     }
-    if headers.contains_key("X-Consul-LastContact") {
-        builder = builder.last_contact(headers["X-Consul-LastContact"].to_str().unwrap())
+    if let Some(v) = header(headers, "X-Consul-Query-Backend")? {
+        builder = builder.query_backend(parse_header::<QueryBackend>("X-Consul-Query-Backend", v)?);
+    }
+
+    Ok(builder)
+}
+
+/// Returns the value of the given header as a string, if present.
+fn header<'a>(headers: &'a http::HeaderMap, name: &str) -> Result<Option<&'a str>, ClientError> {
+    headers
+        .get(name)
+        .map(|v| {
+            v.to_str().map_err(|e| ClientError::HeaderDecodeError {
+                header: name.into(),
+                source: e,
+            })
+        })
+        .transpose()
+}
+
+/// Parses the value of the given header into the requested type.
+fn parse_header<T: FromStr>(name: &str, value: &str) -> Result<T, ClientError> {
+    value.parse().map_err(|_| ClientError::HeaderParseError {
+        header: name.into(),
+        value: value.into(),
+    })
+}
+
+/// Sends the request for an [Endpoint] using the client's
+/// [Transport][crate::transport::Transport].
+///
+/// Error responses are checked here, rather than by the transport, so that
+/// their headers are available when parsing them. Requests which don't
+/// receive a response within the client's `read_timeout`, extended by the
+/// maximum wait of a blocking query, fail with a [ClientError::TimeoutError].
+async fn send<E>(
+    client: &impl Client,
+    endpoint: &E,
+    middle: &EndpointMiddleware,
+) -> Result<EndpointResult<E::Response>, ClientError>
+where
+    E: Endpoint,
+{
+    let mut req = endpoint.request(client.http().base())?;
+    middle.apply(&mut req)?;
+    debug!("Sending {} request to {}", req.method(), req.uri());
+
+    // Blocking queries may be held by the server for up to their wait time
+    let mut timeout = client.settings().read_timeout;
+    if let Some(b) = middle.features.as_ref().and_then(|f| f.blocking.as_ref()) {
+        timeout += b.max_wait();
     }
-    if headers.contains_key("X-Consul-Query-Backend") {
-        builder = builder.query_backend(headers["X-Consul-Query-Backend"].to_str().unwrap())
+    let mut res = tokio::time::timeout(timeout, client.http().send(req))
+        .await
+        .map_err(|_| ClientError::TimeoutError { timeout })??;
+    debug!("Received {} response", res.status().as_u16());
+    if !HTTP_SUCCESS_CODES.contains(&res.status().as_u16()) {
+        return Err(parse_err(&res));
     }
 
-    builder
+    middle.response(endpoint, &mut res)?;
+    Ok(EndpointResult::new(res, E::RESPONSE_BODY_TYPE))
 }
 
-/// Extracts any API errors found and converts them to [ClientError::APIError].
-fn parse_err(e: RestClientError) -> ClientError {
-    if let RestClientError::ServerResponseError { code, content } = &e {
-        ClientError::APIError {
-            code: *code,
-            message: content.clone(),
+/// Converts an error response into the [ClientError] variant which best
+/// describes it.
+///
+/// Consul reports most errors using only a status code and a plain text
+/// message, so well known messages in 403 and 5xx responses are matched to
+/// distinguish between errors sharing a status code. Other status codes are
+/// mapped by their code alone, so that a 409 is always a
+/// [ClientError::CasConflict] even if its body mentions a known message (i.e.
+/// the errors of a rolled back transaction). Responses which don't match a known error are
+/// returned as a [ClientError::APIError].
+fn parse_err(res: &http::Response<Vec<u8>>) -> ClientError {
+    let code = res.status().as_u16();
+    let message = String::from_utf8(res.body().clone()).ok();
+    let text = message.as_deref().unwrap_or_default();
+
+    match code {
+        403 | 500..=599 if text.contains("ACL not found") => ClientError::ACLNotFound { message },
+        403 | 500..=599 if text.contains("Permission denied") => {
+            ClientError::PermissionDenied { message }
         }
-    } else {
-        ClientError::from(e)
+        500..=599 if text.contains("No cluster leader") => ClientError::NoLeader { code, message },
+        403 => ClientError::PermissionDenied { message },
+        404 => ClientError::NotFound {
+            index: res
+                .headers()
+                .get("X-Consul-Index")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok()),
+            message,
+        },
+        409 => ClientError::CasConflict { message },
+        429 => ClientError::RateLimited {
+            retry_after: res
+                .headers()
+                .get(http::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse().ok())
+                .map(Duration::from_secs),
+        },
+        _ => ClientError::APIError { code, message },
     }
 }
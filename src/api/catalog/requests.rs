@@ -24,6 +24,7 @@ use std::{collections::HashMap, fmt::Debug};
     response = "bool",
     builder = "true"
 )]
+#[query_endpoint(idempotent)]
 #[builder(setter(into, strip_option), default)]
 #[serde(rename_all = "PascalCase")]
 pub struct RegisterEntityRequest {
@@ -57,6 +58,7 @@ pub struct RegisterEntityRequest {
     response = "bool",
     builder = "true"
 )]
+#[query_endpoint(idempotent)]
 #[builder(setter(into, strip_option), default)]
 #[serde(rename_all = "PascalCase")]
 pub struct DeregisterEntityRequest {
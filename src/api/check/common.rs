@@ -1,3 +1,4 @@
+use crate::api::GoDuration;
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
@@ -38,7 +39,7 @@ pub struct AgentServiceCheck {
     pub body: Option<String>,
     #[serde(rename = "CheckID")]
     pub check_id: Option<String>,
-    pub deregister_critical_service_after: Option<String>,
+    pub deregister_critical_service_after: Option<GoDuration>,
     #[serde(rename = "DockerContainerID")]
     pub docker_container_id: Option<String>,
     pub failures_before_critical: Option<u64>,
@@ -49,7 +50,7 @@ pub struct AgentServiceCheck {
     pub header: Option<HashMap<String, String>>,
     #[serde(rename = "HTTP")]
     pub http: Option<String>,
-    pub interval: Option<String>,
+    pub interval: Option<GoDuration>,
     pub method: Option<String>,
     pub name: Option<String>,
     pub notes: Option<String>,
@@ -58,13 +59,13 @@ pub struct AgentServiceCheck {
     pub success_before_passing: Option<u64>,
     #[serde(rename = "TCP")]
     pub tcp: Option<String>,
-    pub timeout: Option<String>,
+    pub timeout: Option<GoDuration>,
     #[serde(rename = "TLSServerName")]
     pub tls_server_name: Option<String>,
     #[serde(rename = "TLSSkipVerify")]
     pub tlk_skip_verify: Option<String>,
     #[serde(rename = "TTL")]
-    pub ttl: Option<String>,
+    pub ttl: Option<GoDuration>,
 }
 
 #[skip_serializing_none]
@@ -97,15 +98,15 @@ pub struct HealthCheck {
 #[builder(setter(into, strip_option), default)]
 pub struct HealthCheckDefinition {
     pub body: Option<String>,
-    pub deregister_critical_service_after_duration: Option<String>,
+    pub deregister_critical_service_after_duration: Option<GoDuration>,
     pub header: Option<HashMap<String, String>>,
     #[serde(rename = "HTTP")]
     pub http: Option<String>,
-    pub interval_duration: Option<String>,
+    pub interval_duration: Option<GoDuration>,
     pub method: Option<String>,
     #[serde(rename = "TCP")]
     pub tcp: Option<String>,
-    pub timeout_duration: Option<String>,
+    pub timeout_duration: Option<GoDuration>,
     #[serde(rename = "TLSServerName")]
     pub tls_server_name: Option<String>,
     #[serde(rename = "TLSSkipVerify")]
@@ -1,4 +1,4 @@
-use crate::api::Features;
+use crate::api::{Features, GoDuration};
 
 use super::common::AgentCheck;
 use consulrs_derive::QueryEndpoint;
@@ -39,6 +39,7 @@ pub struct ListChecksRequest {
 #[derive(Builder, Clone, Debug, Default, Endpoint, QueryEndpoint, Serialize)]
 #[endpoint(path = "agent/check/register", method = "PUT", builder = "true")]
 #[serde(rename_all = "PascalCase")]
+#[query_endpoint(idempotent)]
 #[builder(setter(into, strip_option), default)]
 pub struct RegisterCheckRequest {
     #[endpoint(skip)]
@@ -49,7 +50,7 @@ pub struct RegisterCheckRequest {
     pub alias_service: Option<String>,
     pub args: Option<Vec<String>>,
     pub body: Option<String>,
-    pub deregister_critical_service_after: Option<String>,
+    pub deregister_critical_service_after: Option<GoDuration>,
     pub docker_container_id: Option<String>,
     pub failures_before_critical: Option<u64>,
     #[serde(rename = "GRPC")]
@@ -63,7 +64,7 @@ pub struct RegisterCheckRequest {
     pub http: Option<String>,
     #[serde(rename = "ID")]
     pub id: Option<String>,
-    pub interval: Option<String>,
+    pub interval: Option<GoDuration>,
     pub method: Option<String>,
     pub namespace: Option<String>,
     pub notes: Option<String>,
@@ -73,13 +74,13 @@ pub struct RegisterCheckRequest {
     pub success_before_passing: Option<u64>,
     #[serde(rename = "TCP")]
     pub tcp: Option<String>,
-    pub timeout: Option<String>,
+    pub timeout: Option<GoDuration>,
     #[serde(rename = "TLSServerName")]
     pub tls_server_name: Option<String>,
     #[serde(rename = "TLSSkipVerify")]
     pub tls_skip_verify: Option<String>,
     #[serde(rename = "TTL")]
-    pub ttl: Option<String>,
+    pub ttl: Option<GoDuration>,
 }
 
 /// ## Deregister Check
@@ -95,6 +96,7 @@ pub struct RegisterCheckRequest {
     method = "PUT",
     builder = "true"
 )]
+#[query_endpoint(idempotent)]
 #[builder(setter(into, strip_option), default)]
 pub struct DeregisterCheckRequest {
     #[endpoint(skip)]
@@ -119,6 +121,7 @@ pub struct DeregisterCheckRequest {
     method = "PUT",
     builder = "true"
 )]
+#[query_endpoint(idempotent)]
 #[builder(setter(into, strip_option), default)]
 pub struct TtlCheckPassRequest {
     #[endpoint(skip)]
@@ -144,6 +147,7 @@ pub struct TtlCheckPassRequest {
     method = "PUT",
     builder = "true"
 )]
+#[query_endpoint(idempotent)]
 #[builder(setter(into, strip_option), default)]
 pub struct TtlCheckWarnRequest {
     #[endpoint(skip)]
@@ -169,6 +173,7 @@ pub struct TtlCheckWarnRequest {
     method = "PUT",
     builder = "true"
 )]
+#[query_endpoint(idempotent)]
 #[builder(setter(into, strip_option), default)]
 pub struct TtlCheckFailRequest {
     #[endpoint(skip)]
@@ -194,6 +199,7 @@ pub struct TtlCheckFailRequest {
     method = "PUT",
     builder = "true"
 )]
+#[query_endpoint(idempotent)]
 #[builder(setter(into, strip_option), default)]
 pub struct TtlCheckUpdateRequest {
     #[endpoint(skip)]
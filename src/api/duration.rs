@@ -0,0 +1,172 @@
+use std::{convert::TryFrom, fmt, str::FromStr, time::Duration};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::ClientError;
+
+/// A [Duration] which is represented using Go's duration format (i.e. `10s`
+/// or `1m30s`) when sent to Consul.
+///
+/// Consul accepts durations as Go duration strings but isn't consistent in
+/// how it returns them: some fields are returned as strings while others (for
+/// example, [SessionEntry::lock_delay][crate::api::session::common::SessionEntry])
+/// are returned as a number of nanoseconds. Both representations are accepted
+/// when deserializing, and an empty string is treated as a zero duration.
+///
+/// A [GoDuration] is most easily created from a [Duration]:
+///
+/// ```
+/// use consulrs::api::GoDuration;
+/// use std::time::Duration;
+///
+/// let ttl: GoDuration = Duration::from_secs(90).into();
+/// assert_eq!(ttl.to_string(), "1m30s");
+/// assert_eq!("1m30s".parse::<GoDuration>().unwrap(), ttl);
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct GoDuration(pub Duration);
+
+impl GoDuration {
+    /// Returns the underlying [Duration].
+    pub fn as_duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl From<Duration> for GoDuration {
+    fn from(d: Duration) -> Self {
+        GoDuration(d)
+    }
+}
+
+impl From<GoDuration> for Duration {
+    fn from(d: GoDuration) -> Self {
+        d.0
+    }
+}
+
+impl fmt::Display for GoDuration {
+    /// Formats the duration in the same way as Go's `time.Duration.String`,
+    /// except that microseconds use the `us` suffix.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+        let nanos = self.0.subsec_nanos() as u64;
+
+        if secs == 0 {
+            return match nanos {
+                0 => write!(f, "0s"),
+                n if n < 1_000 => write!(f, "{}ns", n),
+                n if n < 1_000_000 => write!(f, "{}us", fraction(n, 3)),
+                n => write!(f, "{}ms", fraction(n, 6)),
+            };
+        }
+
+        if secs >= 3600 {
+            write!(f, "{}h", secs / 3600)?;
+        }
+        if secs >= 60 {
+            write!(f, "{}m", secs / 60 % 60)?;
+        }
+        write!(f, "{}s", fraction(secs % 60 * 1_000_000_000 + nanos, 9))
+    }
+}
+
+impl FromStr for GoDuration {
+    type Err = ClientError;
+
+    /// Parses a Go duration string, such as `300ms`, `1.5h` or `2h45m`.
+    /// Negative durations aren't supported.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ClientError::DurationParseError { value: s.into() };
+
+        let mut rest = s.trim();
+        if rest.is_empty() {
+            return Err(err());
+        }
+
+        let mut total = Duration::ZERO;
+        while !rest.is_empty() {
+            let split = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            let (num, tail) = rest.split_at(split);
+            let num: f64 = num.parse().map_err(|_| err())?;
+            let unit_len = tail
+                .find(|c: char| c.is_ascii_digit() || c == '.')
+                .unwrap_or(tail.len());
+            let (unit, tail) = tail.split_at(unit_len);
+            let secs = match unit {
+                "ns" => num / 1e9,
+                "us" | "µs" | "μs" => num / 1e6,
+                "ms" => num / 1e3,
+                "s" => num,
+                "m" => num * 60.0,
+                "h" => num * 3600.0,
+                // Go only allows a missing unit for zero
+                "" if num == 0.0 => 0.0,
+                _ => return Err(err()),
+            };
+            // Values which are too large or not finite (i.e. `1e400s`) fail
+            // rather than panicking, since this also parses server responses
+            total = Duration::try_from_secs_f64(secs)
+                .ok()
+                .and_then(|d| total.checked_add(d))
+                .ok_or_else(err)?;
+            rest = tail;
+        }
+
+        Ok(GoDuration(total))
+    }
+}
+
+impl Serialize for GoDuration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for GoDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(GoDurationVisitor)
+    }
+}
+
+struct GoDurationVisitor;
+
+impl<'de> de::Visitor<'de> for GoDurationVisitor {
+    type Value = GoDuration;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a Go duration string or a number of nanoseconds")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        if v.is_empty() {
+            return Ok(GoDuration::default());
+        }
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(GoDuration(Duration::from_nanos(v)))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        u64::try_from(v)
+            .map(|v| GoDuration(Duration::from_nanos(v)))
+            .map_err(|_| E::custom(format!("negative duration: {}", v)))
+    }
+}
+
+/// Formats `value` as a decimal number with `precision` fractional digits,
+/// trimming any trailing zeros.
+fn fraction(value: u64, precision: u32) -> String {
+    let unit = 10u64.pow(precision);
+    let (whole, frac) = (value / unit, value % unit);
+    if frac == 0 {
+        return whole.to_string();
+    }
+
+    let frac = format!("{:0width$}", frac, width = precision as usize);
+    format!("{}.{}", whole, frac.trim_end_matches('0'))
+}
@@ -1,8 +1,10 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, str::FromStr, time::Duration};
 
 use derive_builder::Builder;
 use http::{HeaderValue, Request};
 
+use crate::{api::GoDuration, error::ClientError};
+
 /// An [Endpoint][rustify::Endpoint] which contains optional [Features] for
 /// modifying how its generated request is handled.
 ///
@@ -12,8 +14,17 @@ use http::{HeaderValue, Request};
 /// enabled for the request. This crate does not perform any checks to ensure
 /// features are being used correctly - incorrect usage will result in an API
 /// error which will eventually make it back to the end-user.
+///
+/// Endpoints which can safely be sent more than once with the same outcome
+/// should be marked with `#[query_endpoint(idempotent)]` so that they are
+/// eligible for retries in the same way that `GET` requests are.
 pub trait FeaturedEndpoint {
     fn features(&self) -> Option<Features>;
+
+    /// Returns whether the endpoint was explicitly marked as idempotent.
+    fn idempotent(&self) -> bool {
+        false
+    }
 }
 
 /// A set of features which can be applied to an endpoint request.
@@ -43,8 +54,11 @@ pub struct Features {
 impl Features {
     /// Mutates a [Request] by adding query parameters and header fields as
     /// determined by the features configured.
-    #[instrument(skip(self, request))]
-    pub fn process(&self, request: &mut Request<Vec<u8>>) {
+    ///
+    /// Returns an error if a feature can't be represented in the request (i.e.
+    /// a cache directive which isn't a valid header value).
+    #[instrument(skip(self, request), err)]
+    pub fn process(&self, request: &mut Request<Vec<u8>>) -> Result<(), ClientError> {
         let mut query = HashMap::<String, String>::new();
         let mut keys = Vec::<String>::new();
         info!("Adding features to request");
@@ -52,7 +66,7 @@ impl Features {
         // Blocking Queries
         if let Some(b) = &self.blocking {
             if let Some(w) = &b.wait {
-                query.insert("wait".into(), w.clone());
+                query.insert("wait".into(), w.to_string());
             }
 
             query.insert("index".into(), b.index.to_string());
@@ -61,9 +75,13 @@ impl Features {
         // Caching
         if let Some(c) = &self.cached {
             if !c.is_empty() {
-                request
-                    .headers_mut()
-                    .insert("Cache-Control", HeaderValue::from_str(c.as_str()).unwrap());
+                let value = HeaderValue::from_str(c.as_str()).map_err(|e| {
+                    ClientError::InvalidHeaderValueError {
+                        header: "Cache-Control".into(),
+                        source: e,
+                    }
+                })?;
+                request.headers_mut().insert("Cache-Control", value);
             }
 
             keys.push("cached".into());
@@ -84,7 +102,9 @@ impl Features {
             keys.push(mode.into())
         }
 
-        let mut url = url::Url::parse(request.uri().to_string().as_str()).unwrap();
+        let uri = request.uri().to_string();
+        let invalid_url = || ClientError::InvalidUrlError { url: uri.clone() };
+        let mut url = url::Url::parse(uri.as_str()).map_err(|_| invalid_url())?;
 
         if !query.is_empty() {
             url.query_pairs_mut().extend_pairs(query);
@@ -95,9 +115,10 @@ impl Features {
                 .extend_keys_only::<Vec<String>, String>(keys);
         }
 
-        *request.uri_mut() = http::Uri::from_str(url.as_str()).unwrap();
+        *request.uri_mut() = http::Uri::from_str(url.as_str()).map_err(|_| invalid_url())?;
 
         info!("Final url with features: {}", request.uri());
+        Ok(())
     }
 
     /// Returns a default instance of [FeaturesBuilder] for configuring features.
@@ -106,11 +127,32 @@ impl Features {
     }
 }
 
+/// The time Consul waits on a blocking query which doesn't specify `wait`.
+const DEFAULT_WAIT: Duration = Duration::from_secs(300);
+
+/// The maximum time Consul will wait on a blocking query.
+const MAX_WAIT: Duration = Duration::from_secs(600);
+
 /// Configuration options for the Blocking Queries feature.
 #[derive(Debug, Clone)]
 pub struct Blocking {
     pub index: u64,
-    pub wait: Option<String>,
+    pub wait: Option<GoDuration>,
+}
+
+impl Blocking {
+    /// Returns the longest time Consul may hold this query before responding.
+    ///
+    /// Consul adds a random jitter of up to `wait / 16` to the requested wait
+    /// time, which defaults to 5 minutes and is capped at 10 minutes.
+    pub fn max_wait(&self) -> Duration {
+        let wait = self
+            .wait
+            .map(Duration::from)
+            .unwrap_or(DEFAULT_WAIT)
+            .min(MAX_WAIT);
+        wait + wait / 16
+    }
 }
 
 /// Configuration options for the Consistency Mode feature.
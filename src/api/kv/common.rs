@@ -10,6 +10,12 @@ use crate::error::ClientError;
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Base64String(String);
 
+impl From<&[u8]> for Base64String {
+    fn from(bytes: &[u8]) -> Self {
+        Base64String(base64::encode(bytes))
+    }
+}
+
 impl TryInto<Vec<u8>> for Base64String {
     type Error = ClientError;
 
@@ -44,6 +50,25 @@ pub struct KVPair {
     pub value: Option<Base64String>,
 }
 
+/// A single entry in the JSON format used by `consul kv export` and
+/// `consul kv import`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct KVExportEntry {
+    pub key: String,
+    pub flags: u64,
+    pub value: Base64String,
+}
+
+impl From<KVPair> for KVExportEntry {
+    fn from(pair: KVPair) -> Self {
+        KVExportEntry {
+            key: pair.key,
+            flags: pair.flags,
+            value: pair.value.unwrap_or_default(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct GenericKVPair<T: DeserializeOwned> {
     pub create_index: u64,
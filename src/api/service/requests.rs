@@ -112,6 +112,7 @@ pub struct ServiceHealthByIdRequest {
 #[derive(Builder, Clone, Debug, Default, Endpoint, QueryEndpoint, Serialize)]
 #[endpoint(path = "agent/service/register", method = "PUT", builder = "true")]
 #[serde(rename_all = "PascalCase")]
+#[query_endpoint(idempotent)]
 #[builder(setter(into, strip_option), default)]
 pub struct RegisterServiceRequest {
     #[endpoint(skip)]
@@ -148,6 +149,7 @@ pub struct RegisterServiceRequest {
     method = "PUT",
     builder = "true"
 )]
+#[query_endpoint(idempotent)]
 #[builder(setter(into, strip_option), default)]
 pub struct DeregisterServiceRequest {
     #[endpoint(skip)]
@@ -171,6 +173,7 @@ pub struct DeregisterServiceRequest {
     method = "PUT",
     builder = "true"
 )]
+#[query_endpoint(idempotent)]
 #[builder(setter(into, strip_option), default)]
 pub struct EnableMaintenanceRequest {
     #[endpoint(skip)]
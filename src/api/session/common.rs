@@ -1,3 +1,4 @@
+use crate::api::GoDuration;
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
@@ -21,12 +22,12 @@ pub struct SessionEntry {
     pub create_index: Option<u64>,
     #[serde(rename = "ID")]
     pub id: Option<String>,
-    pub lock_delay: Option<u64>,
+    pub lock_delay: Option<GoDuration>,
     pub name: Option<String>,
     pub namespace: Option<String>,
     pub node: Option<String>,
     pub node_checks: Option<Vec<String>>,
     pub service_checks: Option<Vec<ServiceCheck>>,
     #[serde(rename = "TTL")]
-    pub ttl: Option<String>,
+    pub ttl: Option<GoDuration>,
 }
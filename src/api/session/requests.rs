@@ -2,7 +2,7 @@ use super::{
     common::{ServiceCheck, SessionEntry},
     responses::CreateSessionResponse,
 };
-use crate::api::Features;
+use crate::api::{Features, GoDuration};
 use consulrs_derive::QueryEndpoint;
 use derive_builder::Builder;
 use rustify_derive::Endpoint;
@@ -33,14 +33,14 @@ pub struct CreateSessionRequest {
     pub create_index: Option<u64>,
     #[serde(rename = "ID")]
     pub id: Option<String>,
-    pub lock_delay: Option<String>,
+    pub lock_delay: Option<GoDuration>,
     pub name: Option<String>,
     pub namespace: Option<String>,
     pub node: Option<String>,
     pub node_checks: Option<Vec<String>>,
     pub service_checks: Option<Vec<ServiceCheck>>,
     #[serde(rename = "TTL")]
-    pub ttl: Option<String>,
+    pub ttl: Option<GoDuration>,
 }
 
 /// ## Delete Session
@@ -53,6 +53,7 @@ pub struct CreateSessionRequest {
 #[derive(Builder, Clone, Debug, Default, Endpoint, QueryEndpoint, Serialize)]
 #[endpoint(path = "session/destroy/{self.uuid}", method = "PUT", builder = "true")]
 #[serde(rename_all = "PascalCase")]
+#[query_endpoint(idempotent)]
 #[builder(setter(into, strip_option), default)]
 pub struct DeleteSessionRequest {
     #[endpoint(skip)]
@@ -160,6 +161,7 @@ pub struct ListSessionsRequest {
     builder = "true"
 )]
 #[serde(rename_all = "PascalCase")]
+#[query_endpoint(idempotent)]
 #[builder(setter(into, strip_option), default)]
 pub struct RenewSessionRequest {
     #[endpoint(skip)]
@@ -0,0 +1,143 @@
+use std::fmt;
+
+use derive_builder::Builder;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use crate::api::kv::common::Base64String;
+
+/// A single operation within a transaction.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum TxnOp {
+    #[serde(rename = "KV")]
+    KV(KVTxnOp),
+}
+
+impl From<KVTxnOp> for TxnOp {
+    fn from(op: KVTxnOp) -> Self {
+        TxnOp::KV(op)
+    }
+}
+
+/// The verb of a [KVTxnOp].
+///
+/// See https://www.consul.io/api-docs/txn#kv-operations
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KVTxnVerb {
+    Set,
+    Cas,
+    Lock,
+    Unlock,
+    Get,
+    GetTree,
+    CheckIndex,
+    CheckSession,
+    CheckNotExists,
+    Delete,
+    DeleteTree,
+    DeleteCas,
+}
+
+/// An operation on the KV store within a transaction.
+#[skip_serializing_none]
+#[derive(Builder, Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+#[builder(setter(into, strip_option))]
+pub struct KVTxnOp {
+    pub verb: KVTxnVerb,
+    pub key: String,
+    #[builder(default)]
+    pub flags: Option<u64>,
+    #[builder(default)]
+    pub index: Option<u64>,
+    #[builder(default)]
+    pub namespace: Option<String>,
+    #[builder(default)]
+    pub session: Option<String>,
+    #[builder(default)]
+    pub value: Option<Base64String>,
+}
+
+impl KVTxnOp {
+    /// Returns a default instance of [KVTxnOpBuilder].
+    pub fn builder() -> KVTxnOpBuilder {
+        KVTxnOpBuilder::default()
+    }
+
+    /// Sets the given key to the given value.
+    pub fn set(key: &str, value: &[u8]) -> Self {
+        KVTxnOp {
+            value: Some(value.into()),
+            ..Self::verb(KVTxnVerb::Set, key)
+        }
+    }
+
+    /// Sets the given key to the given value if its modify index matches
+    /// `index`. An index of 0 only sets the key if it doesn't exist.
+    pub fn cas(key: &str, value: &[u8], index: u64) -> Self {
+        KVTxnOp {
+            index: Some(index),
+            value: Some(value.into()),
+            ..Self::verb(KVTxnVerb::Cas, key)
+        }
+    }
+
+    /// Reads the given key, failing the transaction if it doesn't exist.
+    pub fn get(key: &str) -> Self {
+        Self::verb(KVTxnVerb::Get, key)
+    }
+
+    /// Fails the transaction if the modify index of the given key doesn't
+    /// match `index`.
+    pub fn check_index(key: &str, index: u64) -> Self {
+        KVTxnOp {
+            index: Some(index),
+            ..Self::verb(KVTxnVerb::CheckIndex, key)
+        }
+    }
+
+    /// Deletes the given key.
+    pub fn delete(key: &str) -> Self {
+        Self::verb(KVTxnVerb::Delete, key)
+    }
+
+    /// Deletes the given key if its modify index matches `index`.
+    pub fn delete_cas(key: &str, index: u64) -> Self {
+        KVTxnOp {
+            index: Some(index),
+            ..Self::verb(KVTxnVerb::DeleteCas, key)
+        }
+    }
+
+    /// Deletes all keys starting with the given prefix.
+    pub fn delete_tree(prefix: &str) -> Self {
+        Self::verb(KVTxnVerb::DeleteTree, prefix)
+    }
+
+    fn verb(verb: KVTxnVerb, key: &str) -> Self {
+        KVTxnOp {
+            verb,
+            key: key.into(),
+            flags: None,
+            index: None,
+            namespace: None,
+            session: None,
+            value: None,
+        }
+    }
+}
+
+/// An error which caused a transaction to be rolled back.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TxnError {
+    pub op_index: usize,
+    pub what: String,
+}
+
+impl fmt::Display for TxnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "operation {}: {}", self.op_index, self.what)
+    }
+}
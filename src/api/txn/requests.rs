@@ -0,0 +1,35 @@
+use super::responses::TxnResponse;
+use crate::api::Features;
+use consulrs_derive::QueryEndpoint;
+use derive_builder::Builder;
+use rustify_derive::Endpoint;
+use std::fmt::Debug;
+
+/// ## Create Transaction
+/// This endpoint permits submitting a list of operations to apply to Consul
+/// inside of a transaction.
+///
+/// The operations are a JSON encoded list of
+/// [TxnOp][crate::api::txn::common::TxnOp], which is most easily sent using
+/// [txn::execute][crate::txn::execute].
+///
+/// * Path: txn
+/// * Method: PUT
+/// * Response: [TxnResponse]
+/// * Reference: https://www.consul.io/api-docs/txn#create-transaction
+#[derive(Builder, Debug, Default, Endpoint, QueryEndpoint)]
+#[endpoint(
+    path = "txn",
+    method = "PUT",
+    response = "TxnResponse",
+    builder = "true"
+)]
+#[builder(setter(into, strip_option), default)]
+pub struct CreateTransactionRequest {
+    #[endpoint(skip)]
+    pub features: Option<Features>,
+    #[endpoint(raw)]
+    pub operations: Vec<u8>,
+    #[endpoint(query)]
+    pub dc: Option<String>,
+}
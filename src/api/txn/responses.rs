@@ -0,0 +1,19 @@
+use serde::Deserialize;
+
+use super::common::TxnError;
+use crate::api::kv::common::KVPair;
+
+/// Response from executing a transaction.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TxnResponse {
+    pub errors: Option<Vec<TxnError>>,
+    pub results: Option<Vec<TxnResult>>,
+}
+
+/// The result of a single operation within a transaction.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TxnResult {
+    #[serde(rename = "KV")]
+    pub kv: Option<KVPair>,
+}
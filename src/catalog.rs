@@ -34,7 +34,10 @@ pub async fn datacenters(
     opts: Option<&mut ListDatacentersRequestBuilder>,
 ) -> Result<ApiResponse<Vec<String>>, ClientError> {
     let mut t = ListDatacentersRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -48,7 +51,11 @@ pub async fn deregister(
     opts: Option<&mut DeregisterEntityRequestBuilder>,
 ) -> Result<ApiResponse<bool>, ClientError> {
     let mut t = DeregisterEntityRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).node(node).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .node(node)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -62,7 +69,11 @@ pub async fn gateway(
     opts: Option<&mut ListGatewayServicesRequestBuilder>,
 ) -> Result<ApiResponse<Option<Vec<GatewayServiceResponse>>>, ClientError> {
     let mut t = ListGatewayServicesRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).gateway(gateway).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .gateway(gateway)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -76,7 +87,11 @@ pub async fn node(
     opts: Option<&mut ListNodeServicesRequestBuilder>,
 ) -> Result<ApiResponse<ListNodeServicesResponse>, ClientError> {
     let mut t = ListNodeServicesRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).node(node).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .node(node)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -89,7 +104,10 @@ pub async fn nodes(
     opts: Option<&mut ListNodesRequestBuilder>,
 ) -> Result<ApiResponse<Vec<Node>>, ClientError> {
     let mut t = ListNodesRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -103,7 +121,11 @@ pub async fn nodes_with_service(
     opts: Option<&mut ListNodesForServiceRequestBuilder>,
 ) -> Result<ApiResponse<Vec<CatalogService>>, ClientError> {
     let mut t = ListNodesForServiceRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).service(service).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .service(service)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -117,7 +139,11 @@ pub async fn nodes_with_connect_service(
     opts: Option<&mut ListNodesForConnectServiceRequestBuilder>,
 ) -> Result<ApiResponse<Vec<ListNodesForServiceResponse>>, ClientError> {
     let mut t = ListNodesForConnectServiceRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).service(service).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .service(service)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -137,7 +163,7 @@ pub async fn register(
         .node(node)
         .address(address)
         .build()
-        .unwrap();
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -150,6 +176,9 @@ pub async fn services(
     opts: Option<&mut ListServicesRequestBuilder>,
 ) -> Result<ApiResponse<HashMap<String, Vec<String>>>, ClientError> {
     let mut t = ListServicesRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
@@ -29,7 +29,11 @@ pub async fn deregister(
     opts: Option<&mut DeregisterCheckRequestBuilder>,
 ) -> Result<ApiResponse<()>, ClientError> {
     let mut t = DeregisterCheckRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).check(name).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .check(name)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_empty(client, endpoint).await
 }
 
@@ -43,7 +47,11 @@ pub async fn fail(
     opts: Option<&mut TtlCheckFailRequestBuilder>,
 ) -> Result<ApiResponse<()>, ClientError> {
     let mut t = TtlCheckFailRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).check(name).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .check(name)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_empty(client, endpoint).await
 }
 
@@ -56,7 +64,10 @@ pub async fn list(
     opts: Option<&mut ListChecksRequestBuilder>,
 ) -> Result<ApiResponse<HashMap<String, AgentCheck>>, ClientError> {
     let mut t = ListChecksRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -70,7 +81,11 @@ pub async fn pass(
     opts: Option<&mut TtlCheckPassRequestBuilder>,
 ) -> Result<ApiResponse<()>, ClientError> {
     let mut t = TtlCheckPassRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).check(name).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .check(name)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_empty(client, endpoint).await
 }
 
@@ -84,7 +99,11 @@ pub async fn register(
     opts: Option<&mut RegisterCheckRequestBuilder>,
 ) -> Result<ApiResponse<()>, ClientError> {
     let mut t = RegisterCheckRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).name(name).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .name(name)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_empty(client, endpoint).await
 }
 
@@ -104,7 +123,7 @@ pub async fn set_status(
         .check(name)
         .status(status)
         .build()
-        .unwrap();
+        .map_err(ClientError::from_builder)?;
     api::exec_with_empty(client, endpoint).await
 }
 
@@ -118,6 +137,10 @@ pub async fn warn(
     opts: Option<&mut TtlCheckWarnRequestBuilder>,
 ) -> Result<ApiResponse<()>, ClientError> {
     let mut t = TtlCheckWarnRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).check(name).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .check(name)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_empty(client, endpoint).await
 }
@@ -1,21 +1,37 @@
 use async_trait::async_trait;
 use derive_builder::Builder;
+use rand::Rng;
 use rustify::clients::reqwest::Client as HTTPClient;
-use std::{env, fs};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use crate::{
     api::{EndpointMiddleware, Features},
     error::ClientError,
+    token::{FileTokenProvider, TokenProvider},
+    transport::{
+        FailoverTransport, RecordingTransport, ReplayTransport, StreamTransport, Transport,
+    },
 };
 
 /// The client interface capabale of interacting with API functions
 #[async_trait]
 pub trait Client: Send + Sync + Sized {
+    /// The [Transport] used for sending requests
+    type Http: Transport;
+
     /// Returns the underlying HTTP client being used for API calls
-    fn http(&self) -> &HTTPClient;
+    fn http(&self) -> &Self::Http;
 
     /// Returns the middleware to be used when executing API calls
-    fn middle(&self, features: Option<Features>) -> EndpointMiddleware;
+    ///
+    /// This is called before every request, allowing the ACL token to be
+    /// refreshed from the configured [TokenProvider].
+    fn middle(&self, features: Option<Features>) -> Result<EndpointMiddleware, ClientError>;
 
     /// Returns the settings used to configure this client
     fn settings(&self) -> &ConsulClientSettings;
@@ -24,26 +40,40 @@ pub trait Client: Send + Sync + Sized {
 /// A client which can be used to execute calls against a Consul server.
 ///
 /// A consul client is configured using [ConsulClientSettings] and will
-/// automatically configure a backing instance of a [HTTPClient] which is
-/// used for executing [Endpoints][rustify::endpoint::Endpoint].
-pub struct ConsulClient {
-    pub http: HTTPClient,
+/// automatically configure a backing instance of a [FailoverTransport] which
+/// is used for executing [Endpoints][rustify::endpoint::Endpoint] against the
+/// configured addresses.
+///
+/// Alternatively, any [Transport] can be used as the backend of the client by
+/// creating it with [ConsulClient::with_transport]. In this case, settings
+/// which configure the connection (addresses, certificates, etc.) are ignored
+/// and left to the transport.
+pub struct ConsulClient<T: Transport = FailoverTransport> {
+    pub http: T,
     pub settings: ConsulClientSettings,
 }
 
 #[async_trait]
-impl Client for ConsulClient {
-    fn http(&self) -> &HTTPClient {
+impl<T: Transport> Client for ConsulClient<T> {
+    type Http = T;
+
+    fn http(&self) -> &T {
         &self.http
     }
 
-    fn middle(&self, features: Option<Features>) -> EndpointMiddleware {
+    fn middle(&self, features: Option<Features>) -> Result<EndpointMiddleware, ClientError> {
         let version_str = format!("v{}", self.settings.version);
-        EndpointMiddleware {
+        let token = match &self.settings.token_provider {
+            Some(p) => p.token()?,
+            None => self.settings.token.clone(),
+        };
+        Ok(EndpointMiddleware {
+            datacenter: self.settings.datacenter.clone(),
             features,
-            token: self.settings.token.clone(),
+            namespace: self.settings.namespace.clone(),
+            token,
             version: version_str,
-        }
+        })
     }
 
     fn settings(&self) -> &ConsulClientSettings {
@@ -62,6 +92,7 @@ impl ConsulClient {
             event!(tracing::Level::WARN, "Disabling TLS verification");
         }
         http_client = http_client.danger_accept_invalid_certs(!settings.verify);
+        http_client = http_client.connect_timeout(settings.connect_timeout);
 
         // Adds CA certificates
         for path in &settings.ca_certs {
@@ -110,23 +141,150 @@ impl ConsulClient {
         let http_client = http_client
             .build()
             .map_err(|e| ClientError::RestClientBuildError { source: e })?;
-        let http = HTTPClient::new(settings.address.as_str(), http_client);
+
+        // The preferred address always comes first
+        let mut addresses = vec![parse_address(&settings.address)?];
+        for address in settings.addresses.iter() {
+            let address = parse_address(address)?;
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+        debug!("Using Consul addresses {:?}", addresses);
+
+        // Addresses prefixed with unix:// are served over a Unix domain socket
+        let mut targets = Vec::<(String, Box<dyn StreamTransport>)>::new();
+        for address in addresses {
+            let transport: Box<dyn StreamTransport> = if address.starts_with("unix://") {
+                unix_transport(&address)?
+            } else {
+                Box::new(HTTPClient::new(&address, http_client.clone()))
+            };
+            targets.push((address, transport));
+        }
+
+        let http = FailoverTransport::new(
+            targets,
+            &format!("v{}", settings.version),
+            settings.probe_interval,
+        );
+        Ok(ConsulClient { settings, http })
+    }
+}
+
+impl<T: Transport> ConsulClient<T> {
+    /// Creates a new [ConsulClient] using the given [ConsulClientSettings]
+    /// which sends all requests through the given [Transport].
+    pub fn with_transport(settings: ConsulClientSettings, http: T) -> ConsulClient<T> {
+        ConsulClient { settings, http }
+    }
+
+    /// Wraps the [Transport] of this client in a [RecordingTransport] which
+    /// records all requests and responses to the fixture file at the given
+    /// path. The fixture is written by [RecordingTransport::finish] or when
+    /// the client is dropped.
+    pub fn record(self, path: impl Into<PathBuf>) -> ConsulClient<RecordingTransport<T>> {
+        ConsulClient {
+            http: RecordingTransport::new(self.http, path),
+            settings: self.settings,
+        }
+    }
+}
+
+impl ConsulClient<ReplayTransport> {
+    /// Creates a new [ConsulClient] which serves all requests from the fixture
+    /// file at the given path, as previously recorded by
+    /// [ConsulClient::record].
+    pub fn replay(
+        settings: ConsulClientSettings,
+        path: impl AsRef<Path>,
+    ) -> Result<ConsulClient<ReplayTransport>, ClientError> {
+        let http = ReplayTransport::from_file(path)?;
         Ok(ConsulClient { settings, http })
     }
 }
 
+/// Validates the given address, defaulting to `http://` when it has no scheme
+/// like the Consul CLI does.
+fn parse_address(address: &str) -> Result<String, ClientError> {
+    if address.starts_with("unix://") {
+        return Ok(address.to_string());
+    }
+
+    let address = match address.contains("://") {
+        true => address.to_string(),
+        false => format!("http://{}", address),
+    };
+    match url::Url::parse(&address) {
+        Ok(u) if !u.cannot_be_a_base() && matches!(u.scheme(), "http" | "https") => Ok(address),
+        _ => Err(ClientError::InvalidAddressError { address }),
+    }
+}
+
+#[cfg(unix)]
+fn unix_transport(address: &str) -> Result<Box<dyn StreamTransport>, ClientError> {
+    info!("Using Unix domain socket at {}", address);
+    Ok(Box::new(crate::transport::UnixTransport::new(address)))
+}
+
+#[cfg(not(unix))]
+fn unix_transport(address: &str) -> Result<Box<dyn StreamTransport>, ClientError> {
+    Err(ClientError::InvalidAddressError {
+        address: address.into(),
+    })
+}
+
 /// Contains settings for configuring a [ConsulClient].
 ///
 /// Most settings that are not directly configured will have their default value
 /// pulled from their respective environment variables. Specifically:
 ///
 /// * `address`: CONSUL_HTTP_ADDR
+/// * `addresses`: CONSUL_HTTP_ADDR
 /// * `ca_certs`: CONSUL_CACERT / CONSUL_CAPATH
 /// * `client_cert`: CONSUL_CLIENT_CERT
 /// * `client_key`: CONSUL_CLIENT_KEY
+/// * `namespace`: CONSUL_NAMESPACE
 /// * `token`: CONSUL_HTTP_TOKEN
+/// * `token_file`: CONSUL_HTTP_TOKEN_FILE
 /// * `verify`: CONSUL_HTTP_SSL_VERIFY
 ///
+/// Addresses may either be HTTP(S) URLs or a path to a Unix domain socket
+/// prefixed with `unix://` (i.e. `unix:///var/run/consul.sock`). Addresses
+/// without a scheme default to `http://`, and any other invalid address fails
+/// [ConsulClient::new] with a [ClientError::InvalidAddressError].
+///
+/// Multiple addresses can be configured using `addresses` or by providing a
+/// comma-separated list in CONSUL_HTTP_ADDR. Requests are routed to `address`
+/// and fail over to the remaining `addresses`, in order, when a connection
+/// can't be established. Failed addresses are re-probed every
+/// `probe_interval`. See [FailoverTransport] for more details.
+///
+/// Requests which fail due to connection errors or transient server errors are
+/// retried according to the configured [RetryPolicy].
+///
+/// The `datacenter` and `namespace` settings are applied to every request as
+/// the `dc` and `ns` query parameters respectively. Requests which specify
+/// their own `dc` or `ns` take precedence over these defaults.
+///
+/// Values written to the KV store are limited to `kv_max_value_size` bytes,
+/// which defaults to Consul's limit of 512KiB and should match the
+/// `kv_max_value_size` limit configured on the servers.
+///
+/// Connections which can't be established within `connect_timeout` fail, as
+/// do requests which don't receive a response within `read_timeout`. Requests
+/// using [blocking queries][crate::api::features::Blocking] are given an
+/// extended deadline which covers the maximum time Consul may hold the query
+/// on top of the `read_timeout`.
+///
+/// The ACL token sent with each request is taken from `token_provider` when
+/// one is configured, otherwise the static `token` is used. Setting
+/// `token_file` (or `$CONSUL_HTTP_TOKEN_FILE`) configures a
+/// [FileTokenProvider] which re-reads the file when it changes, checking it at
+/// most once per second. Like the Consul CLI, an explicitly configured `token`
+/// takes precedence over `token_file`, which takes precedence over
+/// `$CONSUL_HTTP_TOKEN` and then `$CONSUL_HTTP_TOKEN_FILE`.
+///
 /// Note that the client key must be in an RSA or PKCS#8 format, otherwise the
 /// client will fail to be created with a "key not found" error.
 #[derive(Builder, Clone, Debug)]
@@ -134,14 +292,37 @@ impl ConsulClient {
 pub struct ConsulClientSettings {
     #[builder(default = "self.default_address()")]
     pub address: String,
+    #[builder(default = "self.default_addresses()")]
+    pub addresses: Vec<String>,
     #[builder(default = "self.default_ca_certs()")]
     pub ca_certs: Vec<String>,
     #[builder(default = "self.default_client_cert()")]
     pub client_cert: Option<String>,
     #[builder(default = "self.default_client_key()")]
     pub client_key: Option<String>,
+    #[builder(default = "Duration::from_secs(10)")]
+    pub connect_timeout: Duration,
+    #[builder(default)]
+    pub datacenter: Option<String>,
+    #[builder(default = "512 * 1024")]
+    pub kv_max_value_size: usize,
+    #[builder(default = "self.default_namespace()")]
+    pub namespace: Option<String>,
+    #[builder(default = "Duration::from_secs(10)")]
+    pub probe_interval: Duration,
+    #[builder(default = "Duration::from_secs(60)")]
+    pub read_timeout: Duration,
+    #[builder(default)]
+    pub retry: RetryPolicy,
     #[builder(setter(into), default = "self.default_token()")]
     pub token: Option<String>,
+    #[builder(default = "self.default_token_file()")]
+    pub token_file: Option<String>,
+    #[builder(
+        setter(into = false, strip_option),
+        default = "self.default_token_provider()"
+    )]
+    pub token_provider: Option<Arc<dyn TokenProvider>>,
     #[builder(default = "self.default_verify()")]
     pub verify: bool,
     #[builder(setter(into, strip_option), default = "1")]
@@ -150,15 +331,38 @@ pub struct ConsulClientSettings {
 
 impl ConsulClientSettingsBuilder {
     fn default_address(&self) -> String {
-        match env::var("CONSUL_HTTP_ADDR") {
-            Ok(s) => {
-                info!("Using consul address from $CONSUL_HTTP_ADDR");
-                s
-            }
-            Err(_) => {
-                info!("Using default consul address http://127.0.0.1:8500");
-                String::from("http://127.0.0.1:8500")
-            }
+        if let Some(a) = self.addresses.as_ref().and_then(|a| a.first()) {
+            return a.clone();
+        }
+
+        // Only the first address from the environment is preferred
+        self.env_addresses().remove(0)
+    }
+
+    fn default_addresses(&self) -> Vec<String> {
+        match &self.address {
+            Some(a) => vec![a.clone()],
+            None => self.env_addresses(),
+        }
+    }
+
+    fn env_addresses(&self) -> Vec<String> {
+        let addresses = env::var("CONSUL_HTTP_ADDR")
+            .map(|s| {
+                s.split(',')
+                    .map(|a| a.trim())
+                    .filter(|a| !a.is_empty())
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        if addresses.is_empty() {
+            info!("Using default consul address http://127.0.0.1:8500");
+            vec![String::from("http://127.0.0.1:8500")]
+        } else {
+            info!("Using consul address(es) from $CONSUL_HTTP_ADDR");
+            addresses
         }
     }
 
@@ -173,8 +377,11 @@ impl ConsulClientSettingsBuilder {
         if let Ok(s) = env::var("CONSUL_CAPATH") {
             info!("Found CA certificate path in $CONSUL_CAPATH");
             if let Ok(p) = fs::read_dir(s) {
-                for path in p {
-                    paths.push(path.unwrap().path().to_str().unwrap().to_string())
+                for path in p.flatten() {
+                    match path.path().to_str() {
+                        Some(s) => paths.push(s.to_string()),
+                        None => warn!("Skipping non UTF-8 path in $CONSUL_CAPATH"),
+                    }
                 }
             }
         }
@@ -208,6 +415,19 @@ impl ConsulClientSettingsBuilder {
         }
     }
 
+    fn default_namespace(&self) -> Option<String> {
+        match env::var("CONSUL_NAMESPACE") {
+            Ok(s) => {
+                info!("Using consul namespace from $CONSUL_NAMESPACE");
+                Some(s)
+            }
+            Err(_) => {
+                debug!("Not using a default consul namespace");
+                None
+            }
+        }
+    }
+
     fn default_token(&self) -> Option<String> {
         match env::var("CONSUL_HTTP_TOKEN") {
             Ok(s) => {
@@ -221,6 +441,36 @@ impl ConsulClientSettingsBuilder {
         }
     }
 
+    fn default_token_file(&self) -> Option<String> {
+        // A token configured explicitly or in the environment takes precedence
+        if matches!(self.token, Some(Some(_))) || env::var("CONSUL_HTTP_TOKEN").is_ok() {
+            return None;
+        }
+
+        match env::var("CONSUL_HTTP_TOKEN_FILE") {
+            Ok(s) => {
+                info!("Using consul ACL token file from $CONSUL_HTTP_TOKEN_FILE");
+                Some(s)
+            }
+            Err(_) => {
+                debug!("Not using a consul ACL token file");
+                None
+            }
+        }
+    }
+
+    fn default_token_provider(&self) -> Option<Arc<dyn TokenProvider>> {
+        if matches!(self.token, Some(Some(_))) {
+            return None;
+        }
+
+        let path = match &self.token_file {
+            Some(p) => p.clone(),
+            None => self.default_token_file(),
+        };
+        path.map(|p| Arc::new(FileTokenProvider::new(p)) as Arc<dyn TokenProvider>)
+    }
+
     fn default_verify(&self) -> bool {
         info!("Checking TLS verification using $CONSUL_HTTP_SSL_VERIFY");
         let verify = env::var("CONSUL_HTTP_SSL_VERIFY").unwrap_or_else(|_| "true".into());
@@ -231,3 +481,85 @@ impl ConsulClientSettingsBuilder {
         }
     }
 }
+
+/// Configures how failed requests are retried.
+///
+/// Only `GET` requests and endpoints which have been explicitly marked as
+/// idempotent are retried. A request is considered failed when the connection
+/// to Consul could not be established or when the server responds with one of
+/// the configured `retry_codes` (for example, a 500 returned while a leader
+/// election is taking place).
+///
+/// The delay between attempts starts at `initial_backoff` and is multiplied by
+/// `multiplier` after every attempt, up to a maximum of `max_backoff`. When
+/// `jitter` is enabled, a random delay between zero and the computed backoff
+/// is used instead in order to spread out retries from multiple clients.
+///
+/// Setting `max_attempts` to 1 disables retries.
+#[derive(Builder, Clone, Debug)]
+#[builder(setter(into))]
+pub struct RetryPolicy {
+    #[builder(default = "3")]
+    pub max_attempts: u32,
+    #[builder(default = "Duration::from_millis(100)")]
+    pub initial_backoff: Duration,
+    #[builder(default = "Duration::from_secs(5)")]
+    pub max_backoff: Duration,
+    #[builder(default = "2.0")]
+    pub multiplier: f64,
+    #[builder(default = "true")]
+    pub jitter: bool,
+    #[builder(default = "vec![429, 500, 502, 503, 504]")]
+    pub retry_codes: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::builder().build().unwrap()
+    }
+}
+
+impl RetryPolicy {
+    /// Returns a default instance of [RetryPolicyBuilder] for configuring a
+    /// policy.
+    pub fn builder() -> RetryPolicyBuilder {
+        RetryPolicyBuilder::default()
+    }
+
+    /// Returns a [RetryPolicy] which never retries requests.
+    pub fn disabled() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the delay to wait before making the next attempt, where
+    /// `attempt` is the number of attempts which have already been made.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self.multiplier.powi(attempt.saturating_sub(1) as i32);
+        let secs = (self.initial_backoff.as_secs_f64() * exp)
+            .min(self.max_backoff.as_secs_f64())
+            .max(0.0);
+        let delay = Duration::from_secs_f64(secs);
+
+        if self.jitter {
+            delay.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+        } else {
+            delay
+        }
+    }
+
+    /// Returns whether the given error is considered transient by this policy.
+    pub fn is_retryable(&self, error: &ClientError) -> bool {
+        match error {
+            ClientError::RestClientError { .. } | ClientError::TimeoutError { .. } => {
+                error.is_retryable()
+            }
+            _ => error
+                .status_code()
+                .map(|c| self.retry_codes.contains(&c))
+                .unwrap_or(false),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Codecs for encoding typed values stored in the KV store.
+//!
+//! A [Codec] converts between a `serde` type and the raw bytes stored at a
+//! key, and is chosen with a type parameter when calling functions such as
+//! [kv::read_as][crate::kv::read_as] and [kv::set_as][crate::kv::set_as]:
+//!
+//! ```should_panic
+//! # use consulrs::client::{ConsulClient, ConsulClientSettingsBuilder};
+//! use consulrs::{codec::Json, kv};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Deserialize, Serialize)]
+//! struct Config {
+//!     replicas: u32,
+//! }
+//!
+//! # let client = ConsulClient::new(
+//! #     ConsulClientSettingsBuilder::default()
+//! #         .address("https://127.0.0.1:8200")
+//! #         .build()
+//! #         .unwrap()
+//! # ).unwrap();
+//! # tokio_test::block_on(async {
+//! kv::set_as::<_, Json>(&client, "config", &Config { replicas: 3 }, None)
+//!     .await
+//!     .unwrap();
+//! let res = kv::read_as::<Config, Json>(&client, "config", None).await.unwrap();
+//! assert_eq!(res.response.value.replicas, 3);
+//! # })
+//! ```
+//!
+//! The [Json] codec is always available while the others are enabled with
+//! cargo features:
+//!
+//! * `msgpack`: [MessagePack]
+//! * `toml`: [Toml]
+//! * `yaml`: [Yaml]
+//!
+//! Custom formats are supported by implementing [Codec].
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::ClientError;
+
+/// Converts values to and from the raw bytes stored in the KV store.
+pub trait Codec {
+    /// Encodes the given value into bytes.
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, ClientError>;
+
+    /// Decodes a value from the given bytes.
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ClientError>;
+}
+
+/// A [Codec] which stores values as JSON.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, ClientError> {
+        serde_json::to_vec(value).map_err(|e| ClientError::JsonSerializeError { source: e })
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ClientError> {
+        serde_json::from_slice(bytes).map_err(|e| ClientError::JsonDeserializeError { source: e })
+    }
+}
+
+/// A [Codec] which stores values as MessagePack, with structs encoded as maps
+/// so that fields can be added without breaking existing values.
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, ClientError> {
+        rmp_serde::to_vec_named(value).map_err(|e| ClientError::MsgpackSerializeError { source: e })
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ClientError> {
+        rmp_serde::from_slice(bytes).map_err(|e| ClientError::MsgpackDeserializeError { source: e })
+    }
+}
+
+/// A [Codec] which stores values as TOML.
+///
+/// Note that TOML documents must be tables, so only types which serialize
+/// into a map (such as structs) are supported.
+#[cfg(feature = "toml")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Toml;
+
+#[cfg(feature = "toml")]
+impl Codec for Toml {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, ClientError> {
+        toml::to_vec(value).map_err(|e| ClientError::TomlSerializeError { source: e })
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ClientError> {
+        toml::from_slice(bytes).map_err(|e| ClientError::TomlDeserializeError { source: e })
+    }
+}
+
+/// A [Codec] which stores values as YAML.
+#[cfg(feature = "yaml")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Yaml;
+
+#[cfg(feature = "yaml")]
+impl Codec for Yaml {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, ClientError> {
+        serde_yaml::to_vec(value).map_err(|e| ClientError::YamlSerializeError { source: e })
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ClientError> {
+        serde_yaml::from_slice(bytes).map_err(|e| ClientError::YamlDeserializeError { source: e })
+    }
+}
@@ -0,0 +1,520 @@
+//! Loads configuration from a KV prefix and watches it for changes.
+//!
+//! The keys under a prefix are mapped onto a `serde` struct by treating each
+//! `/` in a key as a nested field, so the following keys:
+//!
+//! ```text
+//! apps/web/name          = web
+//! apps/web/db/replicas   = 3
+//! apps/web/db/hosts      = ["10.0.0.1", "10.0.0.2"]
+//! ```
+//!
+//! are read from `apps/web` into:
+//!
+//! ```
+//! # use serde::{Deserialize, Serialize};
+//! #[derive(Default, Deserialize, Serialize)]
+//! #[serde(default)]
+//! struct Config {
+//!     name: String,
+//!     db: Database,
+//! }
+//!
+//! #[derive(Default, Deserialize, Serialize)]
+//! #[serde(default)]
+//! struct Database {
+//!     replicas: u32,
+//!     hosts: Vec<String>,
+//! }
+//! ```
+//!
+//! The keys are merged over the [Default] value of the struct, so only the
+//! fields which differ from their defaults need to be stored. Each value is
+//! decoded according to the type of its field: string fields always use the
+//! value as-is, while other fields parse it as JSON so that numbers, booleans,
+//! lists and maps can be stored. A key which exists always sets an optional
+//! field.
+//!
+//! Use [load] to read the configuration once or [watch] to receive updates
+//! whenever it changes:
+//!
+//! ```no_run
+//! # use consulrs::client::{ConsulClient, ConsulClientSettingsBuilder};
+//! # use serde::{Deserialize, Serialize};
+//! # #[derive(Debug, Default, Deserialize, Serialize)]
+//! # struct Config {}
+//! use consulrs::config_watch;
+//!
+//! # tokio_test::block_on(async {
+//! let client = ConsulClient::new(ConsulClientSettingsBuilder::default().build().unwrap()).unwrap();
+//! let (watcher, mut rx) = config_watch::watch::<Config>(&client, "apps/web", None)
+//!     .await
+//!     .unwrap();
+//!
+//! tokio::join!(watcher.run(&client), async move {
+//!     while rx.changed().await.is_ok() {
+//!         let state = rx.borrow();
+//!         match &state.error {
+//!             Some(e) => eprintln!("Invalid configuration: {}", e),
+//!             None => println!("Configuration changed: {:?}", state.value),
+//!         }
+//!     }
+//! });
+//! # })
+//! ```
+use std::{collections::BTreeMap, convert::TryInto, str, time::Duration};
+
+use derive_builder::Builder;
+use serde::{
+    de::{value::MapDeserializer, DeserializeOwned, Deserializer, IntoDeserializer, Visitor},
+    forward_to_deserialize_any, Serialize,
+};
+use serde_json::Value;
+use tokio::sync::watch;
+
+use crate::{
+    api::{
+        features::{Blocking, Features},
+        kv::requests::ReadKeyRequest,
+        ApiResponse,
+    },
+    client::{Client, RetryPolicy},
+    error::ClientError,
+    kv,
+};
+
+/// The separator between the segments of a key.
+const SEPARATOR: char = '/';
+
+/// Options which modify how configuration is read by [load] and [watch].
+#[derive(Builder, Clone, Debug)]
+#[builder(setter(into, strip_option))]
+pub struct ConfigWatchOptions {
+    /// The datacenter containing the configuration
+    #[builder(default)]
+    pub dc: Option<String>,
+    /// The namespace containing the configuration
+    #[builder(default)]
+    pub ns: Option<String>,
+    /// How long to wait after a change is detected before reading the
+    /// configuration, so that a burst of writes results in a single update
+    #[builder(default = "Duration::from_secs(1)")]
+    pub debounce: Duration,
+    /// The maximum time a blocking query waits for a change
+    #[builder(default)]
+    pub wait: Option<Duration>,
+    /// The policy which determines the backoff between failed reads. The
+    /// watch never stops retrying, so `max_attempts` is ignored.
+    #[builder(default)]
+    pub retry: RetryPolicy,
+}
+
+impl Default for ConfigWatchOptions {
+    fn default() -> Self {
+        ConfigWatchOptions::builder().build().unwrap()
+    }
+}
+
+impl ConfigWatchOptions {
+    /// Returns a default instance of [ConfigWatchOptionsBuilder].
+    pub fn builder() -> ConfigWatchOptionsBuilder {
+        ConfigWatchOptionsBuilder::default()
+    }
+}
+
+/// The latest configuration published by a [ConfigWatcher].
+#[derive(Debug)]
+pub struct ConfigState<T> {
+    /// The last configuration which was successfully decoded
+    pub value: T,
+    /// The index the configuration was read at
+    pub index: u64,
+    /// The error from the last attempt at reading the configuration, if it
+    /// failed. The `value` is kept until the configuration is valid again.
+    pub error: Option<ClientError>,
+}
+
+/// Watches a KV prefix for changes and publishes the decoded configuration to
+/// the receivers returned by [watch].
+#[derive(Debug)]
+pub struct ConfigWatcher<T> {
+    prefix: String,
+    opts: ConfigWatchOptions,
+    pairs: BTreeMap<String, Vec<u8>>,
+    failed: bool,
+    sender: watch::Sender<ConfigState<T>>,
+}
+
+impl<T> ConfigWatcher<T>
+where
+    T: DeserializeOwned + Serialize + Default,
+{
+    /// Returns a new receiver for the configuration.
+    pub fn subscribe(&self) -> watch::Receiver<ConfigState<T>> {
+        self.sender.subscribe()
+    }
+
+    /// Watches the configuration until all receivers are dropped.
+    ///
+    /// A new value is only published when the keys under the prefix change.
+    /// Failed reads and configuration which can't be decoded are published as
+    /// an `error` alongside the last valid value, and reads are retried with
+    /// backoff.
+    pub async fn run(mut self, client: &impl Client) {
+        let mut index = self.sender.borrow().index;
+        let mut failures = 0;
+        while !self.sender.is_closed() {
+            // An index of 0 doesn't block, so wait for any change instead
+            let res = match read(client, &self.prefix, &self.opts, index.max(1)).await {
+                Ok(r) if r.index == index => continue,
+                Ok(_) => {
+                    // Wait for a burst of changes to settle before reading the
+                    // latest configuration
+                    tokio::time::sleep(self.opts.debounce).await;
+                    read(client, &self.prefix, &self.opts, 0).await
+                }
+                Err(e) => Err(e),
+            };
+
+            match res {
+                Ok(r) => {
+                    failures = 0;
+                    // The index is reset if it goes backwards (i.e. after a
+                    // snapshot restore)
+                    index = if r.index < index { 0 } else { r.index };
+                    self.update(r.pairs, index);
+                }
+                Err(e) => {
+                    failures += 1;
+                    let delay = self.opts.retry.backoff(failures);
+                    warn!(
+                        "Failed reading configuration from {}, retrying in {:?}: {}",
+                        self.prefix, delay, e
+                    );
+                    self.failed = true;
+                    self.sender.send_modify(|s| s.error = Some(e));
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+        debug!("Stopped watching configuration at {}", self.prefix);
+    }
+
+    fn update(&mut self, pairs: BTreeMap<String, Vec<u8>>, index: u64) {
+        // A failed read replaces the error of the previous configuration, so
+        // it needs to be decoded again even if it hasn't changed
+        if pairs == self.pairs && !self.failed {
+            return;
+        }
+        self.failed = false;
+
+        match decode::<T>(&self.prefix, &pairs) {
+            Ok(value) => {
+                info!("Configuration at {} changed", self.prefix);
+                self.sender.send_replace(ConfigState {
+                    value,
+                    index,
+                    error: None,
+                });
+            }
+            Err(e) => {
+                warn!("Failed decoding configuration at {}: {}", self.prefix, e);
+                self.sender.send_modify(|s| {
+                    s.index = index;
+                    s.error = Some(e);
+                });
+            }
+        }
+        self.pairs = pairs;
+    }
+}
+
+/// Reads the configuration under the given prefix once, merged over the
+/// default value of `T`.
+///
+/// The prefix is treated as a folder, so a trailing `/` is added if it's
+/// missing. The default value is returned if no keys exist under it.
+///
+/// See [ReadKeyRequest]
+#[instrument(skip(client, opts), err)]
+pub async fn load<T>(
+    client: &impl Client,
+    prefix: &str,
+    opts: Option<&mut ConfigWatchOptionsBuilder>,
+) -> Result<ApiResponse<T>, ClientError>
+where
+    T: DeserializeOwned + Serialize + Default,
+{
+    let mut t = ConfigWatchOptions::builder();
+    let opts = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
+    let prefix = kv::folder_path(prefix)?;
+    let res = read(client, &prefix, &opts, 0).await?;
+    let value = decode(&prefix, &res.pairs)?;
+
+    ApiResponse::builder()
+        .response(value)
+        .index(res.index)
+        .build()
+        .map_err(ClientError::from_builder)
+}
+
+/// Reads the configuration under the given prefix and returns a
+/// [ConfigWatcher] which publishes changes to it along with a receiver for
+/// them.
+///
+/// The initial read must succeed, so the receiver always starts with a valid
+/// configuration. The watcher does nothing until [ConfigWatcher::run] is
+/// awaited.
+#[instrument(skip(client, opts), err)]
+pub async fn watch<T>(
+    client: &impl Client,
+    prefix: &str,
+    opts: Option<&mut ConfigWatchOptionsBuilder>,
+) -> Result<(ConfigWatcher<T>, watch::Receiver<ConfigState<T>>), ClientError>
+where
+    T: DeserializeOwned + Serialize + Default,
+{
+    let mut t = ConfigWatchOptions::builder();
+    let opts = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
+    let prefix = kv::folder_path(prefix)?;
+    let res = read(client, &prefix, &opts, 0).await?;
+    let value = decode(&prefix, &res.pairs)?;
+
+    let (sender, receiver) = watch::channel(ConfigState {
+        value,
+        index: res.index,
+        error: None,
+    });
+    let watcher = ConfigWatcher {
+        prefix,
+        opts,
+        pairs: res.pairs,
+        failed: false,
+        sender,
+    };
+    Ok((watcher, receiver))
+}
+
+/// The values of the keys under a prefix along with the index they were read
+/// at.
+struct Tree {
+    index: u64,
+    pairs: BTreeMap<String, Vec<u8>>,
+}
+
+/// Reads all keys under the given prefix, blocking until the given index
+/// changes if it's non-zero.
+async fn read(
+    client: &impl Client,
+    prefix: &str,
+    opts: &ConfigWatchOptions,
+    index: u64,
+) -> Result<Tree, ClientError> {
+    let mut req = ReadKeyRequest::builder();
+    req.recurse(true);
+    if let Some(dc) = &opts.dc {
+        req.dc(dc);
+    }
+    if let Some(ns) = &opts.ns {
+        req.ns(ns);
+    }
+    if index > 0 {
+        req.features(Features {
+            blocking: Some(Blocking {
+                index,
+                wait: opts.wait.map(|w| w.into()),
+            }),
+            ..Default::default()
+        });
+    }
+
+    let res = match kv::read(client, prefix, Some(&mut req)).await {
+        Ok(r) => r,
+        Err(ClientError::NotFound { index, .. }) => {
+            return Ok(Tree {
+                index: index.unwrap_or(0),
+                pairs: BTreeMap::new(),
+            })
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut pairs = BTreeMap::new();
+    for pair in res.response {
+        let value = match pair.value {
+            Some(v) => v.try_into()?,
+            None => Vec::new(),
+        };
+        pairs.insert(pair.key, value);
+    }
+    Ok(Tree {
+        index: res.index.unwrap_or(0),
+        pairs,
+    })
+}
+
+/// Decodes the given keys under the prefix into `T`, merged over its default
+/// value.
+fn decode<T>(prefix: &str, pairs: &BTreeMap<String, Vec<u8>>) -> Result<T, ClientError>
+where
+    T: DeserializeOwned + Serialize + Default,
+{
+    let mut root = Node::from(
+        serde_json::to_value(T::default())
+            .map_err(|e| ClientError::JsonSerializeError { source: e })?,
+    );
+    for (key, value) in pairs {
+        let relative = match key.strip_prefix(prefix) {
+            Some(r) if !r.is_empty() && !r.ends_with(SEPARATOR) => r,
+            // Folders don't map onto a field
+            _ => continue,
+        };
+        let value =
+            str::from_utf8(value).map_err(|e| ClientError::Utf8DecodeError { source: e })?;
+        root.insert(relative, value);
+    }
+    T::deserialize(root).map_err(|e| ClientError::JsonDeserializeError { source: e })
+}
+
+/// A node in the tree of keys being decoded by [decode].
+///
+/// The values of keys are only parsed once the type of their field is known,
+/// so that a value like `123` stays a string when it's stored in a string
+/// field.
+enum Node {
+    /// A folder of nodes, keyed by the segment of their key
+    Folder(BTreeMap<String, Node>),
+    /// The raw value of a key
+    Key(String),
+    /// A value taken from the default value of `T`
+    Default(Value),
+}
+
+impl Node {
+    /// Inserts the value at the given path, replacing anything which isn't a
+    /// folder along the way.
+    fn insert(&mut self, path: &str, value: &str) {
+        let mut node = self;
+        let mut segments = path.split(SEPARATOR).peekable();
+        while let Some(segment) = segments.next() {
+            if !matches!(node, Node::Folder(_)) {
+                *node = Node::Folder(BTreeMap::new());
+            }
+            let children = match node {
+                Node::Folder(c) => c,
+                _ => unreachable!(),
+            };
+            if segments.peek().is_none() {
+                children.insert(segment.to_string(), Node::Key(value.to_string()));
+                return;
+            }
+            node = children
+                .entry(segment.to_string())
+                .or_insert(Node::Default(Value::Null));
+        }
+    }
+
+    /// Converts the node into a [Value], parsing the values of keys as JSON.
+    fn into_value(self) -> Value {
+        match self {
+            Node::Folder(children) => Value::Object(
+                children
+                    .into_iter()
+                    .map(|(k, v)| (k, v.into_value()))
+                    .collect(),
+            ),
+            Node::Key(raw) => parse(raw),
+            Node::Default(value) => value,
+        }
+    }
+}
+
+impl From<Value> for Node {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Object(map) => {
+                Node::Folder(map.into_iter().map(|(k, v)| (k, v.into())).collect())
+            }
+            v => Node::Default(v),
+        }
+    }
+}
+
+impl<'de> IntoDeserializer<'de, serde_json::Error> for Node {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for Node {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            Node::Folder(children) => visitor.visit_map(MapDeserializer::new(children.into_iter())),
+            Node::Key(raw) => parse(raw).deserialize_any(visitor),
+            Node::Default(value) => value.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            Node::Key(raw) => visitor.visit_string(raw),
+            node => node.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            Node::Default(value) => value.deserialize_option(visitor),
+            node => visitor.visit_some(node),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.into_value().deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 bytes byte_buf unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// Parses the raw value of a key as JSON, falling back to a string if it isn't
+/// valid JSON.
+fn parse(raw: String) -> Value {
+    match serde_json::from_str(&raw) {
+        Ok(v) => v,
+        Err(_) => Value::String(raw),
+    }
+}
@@ -1,14 +1,24 @@
-use std::str::Utf8Error;
+use std::{str::Utf8Error, time::Duration};
 
 use thiserror::Error;
 
+use crate::api::txn::common::TxnError;
+
 /// The common error type returned by this crate
 #[derive(Error, Debug)]
 pub enum ClientError {
+    #[error("The ACL token used for the request was not found")]
+    ACLNotFound { message: Option<String> },
     #[error("The Consul server returned an error (status code {code})")]
     APIError { code: u16, message: Option<String> },
     #[error("Failed decoding Base64 response")]
     Base64DecodeError { source: base64::DecodeError },
+    #[error("Error building request: {message}")]
+    BuilderError { message: String },
+    #[error("The request conflicted with the current state of the resource")]
+    CasConflict { message: Option<String> },
+    #[error("Error parsing duration: {value}")]
+    DurationParseError { value: String },
     #[error("Empty response")]
     EmptyResponseError,
     #[error("Error reading file: {path}")]
@@ -16,17 +26,74 @@ pub enum ClientError {
         source: std::io::Error,
         path: String,
     },
+    #[error("Error writing file: {path}")]
+    FileWriteError {
+        source: std::io::Error,
+        path: String,
+    },
+    #[error("Error evaluating filter: {message}")]
+    FilterError { message: String },
+    #[error("Error decoding response header {header} as UTF-8")]
+    HeaderDecodeError {
+        header: String,
+        source: http::header::ToStrError,
+    },
+    #[error("Error parsing response header {header}: {value}")]
+    HeaderParseError { header: String, value: String },
+    #[error("Unsupported Consul address: {address}")]
+    InvalidAddressError { address: String },
+    #[error("Invalid chunked value at {key}: {message}")]
+    InvalidChunkedValueError { key: String, message: String },
+    #[error("Invalid value for header {header}")]
+    InvalidHeaderValueError {
+        header: String,
+        source: http::header::InvalidHeaderValue,
+    },
+    #[error("Invalid key {key}: {message}")]
+    InvalidKeyError { key: String, message: String },
+    #[error("Invalid snapshot archive: {message}")]
+    InvalidSnapshotError { message: String },
+    #[error("The ACL token contains characters which aren't allowed in a header")]
+    InvalidTokenError {
+        source: http::header::InvalidHeaderValue,
+    },
+    #[error("Error building request URL: {url}")]
+    InvalidUrlError { url: String },
     #[error("Error deserializing JSON string")]
     JsonDeserializeError { source: serde_json::Error },
     #[error("Error Serializing JSON string")]
     JsonSerializeError { source: serde_json::Error },
+    #[cfg(feature = "msgpack")]
+    #[error("Error deserializing MessagePack value")]
+    MsgpackDeserializeError { source: rmp_serde::decode::Error },
+    #[cfg(feature = "msgpack")]
+    #[error("Error serializing MessagePack value")]
+    MsgpackSerializeError { source: rmp_serde::encode::Error },
+    #[error("The Consul cluster has no leader")]
+    NoLeader { code: u16, message: Option<String> },
+    #[error("The requested resource was not found")]
+    NotFound {
+        /// The index to use for a blocking query waiting for the resource to
+        /// be created (`X-Consul-Index`)
+        index: Option<u64>,
+        message: Option<String>,
+    },
     #[error("Error parsing CA certificate as PEM encoded certificate: {path}")]
     ParseCertificateError {
         source: reqwest::Error,
         path: String,
     },
+    #[error("Permission denied")]
+    PermissionDenied { message: Option<String> },
+    #[error("The request was rate limited by the Consul server")]
+    RateLimited { retry_after: Option<Duration> },
     #[error("The request returned an empty response")]
     ResponseEmptyError,
+    #[error("The request failed after {attempts} attempts")]
+    RetriesExhaustedError {
+        attempts: u32,
+        source: Box<ClientError>,
+    },
     #[error("An error occurred with the request")]
     RestClientError {
         #[from]
@@ -34,6 +101,93 @@ pub enum ClientError {
     },
     #[error("Error configuring REST client")]
     RestClientBuildError { source: reqwest::Error },
+    #[error("Snapshot file {file} doesn't match its checksum")]
+    SnapshotChecksumError { file: String },
+    #[error("Error writing streamed response")]
+    StreamWriteError { source: std::io::Error },
+    #[error("The request timed out after {timeout:?}")]
+    TimeoutError { timeout: Duration },
+    #[cfg(feature = "toml")]
+    #[error("Error deserializing TOML document")]
+    TomlDeserializeError { source: toml::de::Error },
+    #[cfg(feature = "toml")]
+    #[error("Error serializing TOML document")]
+    TomlSerializeError { source: toml::ser::Error },
+    #[error(
+        "The transaction was rolled back: {}",
+        .errors.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    )]
+    TransactionError { errors: Vec<TxnError> },
     #[error("Error decoding bytes into UTF-8 string")]
     Utf8DecodeError { source: Utf8Error },
+    #[error("The value for key {key} is {size} bytes which exceeds the limit of {max} bytes")]
+    ValueTooLargeError {
+        key: String,
+        size: usize,
+        max: usize,
+    },
+    #[cfg(feature = "yaml")]
+    #[error("Error deserializing YAML document")]
+    YamlDeserializeError { source: serde_yaml::Error },
+    #[cfg(feature = "yaml")]
+    #[error("Error serializing YAML document")]
+    YamlSerializeError { source: serde_yaml::Error },
+}
+
+impl ClientError {
+    /// Converts an error returned by a builder's `build()` method into a
+    /// [ClientError::BuilderError].
+    pub fn from_builder(e: impl std::fmt::Display) -> Self {
+        ClientError::BuilderError {
+            message: e.to_string(),
+        }
+    }
+
+    /// Returns the HTTP status code returned by the Consul server, if this
+    /// error was caused by an error response.
+    pub fn status_code(&self) -> Option<u16> {
+        match self {
+            ClientError::ACLNotFound { .. } | ClientError::PermissionDenied { .. } => Some(403),
+            ClientError::APIError { code, .. } | ClientError::NoLeader { code, .. } => Some(*code),
+            ClientError::CasConflict { .. } => Some(409),
+            ClientError::NotFound { .. } => Some(404),
+            ClientError::RateLimited { .. } => Some(429),
+            ClientError::RetriesExhaustedError { source, .. } => source.status_code(),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the requested resource was not found.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, ClientError::NotFound { .. })
+    }
+
+    /// Returns whether the request was denied due to ACLs, either because the
+    /// token lacks permissions or because it doesn't exist.
+    pub fn is_permission_denied(&self) -> bool {
+        matches!(
+            self,
+            ClientError::ACLNotFound { .. } | ClientError::PermissionDenied { .. }
+        )
+    }
+
+    /// Returns whether the error is transient and the request may succeed if
+    /// it's retried.
+    ///
+    /// This includes connection failures, timeouts, rate limiting, a missing
+    /// cluster leader and server errors returned by proxies in front of Consul.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClientError::APIError { code, .. } => (502..=504).contains(code),
+            ClientError::NoLeader { .. }
+            | ClientError::RateLimited { .. }
+            | ClientError::TimeoutError { .. } => true,
+            ClientError::RestClientError { source } => matches!(
+                source,
+                rustify::errors::ClientError::RequestError { .. }
+                    | rustify::errors::ClientError::ResponseError { .. }
+            ),
+            _ => false,
+        }
+    }
 }
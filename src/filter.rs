@@ -0,0 +1,441 @@
+//! A builder for Consul [filter expressions][1].
+//!
+//! Endpoints which support [filtering][crate::api::Features] accept an
+//! expression written in Consul's filtering language. Writing these by hand is
+//! error prone since mistakes, such as a missing quote, only surface as an API
+//! error when the request is made. The [Expression] builder takes care of
+//! quoting and escaping values and selectors:
+//!
+//! ```
+//! use consulrs::filter::selector;
+//!
+//! let expr = selector("Service.Tags")
+//!     .contains("primary")
+//!     .and(selector("Service.Meta").key("env").eq("prod"))
+//!     .or(!selector("Checks.Status").eq("passing"));
+//! assert_eq!(
+//!     expr.to_string(),
+//!     r#"Service.Tags contains "primary" and Service.Meta["env"] == "prod" or not Checks.Status == "passing""#
+//! );
+//! ```
+//!
+//! An [Expression] converts into a [String] and can therefore be passed
+//! directly to any builder which accepts a filter.
+//!
+//! When the `testing` feature is enabled, [Expression::evaluate] can be used to
+//! apply an expression to a local value (i.e. an
+//! [AgentService][crate::api::service::common::AgentService]) in order to test
+//! it without a Consul server.
+//!
+//! [1]: https://www.consul.io/api-docs/features/filtering
+use std::{fmt, ops};
+
+#[cfg(feature = "testing")]
+use crate::error::ClientError;
+
+/// Creates a [Selector] from a `.` separated path, i.e. `Service.Tags`.
+pub fn selector(path: &str) -> Selector {
+    Selector {
+        segments: path.split('.').map(|s| Segment::Field(s.into())).collect(),
+    }
+}
+
+/// A selector which refers to a field of the data being filtered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selector {
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Field(String),
+    Key(String),
+}
+
+impl Selector {
+    /// Creates a new [Selector] which refers to the given top-level field.
+    pub fn new(field: &str) -> Self {
+        Selector {
+            segments: vec![Segment::Field(field.into())],
+        }
+    }
+
+    /// Selects the given field of this selector.
+    pub fn field(mut self, name: &str) -> Self {
+        self.segments.push(Segment::Field(name.into()));
+        self
+    }
+
+    /// Selects the given key of the map referred to by this selector.
+    pub fn key(mut self, key: &str) -> Self {
+        self.segments.push(Segment::Key(key.into()));
+        self
+    }
+
+    /// Matches if the selected value equals the given value.
+    pub fn eq(self, value: impl Into<Value>) -> Expression {
+        self.with(MatchOp::Equal(value.into()))
+    }
+
+    /// Matches if the selected value doesn't equal the given value.
+    pub fn ne(self, value: impl Into<Value>) -> Expression {
+        self.with(MatchOp::NotEqual(value.into()))
+    }
+
+    /// Matches if the given value is in the selected collection.
+    pub fn is_in(self, value: impl Into<Value>) -> Expression {
+        self.with(MatchOp::In(value.into()))
+    }
+
+    /// Matches if the given value isn't in the selected collection.
+    pub fn not_in(self, value: impl Into<Value>) -> Expression {
+        self.with(MatchOp::NotIn(value.into()))
+    }
+
+    /// Matches if the selected collection contains the given value.
+    pub fn contains(self, value: impl Into<Value>) -> Expression {
+        self.with(MatchOp::Contains(value.into()))
+    }
+
+    /// Matches if the selected collection doesn't contain the given value.
+    pub fn not_contains(self, value: impl Into<Value>) -> Expression {
+        self.with(MatchOp::NotContains(value.into()))
+    }
+
+    /// Matches if the selected value matches the given regular expression.
+    pub fn matches(self, regex: &str) -> Expression {
+        self.with(MatchOp::Matches(regex.into()))
+    }
+
+    /// Matches if the selected value doesn't match the given regular
+    /// expression.
+    pub fn not_matches(self, regex: &str) -> Expression {
+        self.with(MatchOp::NotMatches(regex.into()))
+    }
+
+    /// Matches if the selected value is empty.
+    pub fn is_empty(self) -> Expression {
+        self.with(MatchOp::Empty)
+    }
+
+    /// Matches if the selected value isn't empty.
+    pub fn is_not_empty(self) -> Expression {
+        self.with(MatchOp::NotEmpty)
+    }
+
+    fn with(self, op: MatchOp) -> Expression {
+        Expression::Match { selector: self, op }
+    }
+
+    #[cfg(feature = "testing")]
+    fn resolve<'a>(&self, value: &'a serde_json::Value) -> Option<&'a serde_json::Value> {
+        self.segments.iter().try_fold(value, |v, s| {
+            let obj = v.as_object()?;
+            match s {
+                Segment::Field(f) => obj.get(f).or_else(|| {
+                    obj.iter()
+                        .find(|(k, _)| k.eq_ignore_ascii_case(f))
+                        .map(|(_, v)| v)
+                }),
+                Segment::Key(k) => obj.get(k),
+            }
+        })
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Field(name) if is_identifier(name) => {
+                    if i > 0 {
+                        write!(f, ".")?;
+                    }
+                    write!(f, "{}", name)?;
+                }
+                // Fields which aren't valid identifiers must be indexed
+                Segment::Field(name) | Segment::Key(name) => write!(f, "[{}]", quote(name))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A literal value used in an [Expression].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Number(String),
+    String(String),
+}
+
+impl Value {
+    #[cfg(feature = "testing")]
+    fn text(&self) -> String {
+        match self {
+            Value::Bool(b) => b.to_string(),
+            Value::Number(n) => n.clone(),
+            Value::String(s) => s.clone(),
+        }
+    }
+
+    /// Returns whether this value equals the given scalar, converting this
+    /// value to the type of the scalar in the same way that Consul does.
+    #[cfg(feature = "testing")]
+    fn equals(&self, other: &serde_json::Value) -> bool {
+        match other {
+            serde_json::Value::Bool(b) => self.text() == b.to_string(),
+            serde_json::Value::Number(n) => match (self.text().parse::<f64>(), n.as_f64()) {
+                (Ok(a), Some(b)) => (a - b).abs() < f64::EPSILON,
+                _ => false,
+            },
+            serde_json::Value::String(s) => self.text() == *s,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", quote(s)),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Number(n.to_string())
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self {
+        Value::Number(n.to_string())
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n.to_string())
+    }
+}
+
+/// The operation performed by a match expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MatchOp {
+    Equal(Value),
+    NotEqual(Value),
+    In(Value),
+    NotIn(Value),
+    Contains(Value),
+    NotContains(Value),
+    Matches(String),
+    NotMatches(String),
+    Empty,
+    NotEmpty,
+}
+
+/// A filter expression.
+///
+/// Expressions are created from a [Selector] and combined using
+/// [Expression::and], [Expression::or] and the `!` operator. Parentheses are
+/// added where needed to preserve the structure of the expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    And(Box<Expression>, Box<Expression>),
+    Match { selector: Selector, op: MatchOp },
+    Not(Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    /// Matches if both this and the given expression match.
+    pub fn and(self, other: Expression) -> Expression {
+        Expression::And(Box::new(self), Box::new(other))
+    }
+
+    /// Matches if either this or the given expression match.
+    pub fn or(self, other: Expression) -> Expression {
+        Expression::Or(Box::new(self), Box::new(other))
+    }
+
+    /// Evaluates this expression against the given value.
+    ///
+    /// The value is serialized to JSON and selectors are resolved against its
+    /// fields, which for the types in this crate use the same names as Consul.
+    /// Selectors which don't resolve to a value are treated as empty. Returns
+    /// a [ClientError::FilterError] if a selector refers to a value which
+    /// doesn't support the operation or a regular expression is invalid.
+    #[cfg(feature = "testing")]
+    pub fn evaluate<T: serde::Serialize>(&self, value: &T) -> Result<bool, ClientError> {
+        let value = serde_json::to_value(value)
+            .map_err(|e| ClientError::JsonSerializeError { source: e })?;
+        self.eval(&value)
+    }
+
+    #[cfg(feature = "testing")]
+    fn eval(&self, value: &serde_json::Value) -> Result<bool, ClientError> {
+        use serde_json::Value as Json;
+
+        let (selector, op) = match self {
+            Expression::And(a, b) => return Ok(a.eval(value)? && b.eval(value)?),
+            Expression::Or(a, b) => return Ok(a.eval(value)? || b.eval(value)?),
+            Expression::Not(e) => return Ok(!e.eval(value)?),
+            Expression::Match { selector, op } => (selector, op),
+        };
+        let field = selector.resolve(value).unwrap_or(&Json::Null);
+        let unsupported = |op: &str| ClientError::FilterError {
+            message: format!("{} can't be applied to selector {}", op, selector),
+        };
+
+        let contains = |v: &Value| match field {
+            Json::Null => Ok(false),
+            Json::Array(a) => Ok(a.iter().any(|e| v.equals(e))),
+            Json::Object(o) => Ok(o.contains_key(&v.text())),
+            Json::String(s) => Ok(s.contains(&v.text())),
+            _ => Err(unsupported("contains")),
+        };
+        let equals = |v: &Value| match field {
+            Json::Null => Ok(false),
+            Json::Array(_) | Json::Object(_) => Err(unsupported("==")),
+            f => Ok(v.equals(f)),
+        };
+        let matches = |re: &str| {
+            let re = regex::Regex::new(re).map_err(|e| ClientError::FilterError {
+                message: e.to_string(),
+            })?;
+            match field {
+                Json::Null => Ok(false),
+                Json::String(s) => Ok(re.is_match(s)),
+                _ => Err(unsupported("matches")),
+            }
+        };
+        let empty = || match field {
+            Json::Null => Ok(true),
+            Json::Array(a) => Ok(a.is_empty()),
+            Json::Object(o) => Ok(o.is_empty()),
+            Json::String(s) => Ok(s.is_empty()),
+            _ => Err(unsupported("is empty")),
+        };
+
+        match op {
+            MatchOp::Equal(v) => equals(v),
+            MatchOp::NotEqual(v) => equals(v).map(|r| !r),
+            MatchOp::In(v) | MatchOp::Contains(v) => contains(v),
+            MatchOp::NotIn(v) | MatchOp::NotContains(v) => contains(v).map(|r| !r),
+            MatchOp::Matches(re) => matches(re),
+            MatchOp::NotMatches(re) => matches(re).map(|r| !r),
+            MatchOp::Empty => empty(),
+            MatchOp::NotEmpty => empty().map(|r| !r),
+        }
+    }
+
+    /// Formats this expression, wrapping it in parentheses if it's a compound
+    /// expression which binds less tightly than the given operator.
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, parent: &Expression) -> fmt::Result {
+        let wrap = matches!(
+            (parent, self),
+            (Expression::And(..), Expression::Or(..))
+                | (Expression::Not(..), Expression::And(..))
+                | (Expression::Not(..), Expression::Or(..))
+        );
+        match wrap {
+            true => write!(f, "({})", self),
+            false => write!(f, "{}", self),
+        }
+    }
+}
+
+impl ops::Not for Expression {
+    type Output = Expression;
+
+    fn not(self) -> Self::Output {
+        Expression::Not(Box::new(self))
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::And(a, b) | Expression::Or(a, b) => {
+                let op = match self {
+                    Expression::And(..) => "and",
+                    _ => "or",
+                };
+                a.fmt_operand(f, self)?;
+                write!(f, " {} ", op)?;
+                b.fmt_operand(f, self)
+            }
+            Expression::Not(e) => {
+                write!(f, "not ")?;
+                e.fmt_operand(f, self)
+            }
+            Expression::Match { selector, op } => match op {
+                MatchOp::Equal(v) => write!(f, "{} == {}", selector, v),
+                MatchOp::NotEqual(v) => write!(f, "{} != {}", selector, v),
+                MatchOp::In(v) => write!(f, "{} in {}", v, selector),
+                MatchOp::NotIn(v) => write!(f, "{} not in {}", v, selector),
+                MatchOp::Contains(v) => write!(f, "{} contains {}", selector, v),
+                MatchOp::NotContains(v) => write!(f, "{} not contains {}", selector, v),
+                MatchOp::Matches(re) => write!(f, "{} matches {}", selector, quote(re)),
+                MatchOp::NotMatches(re) => write!(f, "{} not matches {}", selector, quote(re)),
+                MatchOp::Empty => write!(f, "{} is empty", selector),
+                MatchOp::NotEmpty => write!(f, "{} is not empty", selector),
+            },
+        }
+    }
+}
+
+impl From<Expression> for String {
+    fn from(e: Expression) -> Self {
+        e.to_string()
+    }
+}
+
+/// Returns whether the given name can be used unquoted in a selector.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Quotes the given string, escaping it according to Go's string literal
+/// rules.
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -21,6 +21,10 @@ pub async fn list_nodes_for_service(
     opts: Option<&mut ListNodesForServiceRequestBuilder>,
 ) -> Result<ApiResponse<Vec<HealthServiceChecksInfo>>, ClientError> {
     let mut t = ListNodesForServiceRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).service(service).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .service(service)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
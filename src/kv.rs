@@ -1,3 +1,13 @@
+pub use self::store::KvStore;
+pub use self::transfer::{export, import, ImportOptions, ImportOptionsBuilder, ImportPlan};
+pub use self::tree::{
+    copy_tree, delete_tree, list_folders, move_tree, tree, KVFolder, TreeOptions,
+    TreeOptionsBuilder,
+};
+pub use self::update::{update, update_json, UpdateOptions, UpdateOptionsBuilder};
+
+pub(crate) use self::tree::folder_path;
+
 use std::convert::TryInto;
 
 use crate::{
@@ -14,10 +24,17 @@ use crate::{
         ApiResponse,
     },
     client::Client,
+    codec::{Codec, Json},
     error::ClientError,
 };
 use serde::{de::DeserializeOwned, Serialize};
 
+pub mod chunked;
+mod store;
+mod transfer;
+mod tree;
+mod update;
+
 /// Deletes the given key.
 ///
 /// See [DeleteKeyRequest]
@@ -28,7 +45,11 @@ pub async fn delete(
     opts: Option<&mut DeleteKeyRequestBuilder>,
 ) -> Result<ApiResponse<bool>, ClientError> {
     let mut t = DeleteKeyRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).key(key).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .key(normalize_key(key)?)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -42,7 +63,11 @@ pub async fn keys(
     opts: Option<&mut ReadKeysRequestBuilder>,
 ) -> Result<ApiResponse<Vec<String>>, ClientError> {
     let mut t = ReadKeysRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).key(path).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .key(normalize_key(path)?)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -56,7 +81,11 @@ pub async fn read_raw(
     opts: Option<&mut ReadRawKeyRequestBuilder>,
 ) -> Result<ApiResponse<Vec<u8>>, ClientError> {
     let mut t = ReadRawKeyRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).key(key).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .key(normalize_key(key)?)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_raw(client, endpoint).await
 }
 
@@ -70,11 +99,16 @@ pub async fn read(
     opts: Option<&mut ReadKeyRequestBuilder>,
 ) -> Result<ApiResponse<Vec<KVPair>>, ClientError> {
     let mut t = ReadKeyRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).key(key).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .key(normalize_key(key)?)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
-/// Reads the JSON value at the given key and deserializes it into an object.
+/// Reads the value at the given key and decodes it into an object using the
+/// given [Codec].
 ///
 /// If the API call returns an empty list then this function will return a
 /// [ClientError::EmptyResponseError]. Note that the function only handles a
@@ -82,20 +116,25 @@ pub async fn read(
 ///
 /// See [ReadKeyRequest]
 #[instrument(skip(client, opts), err)]
-pub async fn read_json<T: DeserializeOwned, C: Client>(
-    client: &C,
+pub async fn read_as<T: DeserializeOwned, C: Codec>(
+    client: &impl Client,
     key: &str,
     opts: Option<&mut ReadKeyRequestBuilder>,
 ) -> Result<ApiResponse<GenericKVPair<T>>, ClientError> {
     let mut t = ReadKeyRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).key(key).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .key(normalize_key(key)?)
+        .build()
+        .map_err(ClientError::from_builder)?;
     let mut res = api::exec_with_result(client, endpoint).await?;
 
-    if !res.response.is_empty() {
-        let kv = res.response.pop().unwrap();
-        let bytes: Vec<u8> = kv.value.unwrap().try_into()?;
-        let t = serde_json::from_slice(&bytes)
-            .map_err(|e| ClientError::JsonDeserializeError { source: e })?;
+    if let Some(kv) = res.response.pop() {
+        let bytes: Vec<u8> = match kv.value {
+            Some(v) => v.try_into()?,
+            None => Vec::new(),
+        };
+        let t = C::decode(&bytes)?;
         let gkv = GenericKVPair {
             value: t,
             create_index: kv.create_index,
@@ -108,6 +147,7 @@ pub async fn read_json<T: DeserializeOwned, C: Client>(
         };
         Ok(ApiResponse {
             response: gkv,
+            age: res.age,
             cache: res.cache,
             content_hash: res.content_hash,
             default_acl_policy: res.default_acl_policy,
@@ -121,6 +161,22 @@ pub async fn read_json<T: DeserializeOwned, C: Client>(
     }
 }
 
+/// Reads the JSON value at the given key and deserializes it into an object.
+///
+/// If the API call returns an empty list then this function will return a
+/// [ClientError::EmptyResponseError]. Note that the function only handles a
+/// single value - only the first element in the list is parsed and returned.
+///
+/// See [read_as] and [ReadKeyRequest]
+#[instrument(skip(client, opts), err)]
+pub async fn read_json<T: DeserializeOwned, C: Client>(
+    client: &C,
+    key: &str,
+    opts: Option<&mut ReadKeyRequestBuilder>,
+) -> Result<ApiResponse<GenericKVPair<T>>, ClientError> {
+    read_as::<T, Json>(client, key, opts).await
+}
+
 /// Reads the raw JSON value at the given key and deserializes it into an object.
 ///
 /// See [ReadRawKeyRequest]
@@ -131,7 +187,11 @@ pub async fn read_json_raw<T: DeserializeOwned, C: Client>(
     opts: Option<&mut ReadRawKeyRequestBuilder>,
 ) -> Result<ApiResponse<T>, ClientError> {
     let mut t = ReadRawKeyRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).key(key).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .key(normalize_key(key)?)
+        .build()
+        .map_err(ClientError::from_builder)?;
     let res = api::exec_with_raw(client, endpoint).await?;
 
     if !res.response.is_empty() {
@@ -139,6 +199,7 @@ pub async fn read_json_raw<T: DeserializeOwned, C: Client>(
             .map_err(|e| ClientError::JsonDeserializeError { source: e })?;
         Ok(ApiResponse {
             response: t,
+            age: res.age,
             cache: res.cache,
             content_hash: res.content_hash,
             default_acl_policy: res.default_acl_policy,
@@ -154,6 +215,11 @@ pub async fn read_json_raw<T: DeserializeOwned, C: Client>(
 
 /// Sets the value at the given key.
 ///
+/// The key is [normalized][normalize_key] and must not be empty. Values larger
+/// than the client's `kv_max_value_size` setting are rejected with a
+/// [ClientError::ValueTooLargeError] before they're sent, since Consul would
+/// reject them anyway.
+///
 /// See [SetKeyRequest]
 #[instrument(skip(client, value, opts), err)]
 pub async fn set<'a>(
@@ -162,19 +228,50 @@ pub async fn set<'a>(
     value: &'a [u8],
     opts: Option<&'a mut SetKeyRequestBuilder>,
 ) -> Result<ApiResponse<bool>, ClientError> {
+    let key = normalize_key(key)?;
+    if key.is_empty() {
+        return Err(ClientError::InvalidKeyError {
+            key,
+            message: "key must not be empty".into(),
+        });
+    }
+    let max = client.settings().kv_max_value_size;
+    if value.len() > max {
+        return Err(ClientError::ValueTooLargeError {
+            key,
+            size: value.len(),
+            max,
+        });
+    }
+
     let mut t = SetKeyRequest::builder();
     let endpoint = opts
         .unwrap_or(&mut t)
         .key(key)
         .value(value)
         .build()
-        .unwrap();
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
+/// Encodes the given value using the given [Codec] and stores it at the given
+/// key.
+///
+/// See [set] and [SetKeyRequest]
+#[instrument(skip(client, value, opts), err)]
+pub async fn set_as<T: Serialize + ?Sized, C: Codec>(
+    client: &impl Client,
+    key: &str,
+    value: &T,
+    opts: Option<&mut SetKeyRequestBuilder>,
+) -> Result<ApiResponse<bool>, ClientError> {
+    let bytes = C::encode(value)?;
+    set(client, key, &bytes, opts).await
+}
+
 /// Serializes the given value into JSON and stores it at the given key.
 ///
-/// See [SetKeyRequest]
+/// See [set_as] and [SetKeyRequest]
 #[instrument(skip(client, value, opts), err)]
 pub async fn set_json<T: Serialize>(
     client: &impl Client,
@@ -182,14 +279,33 @@ pub async fn set_json<T: Serialize>(
     value: &T,
     opts: Option<&mut SetKeyRequestBuilder>,
 ) -> Result<ApiResponse<bool>, ClientError> {
-    let mut t = SetKeyRequest::builder();
-    let bytes =
-        serde_json::to_vec(value).map_err(|e| ClientError::JsonSerializeError { source: e })?;
-    let endpoint = opts
-        .unwrap_or(&mut t)
-        .key(key)
-        .value(bytes)
-        .build()
-        .unwrap();
-    api::exec_with_result(client, endpoint).await
+    set_as::<T, Json>(client, key, value, opts).await
+}
+
+/// Normalizes the given key into the form it's sent to Consul in.
+///
+/// Leading slashes are removed since Consul ignores them. Keys containing `.`
+/// or `..` segments or empty segments (`//`) are rejected with a
+/// [ClientError::InvalidKeyError], as they would be rewritten while building
+/// the request URL and silently address a different key. A trailing `/` is
+/// allowed for folder keys. Any other special characters are percent-encoded
+/// when the request URL is built.
+pub fn normalize_key(key: &str) -> Result<String, ClientError> {
+    let key = key.trim_start_matches('/');
+    let invalid = |message: &str| ClientError::InvalidKeyError {
+        key: key.to_string(),
+        message: message.to_string(),
+    };
+
+    let segments = key.strip_suffix('/').unwrap_or(key);
+    if !segments.is_empty() {
+        for segment in segments.split('/') {
+            match segment {
+                "" => return Err(invalid("key must not contain empty segments")),
+                "." | ".." => return Err(invalid("key must not contain '.' or '..' segments")),
+                _ => {}
+            }
+        }
+    }
+    Ok(key.to_string())
 }
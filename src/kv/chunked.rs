@@ -0,0 +1,413 @@
+//! Storage for values which are larger than Consul's limit on the size of a
+//! single value.
+//!
+//! A value is split into chunks which are stored under a folder named after
+//! the key, along with a [ChunkManifest] stored at the key itself which
+//! records the number of chunks and the SHA-256 checksum of the value:
+//!
+//! ```text
+//! certs/bundle                        = {"chunks":2,"chunk_size":262144,...}
+//! certs/bundle/<sha256>-262144/00000  = <first 256KiB>
+//! certs/bundle/<sha256>-262144/00001  = <remaining bytes>
+//! ```
+//!
+//! Chunks are written using transactions and the manifest is swapped in last
+//! using a check-and-set, so readers see either the previous value or the new
+//! one in full. Since chunks are stored under the checksum of their value and
+//! the chunk size, the chunks of the previous value remain readable until the
+//! new manifest is written, after which they're deleted. The folder under the key is reserved
+//! for chunks and is removed by [delete].
+//!
+//! Note that Consul also limits the size of a transaction (`txn_max_req_len`),
+//! so a large value is written using several transactions of up to
+//! [txn::MAX_REQUEST_SIZE] bytes each, while each chunk is also limited by the
+//! client's `kv_max_value_size` setting. A value whose chunks and manifest
+//! fit in a single transaction is written atomically, otherwise only the swap
+//! of the manifest is atomic: if writing fails part way through, [set] deletes the chunks it
+//! wrote on a best-effort basis, and any left behind (e.g. if the client
+//! stops part way through) remain until the key is deleted.
+use std::convert::TryInto;
+
+use derive_builder::Builder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    api::{
+        kv::requests::{
+            DeleteKeyRequest, DeleteKeyRequestBuilder, ReadKeyRequest, ReadKeyRequestBuilder,
+        },
+        txn::{
+            common::{KVTxnOp, KVTxnVerb, TxnOp},
+            requests::CreateTransactionRequest,
+        },
+        ApiResponse,
+    },
+    client::Client,
+    codec::Json,
+    error::ClientError,
+    kv::{self, normalize_key},
+    txn,
+};
+
+/// The default size of each chunk.
+pub const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+
+/// The number of times a read is retried when the value is replaced while
+/// its chunks are being read.
+const READ_ATTEMPTS: u32 = 3;
+
+/// Describes a value which has been split into chunks by [set].
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ChunkManifest {
+    /// The number of chunks
+    pub chunks: usize,
+    /// The maximum size of each chunk
+    pub chunk_size: usize,
+    /// The hex encoded SHA-256 checksum of the value
+    pub sha256: String,
+    /// The size of the value in bytes
+    pub size: usize,
+}
+
+impl ChunkManifest {
+    /// Returns the folder containing the chunks of the value with this
+    /// manifest stored at the given key.
+    ///
+    /// The folder is named after both the checksum and the chunk size, so
+    /// rewriting the same value with a different chunk size never shares
+    /// chunks with the previous manifest.
+    pub fn folder(&self, key: &str) -> String {
+        format!("{}/{}-{}/", key, self.sha256, self.chunk_size)
+    }
+
+    /// Returns the key of the chunk with the given index.
+    pub fn chunk_key(&self, key: &str, index: usize) -> String {
+        format!("{}{:05}", self.folder(key), index)
+    }
+}
+
+/// Options which modify how chunked values are read and written.
+#[derive(Builder, Clone, Debug, Default)]
+#[builder(setter(into, strip_option), default)]
+pub struct ChunkOptions {
+    /// The maximum size of each chunk, which defaults to
+    /// [DEFAULT_CHUNK_SIZE] and is limited so that each chunk fits in a
+    /// transaction and doesn't exceed the client's `kv_max_value_size` setting
+    pub chunk_size: Option<usize>,
+    /// The datacenter containing the value
+    pub dc: Option<String>,
+    /// The namespace containing the value
+    pub ns: Option<String>,
+}
+
+impl ChunkOptions {
+    /// Returns a default instance of [ChunkOptionsBuilder].
+    pub fn builder() -> ChunkOptionsBuilder {
+        ChunkOptionsBuilder::default()
+    }
+}
+
+/// Splits the given value into chunks and stores it at the given key,
+/// returning its manifest.
+///
+/// The manifest is written with a check-and-set against the manifest which
+/// was read before writing the chunks, so a value written concurrently fails
+/// the final transaction with a [ClientError::TransactionError] rather than
+/// being clobbered. When any of the transactions fail, the chunks which were
+/// already written are deleted again unless the manifest at the key has since
+/// been changed to refer to them.
+///
+/// The chunks and manifest are written in a single transaction when they fit
+/// in one, making the write atomic. Larger values are written using several
+/// transactions, in which case only the swap of the manifest is atomic. Once
+/// the manifest is written, failing to delete the chunks of the previous value
+/// is logged rather than returned, since the new value is complete.
+#[instrument(skip(client, value, opts), err)]
+pub async fn set(
+    client: &impl Client,
+    key: &str,
+    value: &[u8],
+    opts: Option<&mut ChunkOptionsBuilder>,
+) -> Result<ChunkManifest, ClientError> {
+    let opts = build(opts)?;
+    let key = chunked_key(key)?;
+    let mut manifest = ChunkManifest {
+        chunks: 0,
+        chunk_size: 0,
+        sha256: hex::encode(Sha256::digest(value)),
+        size: value.len(),
+    };
+
+    // Each chunk must fit in a transaction once it's base64 encoded, as well
+    // as within the limit on the size of a value
+    let max = txn::MAX_REQUEST_SIZE
+        .saturating_sub(txn::OP_OVERHEAD + manifest.chunk_key(&key, 0).len())
+        / 4
+        * 3;
+    let max = max.min(client.settings().kv_max_value_size);
+    manifest.chunk_size = opts.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE).min(max);
+    if manifest.chunk_size == 0 {
+        return Err(ClientError::BuilderError {
+            message: "chunk_size must be greater than 0".into(),
+        });
+    }
+    manifest.chunks = value.len().div_ceil(manifest.chunk_size);
+    let current = read_manifest(client, &key, &opts).await?;
+    let bytes =
+        serde_json::to_vec(&manifest).map_err(|e| ClientError::JsonSerializeError { source: e })?;
+
+    // Write the chunks first and swap in the manifest last
+    let mut ops = value
+        .chunks(manifest.chunk_size)
+        .enumerate()
+        .map(|(i, c)| {
+            let key = manifest.chunk_key(&key, i);
+            vec![(txn::op_size(&key, c), KVTxnOp::set(&key, c))]
+        })
+        .collect::<Vec<_>>();
+    let index = current.as_ref().map(|(i, _)| *i).unwrap_or(0);
+    ops.push(vec![(
+        txn::op_size(&key, &bytes),
+        KVTxnOp::cas(&key, &bytes, index),
+    )]);
+
+    for batch in txn::batches(txn::MAX_REQUEST_SIZE, ops) {
+        if let Err(e) = execute(client, &opts, batch).await {
+            debug!("Deleting the chunks written for {} after a failure", key);
+            if let Err(e) = discard(client, &key, &manifest, &opts).await {
+                warn!("Failed to delete the chunks written for {}: {}", key, e);
+            }
+            return Err(e);
+        }
+    }
+
+    if let Some((_, previous)) = current {
+        if previous.folder(&key) != manifest.folder(&key) {
+            debug!("Deleting the previous chunks of {}", key);
+            let mut req = delete_request(&opts);
+            if let Err(e) = kv::delete_tree(client, &previous.folder(&key), Some(&mut req)).await {
+                warn!("Failed to delete the previous chunks of {}: {}", key, e);
+            }
+        }
+    }
+
+    Ok(manifest)
+}
+
+/// Reads the chunked value at the given key, verifying it against its
+/// manifest.
+///
+/// Returns a [ClientError::NotFound] if the key doesn't exist and a
+/// [ClientError::InvalidChunkedValueError] if chunks are missing or the value
+/// doesn't match its checksum. If the value is replaced while its chunks are
+/// being read, the read is retried.
+#[instrument(skip(client, opts), err)]
+pub async fn read(
+    client: &impl Client,
+    key: &str,
+    opts: Option<&mut ChunkOptionsBuilder>,
+) -> Result<ApiResponse<Vec<u8>>, ClientError> {
+    let opts = build(opts)?;
+    let key = chunked_key(key)?;
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let (index, manifest) = match read_manifest(client, &key, &opts).await? {
+            Some(m) => m,
+            None => {
+                return Err(ClientError::NotFound {
+                    index: None,
+                    message: Some(format!("Key {} doesn't exist", key)),
+                })
+            }
+        };
+
+        let mut req = read_request(&opts);
+        req.recurse(true);
+        let res = match kv::read(client, &manifest.folder(&key), Some(&mut req)).await {
+            Ok(r) => r,
+            Err(e) if e.is_not_found() => ApiResponse::builder()
+                .response(Vec::new())
+                .build()
+                .map_err(ClientError::from_builder)?,
+            Err(e) => return Err(e),
+        };
+
+        let mut pairs = res.response.into_iter().collect::<Vec<_>>();
+        pairs.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        let complete = pairs.len() == manifest.chunks
+            && pairs
+                .iter()
+                .enumerate()
+                .all(|(i, p)| p.key == manifest.chunk_key(&key, i));
+        if !complete {
+            // The chunks of a replaced value are deleted once the new manifest
+            // is written, so check whether that happened before failing
+            let latest = read_manifest(client, &key, &opts).await?;
+            if attempt < READ_ATTEMPTS && latest.map(|(i, _)| i) != Some(index) {
+                debug!("Value at {} was replaced while reading, retrying", key);
+                continue;
+            }
+            return Err(invalid(&key, "chunks are missing"));
+        }
+
+        let mut value = Vec::with_capacity(manifest.size);
+        for pair in pairs {
+            if let Some(v) = pair.value {
+                let bytes: Vec<u8> = v.try_into()?;
+                value.extend(bytes);
+            }
+        }
+        if value.len() != manifest.size || hex::encode(Sha256::digest(&value)) != manifest.sha256 {
+            return Err(invalid(&key, "value doesn't match its checksum"));
+        }
+
+        return Ok(ApiResponse {
+            response: value,
+            age: res.age,
+            cache: res.cache,
+            content_hash: res.content_hash,
+            default_acl_policy: res.default_acl_policy,
+            index: res.index,
+            known_leader: res.known_leader,
+            last_contact: res.last_contact,
+            query_backend: res.query_backend,
+        });
+    }
+}
+
+/// Deletes the chunked value at the given key along with all of its chunks in
+/// a single transaction.
+///
+/// The manifest is deleted with a check-and-set, so a value written
+/// concurrently fails the transaction with a [ClientError::TransactionError].
+/// Deleting a key which doesn't exist succeeds.
+#[instrument(skip(client, opts), err)]
+pub async fn delete(
+    client: &impl Client,
+    key: &str,
+    opts: Option<&mut ChunkOptionsBuilder>,
+) -> Result<(), ClientError> {
+    let opts = build(opts)?;
+    let key = chunked_key(key)?;
+
+    let mut ops = Vec::new();
+    if let Some((index, _)) = read_manifest(client, &key, &opts).await? {
+        ops.push(KVTxnOp::delete_cas(&key, index));
+    }
+    ops.push(KVTxnOp::delete_tree(&format!("{}/", key)));
+    execute(client, &opts, ops).await
+}
+
+/// Deletes the chunks of the given manifest after they failed to be written.
+///
+/// The chunks are only deleted if the manifest at the key doesn't refer to
+/// them, which is checked in the same transaction in case another client
+/// writes the same value concurrently.
+async fn discard(
+    client: &impl Client,
+    key: &str,
+    manifest: &ChunkManifest,
+    opts: &ChunkOptions,
+) -> Result<(), ClientError> {
+    let check = match read_manifest(client, key, opts).await? {
+        Some((_, latest)) if latest.folder(key) == manifest.folder(key) => return Ok(()),
+        Some((index, _)) => KVTxnOp::check_index(key, index),
+        None => KVTxnOp {
+            verb: KVTxnVerb::CheckNotExists,
+            ..KVTxnOp::get(key)
+        },
+    };
+    let ops = vec![check, KVTxnOp::delete_tree(&manifest.folder(key))];
+    execute(client, opts, ops).await
+}
+
+/// Reads the manifest at the given key along with its modify index.
+async fn read_manifest(
+    client: &impl Client,
+    key: &str,
+    opts: &ChunkOptions,
+) -> Result<Option<(u64, ChunkManifest)>, ClientError> {
+    let mut req = read_request(opts);
+    match kv::read_as::<ChunkManifest, Json>(client, key, Some(&mut req)).await {
+        Ok(r) => Ok(Some((r.response.modify_index, r.response.value))),
+        Err(e) if e.is_not_found() => Ok(None),
+        Err(ClientError::JsonDeserializeError { .. }) => {
+            Err(invalid(key, "key doesn't contain a chunk manifest"))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn execute(
+    client: &impl Client,
+    opts: &ChunkOptions,
+    ops: Vec<KVTxnOp>,
+) -> Result<(), ClientError> {
+    let ops = ops
+        .into_iter()
+        .map(|op| {
+            TxnOp::from(KVTxnOp {
+                namespace: opts.ns.clone(),
+                ..op
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut req = CreateTransactionRequest::builder();
+    if let Some(dc) = &opts.dc {
+        req.dc(dc);
+    }
+    txn::execute(client, &ops, Some(&mut req)).await?;
+    Ok(())
+}
+
+fn build(opts: Option<&mut ChunkOptionsBuilder>) -> Result<ChunkOptions, ClientError> {
+    let mut t = ChunkOptions::builder();
+    opts.unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)
+}
+
+/// Normalizes the given key, which can't be empty or a folder since the
+/// chunks are stored under it.
+fn chunked_key(key: &str) -> Result<String, ClientError> {
+    let key = normalize_key(key)?;
+    if key.is_empty() || key.ends_with('/') {
+        return Err(ClientError::InvalidKeyError {
+            key,
+            message: "key must not be empty or end with '/'".into(),
+        });
+    }
+    Ok(key)
+}
+
+fn read_request(opts: &ChunkOptions) -> ReadKeyRequestBuilder {
+    let mut req = ReadKeyRequest::builder();
+    if let Some(dc) = &opts.dc {
+        req.dc(dc);
+    }
+    if let Some(ns) = &opts.ns {
+        req.ns(ns);
+    }
+    req
+}
+
+fn delete_request(opts: &ChunkOptions) -> DeleteKeyRequestBuilder {
+    let mut req = DeleteKeyRequest::builder();
+    if let Some(dc) = &opts.dc {
+        req.dc(dc);
+    }
+    if let Some(ns) = &opts.ns {
+        req.ns(ns);
+    }
+    req
+}
+
+fn invalid(key: &str, message: &str) -> ClientError {
+    ClientError::InvalidChunkedValueError {
+        key: key.to_string(),
+        message: message.to_string(),
+    }
+}
@@ -0,0 +1,376 @@
+use std::time::Duration;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    api::{
+        features::{Blocking, ConsistencyMode, Features},
+        kv::{
+            common::{GenericKVPair, KVPair},
+            requests::{
+                DeleteKeyRequest, ReadKeyRequest, ReadKeyRequestBuilder, ReadKeysRequest,
+                SetKeyRequest, SetKeyRequestBuilder,
+            },
+        },
+        ApiResponse, ApiResponseBuilder,
+    },
+    client::Client,
+    codec::Codec,
+    error::ClientError,
+    kv::{delete, keys, normalize_key, read, read_as, set, set_as},
+};
+
+/// The separator between the segments of a key.
+const SEPARATOR: char = '/';
+
+/// A handle to the keys under a prefix of the KV store.
+///
+/// The handle bundles a client with a prefix along with the datacenter,
+/// namespace and consistency mode used for every request made through it.
+/// Keys given to the handle are relative to its prefix and are validated
+/// before they're joined to it: a key can't start with `/` or contain empty
+/// segments (`//`) or `.` and `..` segments, which would otherwise silently
+/// address a different key. Violations are returned as a
+/// [ClientError::InvalidKeyError].
+///
+/// # Example
+/// ```should_panic
+/// # use consulrs::client::{ConsulClient, ConsulClientSettingsBuilder};
+/// use consulrs::kv::KvStore;
+///
+/// # let client = ConsulClient::new(
+/// #     ConsulClientSettingsBuilder::default()
+/// #         .address("https://127.0.0.1:8200")
+/// #         .build()
+/// #         .unwrap()
+/// # ).unwrap();
+/// # tokio_test::block_on(async {
+/// let app = KvStore::new(&client, "apps/web").unwrap().dc("dc1");
+/// let db = app.scoped("db").unwrap();
+///
+/// // Writes to apps/web/db/host
+/// db.put("host", b"10.0.0.1").await.unwrap();
+/// assert_eq!(app.list("").await.unwrap().response, vec!["db/host"]);
+/// # })
+/// ```
+#[derive(Debug)]
+pub struct KvStore<'a, C: Client> {
+    client: &'a C,
+    prefix: String,
+    dc: Option<String>,
+    ns: Option<String>,
+    mode: Option<ConsistencyMode>,
+}
+
+impl<'a, C: Client> Clone for KvStore<'a, C> {
+    fn clone(&self) -> Self {
+        KvStore {
+            client: self.client,
+            prefix: self.prefix.clone(),
+            dc: self.dc.clone(),
+            ns: self.ns.clone(),
+            mode: self.mode.clone(),
+        }
+    }
+}
+
+impl<'a, C: Client> KvStore<'a, C> {
+    /// Returns a new handle to the keys under the given prefix.
+    ///
+    /// The prefix is treated as a folder, so a trailing `/` is added if it's
+    /// missing. An empty prefix refers to the root of the KV store.
+    pub fn new(client: &'a C, prefix: &str) -> Result<Self, ClientError> {
+        Ok(KvStore {
+            client,
+            prefix: folder_path("", prefix)?,
+            dc: None,
+            ns: None,
+            mode: None,
+        })
+    }
+
+    /// Sets the datacenter used for all requests made through this handle.
+    pub fn dc(mut self, dc: impl Into<String>) -> Self {
+        self.dc = Some(dc.into());
+        self
+    }
+
+    /// Sets the namespace used for all requests made through this handle.
+    pub fn ns(mut self, ns: impl Into<String>) -> Self {
+        self.ns = Some(ns.into());
+        self
+    }
+
+    /// Sets the consistency mode used for all reads made through this handle.
+    pub fn consistency(mut self, mode: ConsistencyMode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Returns the prefix of this handle, including its trailing `/`.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Returns a new handle to the keys under the given folder relative to
+    /// this handle's prefix, inheriting its datacenter, namespace and
+    /// consistency mode.
+    pub fn scoped(&self, path: &str) -> Result<Self, ClientError> {
+        Ok(KvStore {
+            prefix: folder_path(&self.prefix, path)?,
+            ..self.clone()
+        })
+    }
+
+    /// Returns the full key for the given key relative to this handle's
+    /// prefix.
+    pub fn key(&self, key: &str) -> Result<String, ClientError> {
+        if key.is_empty() {
+            return Err(invalid_key(key, "key must not be empty"));
+        }
+        validate(key)?;
+        Ok(format!("{}{}", self.prefix, key))
+    }
+
+    /// Reads the given key, returning `None` if it doesn't exist.
+    ///
+    /// Note that the `key` of the returned pair is the full key including
+    /// this handle's prefix.
+    ///
+    /// See [ReadKeyRequest]
+    #[instrument(skip(self), fields(prefix = %self.prefix), err)]
+    pub async fn get(&self, key: &str) -> Result<ApiResponse<Option<KVPair>>, ClientError> {
+        let key = self.key(key)?;
+        let mut req = self.read_request(None);
+        let res = match read(self.client, &key, Some(&mut req)).await {
+            Ok(r) => r,
+            Err(e) if e.is_not_found() => empty()?,
+            Err(e) => return Err(e),
+        };
+        Ok(first(res))
+    }
+
+    /// Reads the given key and decodes its value using the given [Codec],
+    /// returning `None` if it doesn't exist.
+    ///
+    /// See [ReadKeyRequest]
+    #[instrument(skip(self), fields(prefix = %self.prefix), err)]
+    pub async fn get_as<T: DeserializeOwned, D: Codec>(
+        &self,
+        key: &str,
+    ) -> Result<ApiResponse<Option<GenericKVPair<T>>>, ClientError> {
+        let key = self.key(key)?;
+        let mut req = self.read_request(None);
+        match read_as::<T, D>(self.client, &key, Some(&mut req)).await {
+            Ok(r) => Ok(ApiResponse {
+                response: Some(r.response),
+                age: r.age,
+                cache: r.cache,
+                content_hash: r.content_hash,
+                default_acl_policy: r.default_acl_policy,
+                index: r.index,
+                known_leader: r.known_leader,
+                last_contact: r.last_contact,
+                query_backend: r.query_backend,
+            }),
+            Err(e) if e.is_not_found() => empty(),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Stores the given value at the given key.
+    ///
+    /// See [SetKeyRequest]
+    #[instrument(skip(self, value), fields(prefix = %self.prefix), err)]
+    pub async fn put(&self, key: &str, value: &[u8]) -> Result<ApiResponse<bool>, ClientError> {
+        let key = self.key(key)?;
+        let mut req = self.set_request();
+        set(self.client, &key, value, Some(&mut req)).await
+    }
+
+    /// Encodes the given value using the given [Codec] and stores it at the
+    /// given key.
+    ///
+    /// See [SetKeyRequest]
+    #[instrument(skip(self, value), fields(prefix = %self.prefix), err)]
+    pub async fn put_as<T: Serialize + ?Sized, D: Codec>(
+        &self,
+        key: &str,
+        value: &T,
+    ) -> Result<ApiResponse<bool>, ClientError> {
+        let key = self.key(key)?;
+        let mut req = self.set_request();
+        set_as::<T, D>(self.client, &key, value, Some(&mut req)).await
+    }
+
+    /// Deletes the given key.
+    ///
+    /// See [DeleteKeyRequest]
+    #[instrument(skip(self), fields(prefix = %self.prefix), err)]
+    pub async fn delete(&self, key: &str) -> Result<ApiResponse<bool>, ClientError> {
+        let key = self.key(key)?;
+        let mut req = DeleteKeyRequest::builder();
+        if let Some(dc) = &self.dc {
+            req.dc(dc);
+        }
+        if let Some(ns) = &self.ns {
+            req.ns(ns);
+        }
+        delete(self.client, &key, Some(&mut req)).await
+    }
+
+    /// Lists all keys under the given path, relative to this handle's prefix.
+    ///
+    /// An empty path lists every key under the prefix. An empty list is
+    /// returned if no keys exist.
+    ///
+    /// See [ReadKeysRequest]
+    #[instrument(skip(self), fields(prefix = %self.prefix), err)]
+    pub async fn list(&self, path: &str) -> Result<ApiResponse<Vec<String>>, ClientError> {
+        validate(path)?;
+        let mut req = ReadKeysRequest::builder();
+        if let Some(dc) = &self.dc {
+            req.dc(dc);
+        }
+        if let Some(ns) = &self.ns {
+            req.ns(ns);
+        }
+        if self.mode.is_some() {
+            req.features(Features {
+                mode: self.mode.clone(),
+                ..Default::default()
+            });
+        }
+        let path = format!("{}{}", self.prefix, path);
+        let res = match keys(self.client, &path, Some(&mut req)).await {
+            Ok(r) => r,
+            Err(e) if e.is_not_found() => empty()?,
+            Err(e) => return Err(e),
+        };
+
+        Ok(ApiResponse {
+            response: res
+                .response
+                .into_iter()
+                .filter_map(|k| k.strip_prefix(self.prefix.as_str()).map(String::from))
+                .collect(),
+            age: res.age,
+            cache: res.cache,
+            content_hash: res.content_hash,
+            default_acl_policy: res.default_acl_policy,
+            index: res.index,
+            known_leader: res.known_leader,
+            last_contact: res.last_contact,
+            query_backend: res.query_backend,
+        })
+    }
+
+    /// Waits for the given key to change after the given index, returning its
+    /// current value or `None` if it doesn't exist.
+    ///
+    /// This performs a single [blocking query][Blocking] which returns once
+    /// the key is modified or `wait` elapses. The `index` of the response is
+    /// passed to the next call to continue watching the key, while an index
+    /// of 0 returns immediately.
+    ///
+    /// See [ReadKeyRequest]
+    #[instrument(skip(self), fields(prefix = %self.prefix), err)]
+    pub async fn watch(
+        &self,
+        key: &str,
+        index: u64,
+        wait: Option<Duration>,
+    ) -> Result<ApiResponse<Option<KVPair>>, ClientError> {
+        let key = self.key(key)?;
+        let mut req = self.read_request(Some(Blocking {
+            index,
+            wait: wait.map(|w| w.into()),
+        }));
+        let res = match read(self.client, &key, Some(&mut req)).await {
+            Ok(r) => r,
+            // A missing key still reports the index to block on next
+            Err(ClientError::NotFound { index, .. }) => ApiResponse { index, ..empty()? },
+            Err(e) => return Err(e),
+        };
+        Ok(first(res))
+    }
+
+    fn read_request(&self, blocking: Option<Blocking>) -> ReadKeyRequestBuilder {
+        let mut req = ReadKeyRequest::builder();
+        if let Some(dc) = &self.dc {
+            req.dc(dc);
+        }
+        if let Some(ns) = &self.ns {
+            req.ns(ns);
+        }
+        if self.mode.is_some() || blocking.is_some() {
+            req.features(Features {
+                blocking,
+                mode: self.mode.clone(),
+                ..Default::default()
+            });
+        }
+        req
+    }
+
+    fn set_request(&self) -> SetKeyRequestBuilder {
+        let mut req = SetKeyRequest::builder();
+        if let Some(dc) = &self.dc {
+            req.dc(dc);
+        }
+        if let Some(ns) = &self.ns {
+            req.ns(ns);
+        }
+        req
+    }
+}
+
+/// Validates that the given relative path doesn't start with `/` and is
+/// otherwise a valid key, as checked by [normalize_key].
+fn validate(path: &str) -> Result<(), ClientError> {
+    if path.starts_with(SEPARATOR) {
+        return Err(invalid_key(path, "key must not start with '/'"));
+    }
+    normalize_key(path).map(|_| ())
+}
+
+/// Joins the given path to the prefix as a folder ending in `/`.
+fn folder_path(prefix: &str, path: &str) -> Result<String, ClientError> {
+    validate(path)?;
+    if path.is_empty() || path.ends_with(SEPARATOR) {
+        Ok(format!("{}{}", prefix, path))
+    } else {
+        Ok(format!("{}{}{}", prefix, path, SEPARATOR))
+    }
+}
+
+fn invalid_key(key: &str, message: &str) -> ClientError {
+    ClientError::InvalidKeyError {
+        key: key.to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn empty<T>() -> Result<ApiResponse<T>, ClientError>
+where
+    T: Default,
+{
+    ApiResponseBuilder::default()
+        .response(T::default())
+        .build()
+        .map_err(ClientError::from_builder)
+}
+
+fn first(res: ApiResponse<Vec<KVPair>>) -> ApiResponse<Option<KVPair>> {
+    ApiResponse {
+        response: res.response.into_iter().next(),
+        age: res.age,
+        cache: res.cache,
+        content_hash: res.content_hash,
+        default_acl_policy: res.default_acl_policy,
+        index: res.index,
+        known_leader: res.known_leader,
+        last_contact: res.last_contact,
+        query_backend: res.query_backend,
+    }
+}
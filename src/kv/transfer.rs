@@ -0,0 +1,273 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    convert::TryInto,
+};
+
+use derive_builder::Builder;
+
+use crate::{
+    api::{
+        self,
+        kv::{
+            common::{KVExportEntry, KVPair},
+            requests::{ReadKeyRequest, ReadKeyRequestBuilder},
+        },
+        txn::{
+            common::{KVTxnOp, TxnOp},
+            requests::CreateTransactionRequest,
+        },
+        ApiResponse, ApiResponseBuilder,
+    },
+    client::Client,
+    error::ClientError,
+    kv::{normalize_key, read},
+    txn,
+};
+
+/// Options which modify how entries are written by [import].
+#[derive(Builder, Clone, Debug, Default)]
+#[builder(setter(into, strip_option), default)]
+pub struct ImportOptions {
+    /// The datacenter to import into
+    pub dc: Option<String>,
+    /// Computes the changes without writing them
+    pub dry_run: bool,
+    /// The namespace to import into
+    pub ns: Option<String>,
+    /// Deletes existing keys under this prefix which aren't being imported
+    pub prune: Option<String>,
+}
+
+impl ImportOptions {
+    /// Returns a default instance of [ImportOptionsBuilder].
+    pub fn builder() -> ImportOptionsBuilder {
+        ImportOptionsBuilder::default()
+    }
+}
+
+/// The changes made, or which would be made in a dry run, by [import].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportPlan {
+    /// Keys which don't currently exist
+    pub added: Vec<String>,
+    /// Keys whose value or flags differ from the current contents
+    pub changed: Vec<String>,
+    /// Keys which are deleted because they're not being imported, which is
+    /// only done when `prune` is set
+    pub deleted: Vec<String>,
+    /// Keys which already match the entry being imported
+    pub unchanged: Vec<String>,
+}
+
+impl ImportPlan {
+    /// Returns whether importing makes no changes.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.deleted.is_empty()
+    }
+}
+
+/// Exports all keys starting with the given prefix in the format used by
+/// `consul kv export`.
+///
+/// Unlike [read], an empty list is returned if no keys exist under the prefix.
+///
+/// See [ReadKeyRequest]
+#[instrument(skip(client, opts), err)]
+pub async fn export(
+    client: &impl Client,
+    prefix: &str,
+    opts: Option<&mut ReadKeyRequestBuilder>,
+) -> Result<ApiResponse<Vec<KVExportEntry>>, ClientError> {
+    let mut t = ReadKeyRequest::builder();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .key(normalize_key(prefix)?)
+        .recurse(true)
+        .build()
+        .map_err(ClientError::from_builder)?;
+    let res = match api::exec_with_result(client, endpoint).await {
+        Ok(r) => r,
+        Err(e) if e.is_not_found() => ApiResponseBuilder::default()
+            .response(Vec::new())
+            .build()
+            .map_err(ClientError::from_builder)?,
+        Err(e) => return Err(e),
+    };
+
+    Ok(ApiResponse {
+        response: res.response.into_iter().map(KVExportEntry::from).collect(),
+        age: res.age,
+        cache: res.cache,
+        content_hash: res.content_hash,
+        default_acl_policy: res.default_acl_policy,
+        index: res.index,
+        known_leader: res.known_leader,
+        last_contact: res.last_contact,
+        query_backend: res.query_backend,
+    })
+}
+
+/// Imports entries in the format used by `consul kv import`, returning the
+/// changes which were made.
+///
+/// The current contents of the KV store are compared against the entries so
+/// that only keys which are new or have changed are written. When `prune` is
+/// set, existing keys under that prefix which aren't being imported are
+/// deleted. With `dry_run` set the changes are returned without being made.
+/// Keys are only reported as deleted, in a dry run or otherwise, when `prune`
+/// is set.
+///
+/// Changes are written using transactions of up to [txn::MAX_OPERATIONS]
+/// operations each, which are also limited to [txn::MAX_REQUEST_SIZE] bytes
+/// to stay under Consul's limit on the size of a transaction
+/// (`txn_max_req_len`). Every write is checked against the modify
+/// index read when computing the changes, so a key modified concurrently fails
+/// the transaction it's part of with a [ClientError::TransactionError] rather
+/// than being overwritten. Transactions which were applied before a failure
+/// aren't rolled back.
+///
+/// See [CreateTransactionRequest]
+#[instrument(skip(client, entries, opts), err)]
+pub async fn import(
+    client: &impl Client,
+    entries: &[KVExportEntry],
+    opts: Option<&mut ImportOptionsBuilder>,
+) -> Result<ImportPlan, ClientError> {
+    let mut t = ImportOptions::builder();
+    let opts = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
+
+    let keys = entries
+        .iter()
+        .map(|e| normalize_key(&e.key))
+        .collect::<Result<Vec<_>, _>>()?;
+    let prune = opts.prune.as_deref().map(normalize_key).transpose()?;
+
+    let mut req = ReadKeyRequest::builder();
+    req.recurse(true);
+    if let Some(dc) = &opts.dc {
+        req.dc(dc);
+    }
+    if let Some(ns) = &opts.ns {
+        req.ns(ns);
+    }
+    let mut current: HashMap<String, KVPair> = HashMap::new();
+    for prefix in read_prefixes(keys.iter().map(String::as_str).chain(prune.as_deref())) {
+        match read(client, prefix, Some(&mut req.clone())).await {
+            Ok(r) => current.extend(r.response.into_iter().map(|p| (p.key.clone(), p))),
+            Err(e) if e.is_not_found() => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let mut plan = ImportPlan::default();
+    let mut ops = Vec::new();
+    for (key, entry) in keys.iter().zip(entries.iter()) {
+        let value: Vec<u8> = entry.value.clone().try_into()?;
+        let index = match current.get(key) {
+            Some(p) => {
+                let existing: Vec<u8> = match p.value.clone() {
+                    Some(v) => v.try_into()?,
+                    None => Vec::new(),
+                };
+                if p.flags == entry.flags && existing == value {
+                    plan.unchanged.push(key.clone());
+                    continue;
+                }
+                plan.changed.push(key.clone());
+                p.modify_index
+            }
+            None => {
+                plan.added.push(key.clone());
+                0
+            }
+        };
+        let op = KVTxnOp {
+            flags: Some(entry.flags),
+            namespace: opts.ns.clone(),
+            ..KVTxnOp::cas(key, &value, index)
+        };
+        ops.push(vec![(txn::op_size(key, &value), op)]);
+    }
+
+    if let Some(prefix) = &prune {
+        let imported = keys.iter().map(String::as_str).collect::<HashSet<_>>();
+        let mut deleted = current
+            .values()
+            .filter(|p| p.key.starts_with(prefix.as_str()) && !imported.contains(p.key.as_str()))
+            .collect::<Vec<_>>();
+        deleted.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        for pair in deleted {
+            plan.deleted.push(pair.key.clone());
+            let op = KVTxnOp {
+                namespace: opts.ns.clone(),
+                ..KVTxnOp::delete_cas(&pair.key, pair.modify_index)
+            };
+            ops.push(vec![(txn::op_size(&pair.key, &[]), op)]);
+        }
+    }
+
+    if opts.dry_run {
+        return Ok(plan);
+    }
+    for batch in txn::batches(txn::MAX_REQUEST_SIZE, ops) {
+        let batch = batch.into_iter().map(TxnOp::from).collect::<Vec<_>>();
+        let mut req = CreateTransactionRequest::builder();
+        if let Some(dc) = &opts.dc {
+            req.dc(dc);
+        }
+        txn::execute(client, &batch, Some(&mut req)).await?;
+    }
+    debug!(
+        "Imported {} added, {} changed and {} deleted keys",
+        plan.added.len(),
+        plan.changed.len(),
+        plan.deleted.len()
+    );
+
+    Ok(plan)
+}
+
+/// Returns the prefixes to read to find the current contents of the given
+/// keys.
+///
+/// Keys sharing a prefix are read using a single request. Otherwise they're
+/// read per top-level folder so that importing unrelated keys doesn't read the
+/// entire KV store.
+fn read_prefixes<'a>(keys: impl Iterator<Item = &'a str> + Clone) -> BTreeSet<&'a str> {
+    let prefix = common_prefix(keys.clone());
+    if !prefix.is_empty() {
+        return std::iter::once(prefix).collect();
+    }
+    let prefixes = keys
+        .map(|k| match k.find('/') {
+            Some(i) => &k[..=i],
+            None => k,
+        })
+        .collect::<BTreeSet<_>>();
+    // Pruning the root of the KV store requires reading all of it anyway
+    if prefixes.contains("") {
+        return std::iter::once("").collect();
+    }
+    prefixes
+}
+
+/// Returns the longest prefix shared by all of the given keys.
+fn common_prefix<'a>(mut keys: impl Iterator<Item = &'a str>) -> &'a str {
+    let mut prefix = match keys.next() {
+        Some(k) => k,
+        None => return "",
+    };
+    for key in keys {
+        let len = prefix
+            .char_indices()
+            .zip(key.chars())
+            .find(|((_, a), b)| a != b)
+            .map(|((i, _), _)| i)
+            .unwrap_or_else(|| prefix.len().min(key.len()));
+        prefix = &prefix[..len];
+    }
+    prefix
+}
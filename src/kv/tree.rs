@@ -0,0 +1,416 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryInto,
+};
+
+use derive_builder::Builder;
+
+use crate::{
+    api::{
+        self,
+        kv::{
+            common::KVPair,
+            requests::{
+                DeleteKeyRequest, DeleteKeyRequestBuilder, ReadKeyRequest, ReadKeyRequestBuilder,
+                ReadKeysRequest, ReadKeysRequestBuilder,
+            },
+        },
+        txn::{
+            common::{KVTxnOp, TxnOp},
+            requests::CreateTransactionRequest,
+        },
+        ApiResponse, ApiResponseBuilder,
+    },
+    client::Client,
+    error::ClientError,
+    kv::{normalize_key, read},
+    txn,
+};
+
+/// The separator between the segments of a key.
+const SEPARATOR: char = '/';
+
+/// A folder in a tree of keys returned by [tree].
+///
+/// Keys are split into segments on `/`, where every segment except the last
+/// is a folder. Consul also allows a key ending in `/` to be created
+/// explicitly, which is stored as the `entry` of its folder.
+#[derive(Clone, Debug, Default)]
+pub struct KVFolder {
+    /// The full path of the folder, including its trailing `/`
+    pub path: String,
+    /// The key with the same path as this folder, if one exists
+    pub entry: Option<KVPair>,
+    /// The sub-folders of this folder, keyed by their name
+    pub folders: BTreeMap<String, KVFolder>,
+    /// The keys in this folder, keyed by their name
+    pub keys: BTreeMap<String, KVPair>,
+}
+
+impl KVFolder {
+    /// Returns the key at the given path relative to this folder.
+    pub fn get(&self, path: &str) -> Option<&KVPair> {
+        match path.rsplit_once(SEPARATOR) {
+            Some((folder, name)) => self.folder(folder)?.keys.get(name),
+            None => self.keys.get(path),
+        }
+    }
+
+    /// Returns the sub-folder at the given path relative to this folder. A
+    /// trailing `/` is optional.
+    pub fn folder(&self, path: &str) -> Option<&KVFolder> {
+        path.trim_end_matches(SEPARATOR)
+            .split(SEPARATOR)
+            .filter(|s| !s.is_empty())
+            .try_fold(self, |f, name| f.folders.get(name))
+    }
+
+    /// Returns whether the folder contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the total number of keys in this folder and its sub-folders,
+    /// including the entries of folders.
+    pub fn len(&self) -> usize {
+        self.entry.iter().count()
+            + self.keys.len()
+            + self.folders.values().map(KVFolder::len).sum::<usize>()
+    }
+
+    /// Returns all keys in this folder and its sub-folders in sorted order.
+    pub fn pairs(&self) -> Vec<&KVPair> {
+        let mut pairs = self.entry.iter().collect::<Vec<_>>();
+        for folder in self.folders.values() {
+            pairs.extend(folder.pairs());
+        }
+        pairs.extend(self.keys.values());
+        pairs.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        pairs
+    }
+
+    fn insert(&mut self, relative: &str, pair: KVPair) {
+        match relative.split_once(SEPARATOR) {
+            Some((name, rest)) => {
+                let path = format!("{}{}{}", self.path, name, SEPARATOR);
+                let folder = self
+                    .folders
+                    .entry(name.to_string())
+                    .or_insert_with(|| KVFolder {
+                        path,
+                        ..Default::default()
+                    });
+                match rest.is_empty() {
+                    true => folder.entry = Some(pair),
+                    false => folder.insert(rest, pair),
+                }
+            }
+            None => {
+                self.keys.insert(relative.to_string(), pair);
+            }
+        }
+    }
+}
+
+/// Options which modify how keys are written by [copy_tree] and [move_tree].
+#[derive(Builder, Clone, Debug, Default)]
+#[builder(setter(into, strip_option), default)]
+pub struct TreeOptions {
+    /// The datacenter containing the keys
+    pub dc: Option<String>,
+    /// The namespace containing the keys
+    pub ns: Option<String>,
+}
+
+impl TreeOptions {
+    /// Returns a default instance of [TreeOptionsBuilder].
+    pub fn builder() -> TreeOptionsBuilder {
+        TreeOptionsBuilder::default()
+    }
+}
+
+/// Reads all keys under the given folder into a [KVFolder].
+///
+/// The prefix is treated as a folder, so a trailing `/` is added if it's
+/// missing. An empty folder is returned if no keys exist under it.
+///
+/// See [ReadKeyRequest]
+#[instrument(skip(client, opts), err)]
+pub async fn tree(
+    client: &impl Client,
+    prefix: &str,
+    opts: Option<&mut ReadKeyRequestBuilder>,
+) -> Result<ApiResponse<KVFolder>, ClientError> {
+    let prefix = folder_path(prefix)?;
+    let mut t = ReadKeyRequest::builder();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .key(prefix.as_str())
+        .recurse(true)
+        .build()
+        .map_err(ClientError::from_builder)?;
+    let res = match api::exec_with_result(client, endpoint).await {
+        Ok(r) => r,
+        Err(e) if e.is_not_found() => ApiResponseBuilder::default()
+            .response(Vec::new())
+            .build()
+            .map_err(ClientError::from_builder)?,
+        Err(e) => return Err(e),
+    };
+
+    let mut root = KVFolder {
+        path: prefix.clone(),
+        ..Default::default()
+    };
+    for pair in res.response {
+        match pair.key.strip_prefix(prefix.as_str()) {
+            Some("") => root.entry = Some(pair),
+            Some(relative) => {
+                let relative = relative.to_string();
+                root.insert(&relative, pair)
+            }
+            None => warn!("Ignoring key {} outside of {}", pair.key, prefix),
+        }
+    }
+
+    Ok(ApiResponse {
+        response: root,
+        age: res.age,
+        cache: res.cache,
+        content_hash: res.content_hash,
+        default_acl_policy: res.default_acl_policy,
+        index: res.index,
+        known_leader: res.known_leader,
+        last_contact: res.last_contact,
+        query_backend: res.query_backend,
+    })
+}
+
+/// Lists the paths of the folders directly under the given folder.
+///
+/// The prefix is treated as a folder, so a trailing `/` is added if it's
+/// missing. An empty list is returned if no keys exist under it.
+///
+/// See [ReadKeysRequest]
+#[instrument(skip(client, opts), err)]
+pub async fn list_folders(
+    client: &impl Client,
+    prefix: &str,
+    opts: Option<&mut ReadKeysRequestBuilder>,
+) -> Result<ApiResponse<Vec<String>>, ClientError> {
+    let prefix = folder_path(prefix)?;
+    let mut t = ReadKeysRequest::builder();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .key(prefix.as_str())
+        .separator(SEPARATOR.to_string())
+        .build()
+        .map_err(ClientError::from_builder)?;
+    let res = match api::exec_with_result(client, endpoint).await {
+        Ok(r) => r,
+        Err(e) if e.is_not_found() => ApiResponseBuilder::default()
+            .response(Vec::new())
+            .build()
+            .map_err(ClientError::from_builder)?,
+        Err(e) => return Err(e),
+    };
+
+    Ok(ApiResponse {
+        response: res
+            .response
+            .into_iter()
+            .filter(|k| k.ends_with(SEPARATOR) && *k != prefix)
+            .collect(),
+        age: res.age,
+        cache: res.cache,
+        content_hash: res.content_hash,
+        default_acl_policy: res.default_acl_policy,
+        index: res.index,
+        known_leader: res.known_leader,
+        last_contact: res.last_contact,
+        query_backend: res.query_backend,
+    })
+}
+
+/// Deletes the given folder and all keys under it.
+///
+/// The prefix is treated as a folder, so a trailing `/` is added if it's
+/// missing. This prevents deleting `app/` from also deleting keys under
+/// `application/`. An empty prefix is rejected with a
+/// [ClientError::InvalidKeyError] rather than deleting every key in the store.
+///
+/// See [DeleteKeyRequest]
+#[instrument(skip(client, opts), err)]
+pub async fn delete_tree(
+    client: &impl Client,
+    prefix: &str,
+    opts: Option<&mut DeleteKeyRequestBuilder>,
+) -> Result<ApiResponse<bool>, ClientError> {
+    let mut t = DeleteKeyRequest::builder();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .key(subfolder_path(prefix)?)
+        .recurse(true)
+        .build()
+        .map_err(ClientError::from_builder)?;
+    api::exec_with_result(client, endpoint).await
+}
+
+/// Copies all keys under the `src` folder to the `dst` folder, returning the
+/// keys which were written.
+///
+/// The folders must not overlap, so copying to the same folder, a sub-folder
+/// of `src` or a parent of `src` fails with a [ClientError::InvalidKeyError].
+///
+/// Values and flags are preserved while sessions aren't. Existing keys at the
+/// destination are overwritten. Every write is checked against the modify
+/// index of the destination key at the time it was read, so a key written
+/// concurrently fails the transaction with a
+/// [ClientError::TransactionError] rather than being clobbered.
+///
+/// Keys are written using transactions of up to [txn::MAX_OPERATIONS]
+/// operations each, which are also limited to [txn::MAX_REQUEST_SIZE] bytes,
+/// so copying a large tree isn't atomic as a whole.
+#[instrument(skip(client, opts), err)]
+pub async fn copy_tree(
+    client: &impl Client,
+    src: &str,
+    dst: &str,
+    opts: Option<&mut TreeOptionsBuilder>,
+) -> Result<Vec<String>, ClientError> {
+    transfer_tree(client, src, dst, false, opts).await
+}
+
+/// Moves all keys under the `src` folder to the `dst` folder, returning the
+/// keys which were written.
+///
+/// This behaves like [copy_tree] except that each source key is deleted in
+/// the same transaction it's copied in, provided it hasn't been modified since
+/// it was read. Each key is therefore moved atomically, although moving a
+/// large tree isn't atomic as a whole. An empty `src` is rejected with a
+/// [ClientError::InvalidKeyError] rather than moving every key in the store.
+#[instrument(skip(client, opts), err)]
+pub async fn move_tree(
+    client: &impl Client,
+    src: &str,
+    dst: &str,
+    opts: Option<&mut TreeOptionsBuilder>,
+) -> Result<Vec<String>, ClientError> {
+    transfer_tree(client, src, dst, true, opts).await
+}
+
+async fn transfer_tree(
+    client: &impl Client,
+    src: &str,
+    dst: &str,
+    delete: bool,
+    opts: Option<&mut TreeOptionsBuilder>,
+) -> Result<Vec<String>, ClientError> {
+    let mut t = TreeOptions::builder();
+    let opts = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
+    let src = match delete {
+        true => subfolder_path(src)?,
+        false => folder_path(src)?,
+    };
+    let dst = folder_path(dst)?;
+    // Writing into an overlapping folder would copy keys into themselves
+    if dst.starts_with(&src) || src.starts_with(&dst) {
+        return Err(ClientError::InvalidKeyError {
+            key: dst,
+            message: format!("destination must not overlap with the source {}", src),
+        });
+    }
+
+    let read_tree = |prefix: String| {
+        let mut req = ReadKeyRequest::builder();
+        req.recurse(true);
+        if let Some(dc) = &opts.dc {
+            req.dc(dc);
+        }
+        if let Some(ns) = &opts.ns {
+            req.ns(ns);
+        }
+        async move {
+            match read(client, &prefix, Some(&mut req)).await {
+                Ok(r) => Ok(r.response),
+                Err(e) if e.is_not_found() => Ok(Vec::new()),
+                Err(e) => Err(e),
+            }
+        }
+    };
+    let pairs = read_tree(src.clone()).await?;
+    let existing: HashMap<String, u64> = read_tree(dst.clone())
+        .await?
+        .into_iter()
+        .map(|p| (p.key, p.modify_index))
+        .collect();
+
+    // Keep the operations for each key in the same transaction
+    let mut groups = Vec::with_capacity(pairs.len());
+    let mut written = Vec::with_capacity(pairs.len());
+    for pair in pairs.iter() {
+        let key = format!("{}{}", dst, &pair.key[src.len()..]);
+        let value: Vec<u8> = match pair.value.clone() {
+            Some(v) => v.try_into()?,
+            None => Vec::new(),
+        };
+        let index = existing.get(&key).copied().unwrap_or(0);
+        let mut group = vec![(
+            txn::op_size(&key, &value),
+            KVTxnOp {
+                flags: Some(pair.flags),
+                namespace: opts.ns.clone(),
+                ..KVTxnOp::cas(&key, &value, index)
+            },
+        )];
+        if delete {
+            group.push((
+                txn::op_size(&pair.key, &[]),
+                KVTxnOp {
+                    namespace: opts.ns.clone(),
+                    ..KVTxnOp::delete_cas(&pair.key, pair.modify_index)
+                },
+            ));
+        }
+        groups.push(group);
+        written.push(key);
+    }
+
+    for batch in txn::batches(txn::MAX_REQUEST_SIZE, groups) {
+        let ops = batch.into_iter().map(TxnOp::from).collect::<Vec<_>>();
+        let mut req = CreateTransactionRequest::builder();
+        if let Some(dc) = &opts.dc {
+            req.dc(dc);
+        }
+        txn::execute(client, &ops, Some(&mut req)).await?;
+    }
+
+    Ok(written)
+}
+
+/// Normalizes the given prefix and returns it as a folder path ending in `/`.
+/// An empty prefix refers to the root and is returned as-is.
+pub(crate) fn folder_path(prefix: &str) -> Result<String, ClientError> {
+    let prefix = normalize_key(prefix)?;
+    if prefix.is_empty() || prefix.ends_with(SEPARATOR) {
+        Ok(prefix)
+    } else {
+        Ok(format!("{}{}", prefix, SEPARATOR))
+    }
+}
+
+/// Normalizes the given prefix like [folder_path], but rejects an empty prefix
+/// so that destructive operations can't be applied to the root by accident.
+fn subfolder_path(prefix: &str) -> Result<String, ClientError> {
+    let path = folder_path(prefix)?;
+    if path.is_empty() {
+        return Err(ClientError::InvalidKeyError {
+            key: prefix.to_string(),
+            message: "prefix must not refer to the root of the store".to_string(),
+        });
+    }
+    Ok(path)
+}
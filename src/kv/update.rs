@@ -0,0 +1,227 @@
+use std::convert::TryInto;
+
+use derive_builder::Builder;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    api::{
+        kv::{
+            common::{GenericKVPair, KVPair},
+            requests::{ReadKeyRequest, SetKeyRequest},
+        },
+        ApiResponse,
+    },
+    client::{Client, RetryPolicy},
+    error::ClientError,
+    kv::{read, set},
+};
+
+/// Options which modify how [update] and [update_json] read and write a key.
+#[derive(Builder, Clone, Debug)]
+#[builder(setter(into, strip_option))]
+pub struct UpdateOptions {
+    /// The datacenter containing the key
+    #[builder(default)]
+    pub dc: Option<String>,
+    /// The namespace containing the key
+    #[builder(default)]
+    pub ns: Option<String>,
+    /// The policy which limits the number of attempts made when the key is
+    /// modified concurrently and determines the backoff between them
+    #[builder(default = "RetryPolicy::builder().max_attempts(10u32).build().unwrap()")]
+    pub retry: RetryPolicy,
+}
+
+impl Default for UpdateOptions {
+    fn default() -> Self {
+        UpdateOptions::builder().build().unwrap()
+    }
+}
+
+impl UpdateOptions {
+    /// Returns a default instance of [UpdateOptionsBuilder].
+    pub fn builder() -> UpdateOptionsBuilder {
+        UpdateOptionsBuilder::default()
+    }
+}
+
+/// Atomically updates the value at the given key, returning the value which
+/// was written.
+///
+/// The current value is read and passed to `f`, or `None` if the key doesn't
+/// exist, and the value it returns is written using a check-and-set against
+/// the modify index which was read. If the key is modified concurrently the
+/// process is repeated, backing off between attempts according to the `retry`
+/// policy. A missing key is created with a check-and-set index of 0, so a key
+/// created concurrently is also detected. The flags of an existing key are
+/// preserved.
+///
+/// Once `max_attempts` is reached a [ClientError::RetriesExhaustedError]
+/// wrapping a [ClientError::CasConflict] is returned. Note that `f` is called
+/// once per attempt.
+///
+/// See [SetKeyRequest]
+#[instrument(skip(client, f, opts), err)]
+pub async fn update<T, F>(
+    client: &impl Client,
+    key: &str,
+    mut f: F,
+    opts: Option<&mut UpdateOptionsBuilder>,
+) -> Result<ApiResponse<Vec<u8>>, ClientError>
+where
+    T: Into<Vec<u8>>,
+    F: FnMut(Option<Vec<u8>>) -> T,
+{
+    let (res, value) = cas_loop(client, key, opts, |pair| {
+        let current = match pair {
+            Some(p) => Some(value_bytes(p)?),
+            None => None,
+        };
+        let value = f(current).into();
+        Ok((value.clone(), value))
+    })
+    .await?;
+
+    Ok(ApiResponse {
+        response: value,
+        age: res.age,
+        cache: res.cache,
+        content_hash: res.content_hash,
+        default_acl_policy: res.default_acl_policy,
+        index: res.index,
+        known_leader: res.known_leader,
+        last_contact: res.last_contact,
+        query_backend: res.query_backend,
+    })
+}
+
+/// Atomically updates the JSON value at the given key, returning the value
+/// which was written.
+///
+/// This behaves like [update] except that the current value is deserialized
+/// into a [GenericKVPair] before it's passed to `f` and the value `f` returns
+/// is serialized into JSON.
+///
+/// See [SetKeyRequest]
+#[instrument(skip(client, f, opts), err)]
+pub async fn update_json<T, F>(
+    client: &impl Client,
+    key: &str,
+    mut f: F,
+    opts: Option<&mut UpdateOptionsBuilder>,
+) -> Result<ApiResponse<T>, ClientError>
+where
+    T: DeserializeOwned + Serialize,
+    F: FnMut(Option<GenericKVPair<T>>) -> T,
+{
+    let (res, value) = cas_loop(client, key, opts, |pair| {
+        let current = match pair {
+            Some(p) => Some(GenericKVPair {
+                value: serde_json::from_slice(&value_bytes(p)?)
+                    .map_err(|e| ClientError::JsonDeserializeError { source: e })?,
+                create_index: p.create_index,
+                flags: p.flags,
+                key: p.key.clone(),
+                lock_index: p.lock_index,
+                modify_index: p.modify_index,
+                namespace: p.namespace.clone(),
+                session: p.session.clone(),
+            }),
+            None => None,
+        };
+        let value = f(current);
+        let bytes = serde_json::to_vec(&value)
+            .map_err(|e| ClientError::JsonSerializeError { source: e })?;
+        Ok((bytes, value))
+    })
+    .await?;
+
+    Ok(ApiResponse {
+        response: value,
+        age: res.age,
+        cache: res.cache,
+        content_hash: res.content_hash,
+        default_acl_policy: res.default_acl_policy,
+        index: res.index,
+        known_leader: res.known_leader,
+        last_contact: res.last_contact,
+        query_backend: res.query_backend,
+    })
+}
+
+/// Repeatedly reads the key and writes the value returned by `f` using a
+/// check-and-set until the write succeeds or the attempts are exhausted.
+///
+/// Returns the response of the successful write along with the value returned
+/// by `f` for the successful attempt.
+async fn cas_loop<T>(
+    client: &impl Client,
+    key: &str,
+    opts: Option<&mut UpdateOptionsBuilder>,
+    mut f: impl FnMut(Option<&KVPair>) -> Result<(Vec<u8>, T), ClientError>,
+) -> Result<(ApiResponse<bool>, T), ClientError> {
+    let mut t = UpdateOptions::builder();
+    let opts = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+
+        let mut req = ReadKeyRequest::builder();
+        if let Some(dc) = &opts.dc {
+            req.dc(dc);
+        }
+        if let Some(ns) = &opts.ns {
+            req.ns(ns);
+        }
+        let current = match read(client, key, Some(&mut req)).await {
+            Ok(mut r) => r.response.pop(),
+            Err(e) if e.is_not_found() => None,
+            Err(e) => return Err(e),
+        };
+        let (bytes, value) = f(current.as_ref())?;
+
+        let mut req = SetKeyRequest::builder();
+        req.cas(current.as_ref().map(|p| p.modify_index).unwrap_or(0))
+            .flags(current.as_ref().map(|p| p.flags).unwrap_or(0));
+        if let Some(dc) = &opts.dc {
+            req.dc(dc);
+        }
+        if let Some(ns) = &opts.ns {
+            req.ns(ns);
+        }
+        let res = set(client, key, &bytes, Some(&mut req)).await?;
+        if res.response {
+            return Ok((res, value));
+        }
+
+        if attempt >= opts.retry.max_attempts {
+            let err = ClientError::CasConflict {
+                message: Some(format!("Key {} was modified concurrently", key)),
+            };
+            return match attempt {
+                1 => Err(err),
+                _ => Err(ClientError::RetriesExhaustedError {
+                    attempts: attempt,
+                    source: Box::new(err),
+                }),
+            };
+        }
+        let delay = opts.retry.backoff(attempt);
+        debug!(
+            "Key {} was modified concurrently, retrying in {:?}",
+            key, delay
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+fn value_bytes(pair: &KVPair) -> Result<Vec<u8>, ClientError> {
+    match pair.value.clone() {
+        Some(v) => v.try_into(),
+        None => Ok(Vec::new()),
+    }
+}
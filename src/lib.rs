@@ -22,6 +22,7 @@
 //! * [Services](https://www.consul.io/api-docs/agent/service)
 //! * [Sessions](https://www.consul.io/api-docs/session)
 //! * [Snapshots](https://www.consul.io/api-docs/snapshot)
+//! * [Transactions](https://www.consul.io/api-docs/txn)
 //!
 //! Additionally, all optional API features such as consistency modes, blocking,
 //! etc. are also supported.
@@ -98,6 +99,7 @@
 //! use consulrs::api::check::common::AgentServiceCheckBuilder;
 //! use consulrs::api::service::requests::RegisterServiceRequest;
 //! use consulrs::service;
+//! use std::time::Duration;
 //!
 //! # let client = ConsulClient::new(
 //! #     ConsulClientSettingsBuilder::default()
@@ -119,7 +121,7 @@
 //!             .check(
 //!                 AgentServiceCheckBuilder::default()
 //!                     .name("health_check")
-//!                     .interval("10s")
+//!                     .interval(Duration::from_secs(10))
 //!                     .http("http://myservice.lab.com/health")
 //!                     .status("passing")
 //!                     .build()
@@ -138,6 +140,9 @@
 //! All errors generated by this crate are wrapped in the `ClientError` enum
 //! provided by the crate. API errors are captured and returned as their own
 //! variant including the response code and error message from the server.
+//! Well known errors, such as a missing resource, denied permissions or a missing
+//! cluster leader, are returned as dedicated variants (i.e. `NotFound`) and
+//! helpers like `ClientError::is_retryable()` are provided for classifying them.
 //! Connection related errors from `rustify` are wrapped and returned as a single
 //! variant.
 //!
@@ -152,6 +157,10 @@
 //! Docker. In order to run tests Docker must be running locally (Docker Desktop
 //! works).
 //!
+//! Code built on top of this crate can be tested without a Consul server by
+//! enabling the `testing` feature, which provides an in-process fake Consul server
+//! in the `testing` module.
+//!
 //! ## Contributing
 //!
 //! Check out the [issues][2] for items needing attention or submit your own and
@@ -173,9 +182,17 @@ pub mod api;
 pub mod catalog;
 pub mod check;
 pub mod client;
+pub mod codec;
+pub mod config_watch;
 pub mod error;
+pub mod filter;
 pub mod health;
 pub mod kv;
 pub mod service;
 pub mod session;
 pub mod snapshot;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod token;
+pub mod transport;
+pub mod txn;
@@ -29,7 +29,11 @@ pub async fn deregister(
     opts: Option<&mut DeregisterServiceRequestBuilder>,
 ) -> Result<ApiResponse<()>, ClientError> {
     let mut t = DeregisterServiceRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).id(id).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .id(id)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_empty(client, endpoint).await
 }
 
@@ -43,7 +47,11 @@ pub async fn health(
     opts: Option<&mut ServiceHealthRequestBuilder>,
 ) -> Result<ApiResponse<Vec<AgentServiceChecksInfo>>, ClientError> {
     let mut t = ServiceHealthRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).name(name).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .name(name)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -57,7 +65,11 @@ pub async fn health_by_id(
     opts: Option<&mut ServiceHealthByIdRequestBuilder>,
 ) -> Result<ApiResponse<AgentServiceChecksInfo>, ClientError> {
     let mut t = ServiceHealthByIdRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).id(id).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .id(id)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -70,7 +82,10 @@ pub async fn list(
     opts: Option<&mut ListServicesRequestBuilder>,
 ) -> Result<ApiResponse<HashMap<String, AgentService>>, ClientError> {
     let mut t = ListServicesRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -90,7 +105,7 @@ pub async fn maintenance(
         .id(id)
         .enable(enabled)
         .build()
-        .unwrap();
+        .map_err(ClientError::from_builder)?;
     api::exec_with_empty(client, endpoint).await
 }
 
@@ -104,7 +119,11 @@ pub async fn read(
     opts: Option<&mut ReadServiceRequestBuilder>,
 ) -> Result<ApiResponse<AgentService>, ClientError> {
     let mut t = ReadServiceRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).name(name).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .name(name)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -118,6 +137,10 @@ pub async fn register(
     opts: Option<&mut RegisterServiceRequestBuilder>,
 ) -> Result<ApiResponse<()>, ClientError> {
     let mut t = RegisterServiceRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).name(name).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .name(name)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_empty(client, endpoint).await
 }
@@ -27,7 +27,10 @@ pub async fn create(
     opts: Option<&mut CreateSessionRequestBuilder>,
 ) -> Result<ApiResponse<CreateSessionResponse>, ClientError> {
     let mut t = CreateSessionRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -41,7 +44,11 @@ pub async fn delete(
     opts: Option<&mut DeleteSessionRequestBuilder>,
 ) -> Result<ApiResponse<()>, ClientError> {
     let mut t = DeleteSessionRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).uuid(uuid).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .uuid(uuid)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_empty(client, endpoint).await
 }
 
@@ -54,7 +61,10 @@ pub async fn list(
     opts: Option<&mut ListSessionsRequestBuilder>,
 ) -> Result<ApiResponse<Vec<SessionEntry>>, ClientError> {
     let mut t = ListSessionsRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -68,7 +78,11 @@ pub async fn list_by_node(
     opts: Option<&mut ListNodeSessionsRequestBuilder>,
 ) -> Result<ApiResponse<Vec<SessionEntry>>, ClientError> {
     let mut t = ListNodeSessionsRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).node(node).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .node(node)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -82,7 +96,11 @@ pub async fn read(
     opts: Option<&mut ReadSessionRequestBuilder>,
 ) -> Result<ApiResponse<Vec<SessionEntry>>, ClientError> {
     let mut t = ReadSessionRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).uuid(uuid).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .uuid(uuid)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
 
@@ -96,6 +114,10 @@ pub async fn renew(
     opts: Option<&mut RenewSessionRequestBuilder>,
 ) -> Result<ApiResponse<Vec<SessionEntry>>, ClientError> {
     let mut t = RenewSessionRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).uuid(uuid).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .uuid(uuid)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_result(client, endpoint).await
 }
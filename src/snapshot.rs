@@ -1,3 +1,16 @@
+pub use self::agent::{
+    Agent, AgentBuilder, LocalSink, Retention, RetentionBuilder, SavedSnapshot, Sink,
+};
+pub use self::archive::{inspect, inspect_reader, SnapshotInfo, SnapshotMeta};
+
+use hyper::body::HttpBody;
+use rustify::errors::ClientError as RestClientError;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+mod agent;
+mod archive;
+
 use crate::{
     api::{
         self,
@@ -9,6 +22,7 @@ use crate::{
     },
     client::Client,
     error::ClientError,
+    transport::StreamTransport,
 };
 
 /// Takes a point-in-time snapshot of the Consul cluster.
@@ -20,10 +34,70 @@ pub async fn backup(
     opts: Option<&mut GenerateSnapshotRequestBuilder>,
 ) -> Result<ApiResponse<Vec<u8>>, ClientError> {
     let mut t = GenerateSnapshotRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_raw(client, endpoint).await
 }
 
+/// Takes a point-in-time snapshot of the Consul cluster and streams it to the
+/// given writer.
+///
+/// Unlike [backup], the snapshot is written as it's received and is never held
+/// in memory in its entirety. The response contains the number of bytes
+/// written along with the metadata returned by Consul, where `index` is the
+/// index the snapshot was taken at.
+///
+/// See [GenerateSnapshotRequest]
+#[instrument(skip(client, writer, opts), err)]
+pub async fn backup_to<C, W>(
+    client: &C,
+    writer: &mut W,
+    opts: Option<&mut GenerateSnapshotRequestBuilder>,
+) -> Result<ApiResponse<u64>, ClientError>
+where
+    C: Client,
+    C::Http: StreamTransport,
+    W: AsyncWrite + Unpin + Send,
+{
+    let mut t = GenerateSnapshotRequest::builder();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
+    let mut res = api::exec_with_stream(client, endpoint, None).await?;
+
+    let mut written = 0;
+    while let Some(chunk) = res.response.data().await {
+        let chunk = chunk.map_err(|e| ClientError::RestClientError {
+            source: RestClientError::ResponseError { source: e.into() },
+        })?;
+        writer
+            .write_all(&chunk)
+            .await
+            .map_err(|e| ClientError::StreamWriteError { source: e })?;
+        written += chunk.len() as u64;
+    }
+    writer
+        .flush()
+        .await
+        .map_err(|e| ClientError::StreamWriteError { source: e })?;
+    debug!("Wrote {} byte snapshot", written);
+
+    Ok(ApiResponse {
+        response: written,
+        age: res.age,
+        cache: res.cache,
+        content_hash: res.content_hash,
+        default_acl_policy: res.default_acl_policy,
+        index: res.index,
+        known_leader: res.known_leader,
+        last_contact: res.last_contact,
+        query_backend: res.query_backend,
+    })
+}
+
 /// Restores a snapshot to the Consul cluster.
 ///
 /// See [RestoreSnapshotRequest]
@@ -34,6 +108,49 @@ pub async fn restore(
     opts: Option<&mut RestoreSnapshotRequestBuilder>,
 ) -> Result<ApiResponse<()>, ClientError> {
     let mut t = RestoreSnapshotRequest::builder();
-    let endpoint = opts.unwrap_or(&mut t).data(data).build().unwrap();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .data(data)
+        .build()
+        .map_err(ClientError::from_builder)?;
     api::exec_with_empty(client, endpoint).await
 }
+
+/// Restores a snapshot to the Consul cluster by streaming it from the given
+/// reader.
+///
+/// Unlike [restore], the snapshot is sent as it's read and is never held in
+/// memory in its entirety.
+///
+/// See [RestoreSnapshotRequest]
+#[instrument(skip(client, reader, opts), err)]
+pub async fn restore_from<C, R>(
+    client: &C,
+    reader: R,
+    opts: Option<&mut RestoreSnapshotRequestBuilder>,
+) -> Result<ApiResponse<()>, ClientError>
+where
+    C: Client,
+    C::Http: StreamTransport,
+    R: AsyncRead + Send + 'static,
+{
+    let mut t = RestoreSnapshotRequest::builder();
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .build()
+        .map_err(ClientError::from_builder)?;
+    let body = hyper::Body::wrap_stream(ReaderStream::new(reader));
+    let res = api::exec_with_stream(client, endpoint, Some(body)).await?;
+
+    Ok(ApiResponse {
+        response: (),
+        age: res.age,
+        cache: res.cache,
+        content_hash: res.content_hash,
+        default_acl_policy: res.default_acl_policy,
+        index: res.index,
+        known_leader: res.known_leader,
+        last_contact: res.last_contact,
+        query_backend: res.query_backend,
+    })
+}
@@ -0,0 +1,544 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use derive_builder::Builder;
+use tokio::{
+    io::{AsyncRead, AsyncWriteExt},
+    time::{self, MissedTickBehavior},
+};
+
+use crate::{
+    api::kv::requests::SetKeyRequest,
+    api::session::requests::CreateSessionRequest,
+    client::Client,
+    error::ClientError,
+    kv, session,
+    snapshot::{backup_to, inspect_reader, SnapshotInfo},
+    transport::StreamTransport,
+};
+
+/// The extension given to snapshot files written by an [Agent].
+const EXTENSION: &str = ".snap";
+
+/// A destination for the snapshots taken by an [Agent].
+///
+/// Snapshots are identified by their file name, which is generated by the
+/// agent and is safe to use as a file name or object key.
+#[async_trait]
+pub trait Sink: fmt::Debug + Send + Sync {
+    /// Stores the `len` bytes of a snapshot read from the given reader under
+    /// the given name, replacing any existing one.
+    async fn write(
+        &self,
+        name: &str,
+        len: u64,
+        reader: &mut (dyn AsyncRead + Unpin + Send),
+    ) -> Result<(), ClientError>;
+
+    /// Returns the names of all stored snapshots.
+    async fn list(&self) -> Result<Vec<String>, ClientError>;
+
+    /// Deletes the snapshot with the given name.
+    async fn delete(&self, name: &str) -> Result<(), ClientError>;
+}
+
+/// A [Sink] which stores snapshots in a local directory.
+///
+/// Snapshots are first written to a temporary file which is renamed once it's
+/// complete, so a partially written snapshot is never listed.
+#[derive(Clone, Debug)]
+pub struct LocalSink {
+    dir: PathBuf,
+}
+
+impl LocalSink {
+    /// Creates a new [LocalSink] which stores snapshots in the given
+    /// directory. The directory is created when the first snapshot is written.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        LocalSink { dir: dir.into() }
+    }
+
+    /// Returns the directory snapshots are stored in.
+    pub fn dir(&self) -> &Path {
+        self.dir.as_path()
+    }
+}
+
+#[async_trait]
+impl Sink for LocalSink {
+    async fn write(
+        &self,
+        name: &str,
+        _len: u64,
+        reader: &mut (dyn AsyncRead + Unpin + Send),
+    ) -> Result<(), ClientError> {
+        let path = self.dir.join(name);
+        let tmp = self.dir.join(format!(".{}.tmp", name));
+        let write_err = |source, path: &Path| ClientError::FileWriteError {
+            source,
+            path: path.display().to_string(),
+        };
+
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| write_err(e, &self.dir))?;
+        let mut file = tokio::fs::File::create(&tmp)
+            .await
+            .map_err(|e| write_err(e, &tmp))?;
+        tokio::io::copy(reader, &mut file)
+            .await
+            .map_err(|e| write_err(e, &tmp))?;
+        file.flush().await.map_err(|e| write_err(e, &tmp))?;
+        drop(file);
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .map_err(|e| write_err(e, &path))
+    }
+
+    async fn list(&self) -> Result<Vec<String>, ClientError> {
+        let read_err = |source| ClientError::FileReadError {
+            source,
+            path: self.dir.display().to_string(),
+        };
+
+        let mut dir = match tokio::fs::read_dir(&self.dir).await {
+            Ok(d) => d,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(read_err(e)),
+        };
+        let mut names = Vec::new();
+        while let Some(entry) = dir.next_entry().await.map_err(read_err)? {
+            if let Some(name) = entry.file_name().to_str() {
+                if !name.starts_with('.') {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), ClientError> {
+        let path = self.dir.join(name);
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(|e| ClientError::FileWriteError {
+                source: e,
+                path: path.display().to_string(),
+            })
+    }
+}
+
+/// The policy used by an [Agent] to determine which snapshots to keep.
+///
+/// A snapshot is kept if any of the rules select it:
+///
+/// * `keep_last`: the most recent snapshots
+/// * `keep_daily`: the most recent snapshot of each of the last N days on
+///   which a snapshot was taken
+/// * `keep_weekly`: the most recent snapshot of each of the last N weeks on
+///   which a snapshot was taken, where weeks start on Monday
+///
+/// Days and weeks are in UTC. The most recent snapshot is always kept.
+#[derive(Builder, Clone, Debug)]
+#[builder(setter(into))]
+pub struct Retention {
+    #[builder(default = "24")]
+    pub keep_last: usize,
+    #[builder(default = "7")]
+    pub keep_daily: usize,
+    #[builder(default = "4")]
+    pub keep_weekly: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention::builder().build().unwrap()
+    }
+}
+
+impl Retention {
+    /// Returns a default instance of [RetentionBuilder] for configuring a
+    /// policy.
+    pub fn builder() -> RetentionBuilder {
+        RetentionBuilder::default()
+    }
+
+    /// Returns the names of the snapshots which should be deleted, ignoring
+    /// any names which weren't generated by an [Agent] with the given prefix.
+    pub fn expired(&self, prefix: &str, names: &[String]) -> Vec<String> {
+        let mut snapshots = names
+            .iter()
+            .filter_map(|n| parse_name(prefix, n).map(|(secs, index)| (secs, index, n)))
+            .collect::<Vec<_>>();
+        snapshots.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut keep = vec![false; snapshots.len()];
+        let mut days = BTreeSet::new();
+        let mut weeks = BTreeSet::new();
+        for (i, (secs, _, _)) in snapshots.iter().enumerate() {
+            let day = secs / 86400;
+            // 1970-01-01 was a Thursday, so shift weeks to start on Monday
+            let week = (day + 3) / 7;
+
+            keep[i] |= i == 0 || i < self.keep_last;
+            if days.len() < self.keep_daily && days.insert(day) {
+                keep[i] = true;
+            }
+            if weeks.len() < self.keep_weekly && weeks.insert(week) {
+                keep[i] = true;
+            }
+        }
+
+        snapshots
+            .into_iter()
+            .zip(keep)
+            .filter(|(_, keep)| !keep)
+            .map(|((_, _, name), _)| name.clone())
+            .collect()
+    }
+}
+
+/// A snapshot taken by an [Agent].
+#[derive(Clone, Debug)]
+pub struct SavedSnapshot {
+    /// The name the snapshot was stored under
+    pub name: String,
+    /// The result of inspecting the snapshot before it was stored
+    pub info: SnapshotInfo,
+    /// The names of the snapshots deleted by the retention policy
+    pub pruned: Vec<String>,
+}
+
+/// Periodically takes snapshots of a Consul cluster and stores them in a
+/// [Sink].
+///
+/// Snapshots are streamed to a temporary file in `spool_dir` (the system's
+/// temporary directory by default) rather than being held in memory. Every
+/// snapshot is [inspected][crate::snapshot::inspect] from there before it's
+/// streamed to the sink, so a corrupt archive is never written to the sink.
+/// The temporary file is always removed afterwards. Snapshots are named
+/// `{prefix}-{timestamp}-{index}.snap` where the timestamp is the UTC time the
+/// snapshot was taken (i.e. `20211019T143000Z`) and the index is the Raft
+/// index it was taken at. After each snapshot is stored, older snapshots are
+/// deleted according to the [Retention] policy.
+///
+/// When `lock_key` is set, a snapshot is only taken by the agent which holds
+/// the lock on that key. This allows the agent to run in multiple replicas
+/// while only one of them takes snapshots. The lock is held using a session
+/// with a TTL of twice the `interval` (between 10 seconds and 24 hours) which
+/// is renewed before every snapshot, so another replica takes over once the
+/// session of the previous holder expires.
+///
+/// ```no_run
+/// use consulrs::client::{ConsulClient, ConsulClientSettingsBuilder};
+/// use consulrs::snapshot::{Agent, LocalSink};
+/// use std::{sync::Arc, time::Duration};
+///
+/// # tokio_test::block_on(async {
+/// let client = ConsulClient::new(ConsulClientSettingsBuilder::default().build().unwrap()).unwrap();
+/// let agent = Agent::builder()
+///     .interval(Duration::from_secs(3600))
+///     .lock_key("service/snapshot/leader")
+///     .sink(Arc::new(LocalSink::new("/var/lib/consul-snapshots")))
+///     .build()
+///     .unwrap();
+/// agent.run(&client).await;
+/// # })
+/// ```
+#[derive(Builder, Clone, Debug)]
+#[builder(setter(into, strip_option))]
+pub struct Agent {
+    #[builder(default = "Duration::from_secs(3600)")]
+    pub interval: Duration,
+    #[builder(default)]
+    pub lock_key: Option<String>,
+    #[builder(default = "String::from(\"consul\")")]
+    pub prefix: String,
+    #[builder(default)]
+    pub retention: Retention,
+    #[builder(setter(into = false))]
+    pub sink: Arc<dyn Sink>,
+    #[builder(default = "std::env::temp_dir()")]
+    pub spool_dir: PathBuf,
+    #[builder(setter(skip), default)]
+    session: Arc<Mutex<Option<String>>>,
+}
+
+impl Agent {
+    /// Returns a default instance of [AgentBuilder] for configuring an agent.
+    pub fn builder() -> AgentBuilder {
+        AgentBuilder::default()
+    }
+
+    /// Takes a snapshot every `interval` until the returned future is dropped.
+    ///
+    /// The first snapshot is taken immediately. Failures are logged and don't
+    /// stop the agent; use [Agent::snapshot] to handle them directly.
+    pub async fn run<C>(&self, client: &C)
+    where
+        C: Client,
+        C::Http: StreamTransport,
+    {
+        let mut ticker = time::interval(self.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            match self.snapshot(client).await {
+                Ok(Some(s)) => info!("Saved snapshot {}", s.name),
+                Ok(None) => debug!("Skipping snapshot as the lock is held elsewhere"),
+                Err(e) => error!("Failed taking snapshot: {}", e),
+            }
+        }
+    }
+
+    /// Takes a single snapshot, stores it in the sink and applies the
+    /// retention policy.
+    ///
+    /// Returns `None` without taking a snapshot if `lock_key` is set and the
+    /// lock is held by another agent.
+    #[instrument(skip(self, client), err)]
+    pub async fn snapshot<C>(&self, client: &C) -> Result<Option<SavedSnapshot>, ClientError>
+    where
+        C: Client,
+        C::Http: StreamTransport,
+    {
+        if let Some(key) = &self.lock_key {
+            if !self.acquire(client, key).await? {
+                return Ok(None);
+            }
+        }
+
+        let spool = self.spool_dir.join(format!(
+            ".{}-{:016x}.tmp",
+            self.prefix,
+            rand::random::<u64>()
+        ));
+        let res = self.store(client, &spool).await;
+        match tokio::fs::remove_file(&spool).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                warn!("Failed removing {}: {}", spool.display(), e)
+            }
+            _ => {}
+        }
+        let (name, info) = res?;
+
+        let pruned = self.prune().await?;
+        Ok(Some(SavedSnapshot { name, info, pruned }))
+    }
+
+    /// Deletes the snapshots in the sink which aren't kept by the retention
+    /// policy, returning their names.
+    #[instrument(skip(self), err)]
+    pub async fn prune(&self) -> Result<Vec<String>, ClientError> {
+        let names = self.sink.list().await?;
+        let expired = self.retention.expired(&self.prefix, &names);
+        for name in expired.iter() {
+            debug!("Deleting expired snapshot {}", name);
+            self.sink.delete(name).await?;
+        }
+        Ok(expired)
+    }
+
+    /// Releases the lock on `lock_key`, if held, and destroys the session used
+    /// to hold it. This allows another agent to take over immediately instead
+    /// of waiting for the session to expire.
+    #[instrument(skip(self, client), err)]
+    pub async fn release(&self, client: &impl Client) -> Result<(), ClientError> {
+        let id = match self.session_id() {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        if let Some(key) = &self.lock_key {
+            kv::set(
+                client,
+                key,
+                b"",
+                Some(SetKeyRequest::builder().release(&id)),
+            )
+            .await?;
+        }
+        session::delete(client, &id, None).await?;
+        self.set_session_id(None);
+        Ok(())
+    }
+
+    /// Takes a snapshot into the given spool file, inspects it and streams it
+    /// to the sink, returning its name.
+    async fn store<C>(
+        &self,
+        client: &C,
+        spool: &Path,
+    ) -> Result<(String, SnapshotInfo), ClientError>
+    where
+        C: Client,
+        C::Http: StreamTransport,
+    {
+        let read_err = |source| ClientError::FileReadError {
+            source,
+            path: spool.display().to_string(),
+        };
+        let write_err = |source| ClientError::FileWriteError {
+            source,
+            path: spool.display().to_string(),
+        };
+
+        tokio::fs::create_dir_all(&self.spool_dir)
+            .await
+            .map_err(write_err)?;
+        let mut file = tokio::fs::File::create(spool).await.map_err(write_err)?;
+        let len = backup_to(client, &mut file, None).await?.response;
+        drop(file);
+
+        let path = spool.to_path_buf();
+        let info = tokio::task::spawn_blocking(move || {
+            let file = std::fs::File::open(&path).map_err(|e| ClientError::FileReadError {
+                source: e,
+                path: path.display().to_string(),
+            })?;
+            inspect_reader(std::io::BufReader::new(file))
+        })
+        .await
+        .map_err(|e| ClientError::InvalidSnapshotError {
+            message: e.to_string(),
+        })??;
+
+        let name = snapshot_name(&self.prefix, SystemTime::now(), info.meta.index);
+        let mut file = tokio::fs::File::open(spool).await.map_err(read_err)?;
+        self.sink.write(&name, len, &mut file).await?;
+        debug!("Wrote {} byte snapshot {}", len, name);
+        Ok((name, info))
+    }
+
+    /// Attempts to acquire the lock on the given key, creating or renewing the
+    /// agent's session as needed.
+    async fn acquire(&self, client: &impl Client, key: &str) -> Result<bool, ClientError> {
+        let id = match self.session_id() {
+            Some(id) => match session::renew(client, &id, None).await {
+                Ok(_) => id,
+                Err(e) if e.is_not_found() => {
+                    debug!("Session {} expired, creating a new one", id);
+                    self.create_session(client).await?
+                }
+                Err(e) => return Err(e),
+            },
+            None => self.create_session(client).await?,
+        };
+
+        Ok(
+            kv::set(client, key, b"", Some(SetKeyRequest::builder().acquire(id)))
+                .await?
+                .response,
+        )
+    }
+
+    async fn create_session(&self, client: &impl Client) -> Result<String, ClientError> {
+        let ttl = (self.interval * 2).clamp(Duration::from_secs(10), Duration::from_secs(86400));
+        let id = session::create(
+            client,
+            Some(
+                CreateSessionRequest::builder()
+                    .name(format!("{}-snapshot-agent", self.prefix))
+                    .lock_delay(Duration::ZERO)
+                    .ttl(ttl),
+            ),
+        )
+        .await?
+        .response
+        .id;
+        self.set_session_id(Some(id.clone()));
+        Ok(id)
+    }
+
+    fn session_id(&self) -> Option<String> {
+        self.session
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn set_session_id(&self, id: Option<String>) {
+        *self.session.lock().unwrap_or_else(PoisonError::into_inner) = id;
+    }
+}
+
+/// Returns the name of a snapshot taken at the given time and index.
+fn snapshot_name(prefix: &str, time: SystemTime, index: u64) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let (year, month, day) = civil_from_days(secs / 86400);
+    let rem = secs % 86400;
+    format!(
+        "{}-{:04}{:02}{:02}T{:02}{:02}{:02}Z-{}{}",
+        prefix,
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60,
+        index,
+        EXTENSION
+    )
+}
+
+/// Parses a name generated by [snapshot_name], returning the number of seconds
+/// since the Unix epoch the snapshot was taken at and its index.
+fn parse_name(prefix: &str, name: &str) -> Option<(u64, u64)> {
+    let rest = name
+        .strip_prefix(prefix)?
+        .strip_prefix('-')?
+        .strip_suffix(EXTENSION)?;
+    let (timestamp, index) = rest.split_once('-')?;
+    let index = index.parse().ok()?;
+
+    let ts = timestamp.as_bytes();
+    if ts.len() != 16 || ts[8] != b'T' || ts[15] != b'Z' {
+        return None;
+    }
+    let num = |r: std::ops::Range<usize>| timestamp.get(r)?.parse::<u64>().ok();
+    let days = days_from_civil(num(0..4)?, num(4..6)?, num(6..8)?)?;
+    let secs = num(9..11)? * 3600 + num(11..13)? * 60 + num(13..15)?;
+    Some((days * 86400 + secs, index))
+}
+
+/// Converts a number of days since the Unix epoch into a (year, month, day)
+/// date in the proleptic Gregorian calendar.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The inverse of [civil_from_days], returning `None` for dates before the
+/// Unix epoch or which are out of range.
+fn days_from_civil(year: u64, month: u64, day: u64) -> Option<u64> {
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let year = if month <= 2 {
+        year.checked_sub(1)?
+    } else {
+        year
+    };
+    let era = year / 400;
+    let yoe = year % 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    (era * 146_097 + doe).checked_sub(719_468)
+}
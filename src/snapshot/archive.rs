@@ -0,0 +1,316 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, BufReader, Read},
+};
+
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::ClientError;
+
+/// The name of the file containing the snapshot metadata.
+const META_FILE: &str = "meta.json";
+
+/// The name of the file containing the serialized state of the cluster.
+const STATE_FILE: &str = "state.bin";
+
+/// The name of the file containing the checksums of the other files.
+const SUMS_FILE: &str = "SHA256SUMS";
+
+/// The flag set on the message type of records which Consul may skip if it
+/// doesn't recognize the type (`structs.IgnoreUnknownTypeFlag`).
+const IGNORE_UNKNOWN_TYPE_FLAG: u8 = 0x80;
+
+/// The names of the message types found in `state.bin`, indexed by their
+/// numeric value. This mirrors the `structs.MessageType` constants in
+/// `agent/structs/structs.go` as of Consul 1.16.
+const MESSAGE_TYPES: &[&str] = &[
+    "Register",
+    "Deregister",
+    "KVS",
+    "Session",
+    "ACL",
+    "Tombstone",
+    "CoordinateBatchUpdate",
+    "PreparedQuery",
+    "Txn",
+    "Autopilot",
+    "Area",
+    "ACLBootstrap",
+    "Intention",
+    "ConnectCA",
+    "ConnectCAProviderState",
+    "ConnectCAConfig",
+    "Index",
+    "ACLToken",
+    "ACLTokenDelete",
+    "ACLPolicy",
+    "ACLPolicyDelete",
+    "ConnectCALeaf",
+    "ConfigEntry",
+    "ACLRole",
+    "ACLRoleDelete",
+    "ACLBindingRule",
+    "ACLBindingRuleDelete",
+    "ACLAuthMethod",
+    "ACLAuthMethodDelete",
+    "ChunkingState",
+    "FederationState",
+    "SystemMetadata",
+    "ServiceVirtualIP",
+    "FreeVirtualIP",
+    "KindServiceNames",
+    "Peering",
+    "PeeringDelete",
+    "PeeringTerminateByID",
+    "PeeringTrustBundle",
+    "PeeringTrustBundleDelete",
+    "PeeringSecret",
+    "RaftLogVerifierCheckpoint",
+    "Resource",
+    "UpdateVirtualIP",
+];
+
+/// The metadata stored in a snapshot's `meta.json` file.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SnapshotMeta {
+    #[serde(rename = "ID")]
+    pub id: String,
+    pub index: u64,
+    pub term: u64,
+    pub version: u64,
+    #[serde(default)]
+    pub configuration_index: u64,
+    #[serde(default)]
+    pub size: u64,
+}
+
+/// The result of inspecting a snapshot archive with [inspect].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SnapshotInfo {
+    /// The metadata of the snapshot
+    pub meta: SnapshotMeta,
+    /// The last index applied to the state contained in the snapshot
+    pub last_index: u64,
+    /// The number of records in the snapshot, keyed by their message type
+    pub records: BTreeMap<String, u64>,
+    /// The size of the serialized state in bytes
+    pub size: u64,
+}
+
+impl SnapshotInfo {
+    /// Returns the total number of records in the snapshot.
+    pub fn total_records(&self) -> u64 {
+        self.records.values().sum()
+    }
+}
+
+/// Inspects and validates a snapshot archive as returned by
+/// [backup][crate::snapshot::backup].
+///
+/// See [inspect_reader].
+pub fn inspect(data: &[u8]) -> Result<SnapshotInfo, ClientError> {
+    inspect_reader(data)
+}
+
+/// Inspects and validates a snapshot archive read from the given reader.
+///
+/// Consul snapshots are gzipped tar archives containing the snapshot metadata
+/// (`meta.json`), the serialized state of the cluster (`state.bin`) and the
+/// SHA256 checksums of both (`SHA256SUMS`). The archive is read in a single
+/// pass: every file is checksummed while it's read and the records in the
+/// state are counted by message type without being held in memory. No
+/// connection to a Consul server is required.
+///
+/// Returns a [ClientError::InvalidSnapshotError] if the archive is malformed
+/// or a [ClientError::SnapshotChecksumError] if a file doesn't match its
+/// checksum.
+#[instrument(skip(reader), err)]
+pub fn inspect_reader(reader: impl Read) -> Result<SnapshotInfo, ClientError> {
+    let mut archive = tar::Archive::new(GzDecoder::new(reader));
+    let mut hashes = HashMap::<String, String>::new();
+    let mut meta = None;
+    let mut state = None;
+    let mut sums = None;
+
+    for entry in archive.entries().map_err(invalid)? {
+        let entry = entry.map_err(invalid)?;
+        let name = entry
+            .path()
+            .map_err(invalid)?
+            .to_string_lossy()
+            .into_owned();
+        let mut reader = HashingReader::new(entry);
+
+        match name.as_str() {
+            META_FILE => {
+                meta = Some(
+                    serde_json::from_reader::<_, SnapshotMeta>(&mut reader).map_err(|e| {
+                        ClientError::InvalidSnapshotError {
+                            message: format!("Failed parsing {}: {}", META_FILE, e),
+                        }
+                    })?,
+                );
+            }
+            STATE_FILE => {
+                let mut buffered = BufReader::new(&mut reader);
+                state = Some(read_state(&mut buffered)?);
+            }
+            SUMS_FILE => {
+                let mut content = String::new();
+                reader.read_to_string(&mut content).map_err(invalid)?;
+                sums = Some(content);
+            }
+            _ => debug!("Skipping unknown file in snapshot: {}", name),
+        }
+
+        // Drain anything which wasn't consumed so the checksum is complete
+        io::copy(&mut reader, &mut io::sink()).map_err(invalid)?;
+        hashes.insert(name, reader.finish());
+    }
+
+    let missing = |file: &str| ClientError::InvalidSnapshotError {
+        message: format!("Snapshot is missing {}", file),
+    };
+    let meta = meta.ok_or_else(|| missing(META_FILE))?;
+    let (last_index, records, size) = state.ok_or_else(|| missing(STATE_FILE))?;
+    let sums = sums.ok_or_else(|| missing(SUMS_FILE))?;
+
+    verify(&sums, &hashes)?;
+    Ok(SnapshotInfo {
+        meta,
+        last_index,
+        records,
+        size,
+    })
+}
+
+/// Checks the computed hashes against the contents of the `SHA256SUMS` file.
+fn verify(sums: &str, hashes: &HashMap<String, String>) -> Result<(), ClientError> {
+    let mut expected = HashMap::new();
+    for line in sums.lines().filter(|l| !l.trim().is_empty()) {
+        match line.split_once(char::is_whitespace) {
+            Some((hash, file)) => expected.insert(file.trim(), hash.trim()),
+            None => {
+                return Err(ClientError::InvalidSnapshotError {
+                    message: format!("Malformed line in {}: {}", SUMS_FILE, line),
+                })
+            }
+        };
+    }
+
+    for file in [META_FILE, STATE_FILE].iter() {
+        match (expected.get(file), hashes.get(*file)) {
+            (Some(e), Some(a)) if e.eq_ignore_ascii_case(a) => {}
+            (Some(_), _) => {
+                return Err(ClientError::SnapshotChecksumError {
+                    file: file.to_string(),
+                })
+            }
+            (None, _) => {
+                return Err(ClientError::InvalidSnapshotError {
+                    message: format!("{} has no checksum for {}", SUMS_FILE, file),
+                })
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the serialized cluster state, returning the last index applied to
+/// it, the number of records of each message type and its size in bytes.
+///
+/// The state consists of a msgpack encoded header followed by a sequence of
+/// records, each of which is a single byte message type followed by a msgpack
+/// encoded value.
+fn read_state(reader: &mut impl Read) -> Result<(u64, BTreeMap<String, u64>, u64), ClientError> {
+    let mut reader = CountingReader {
+        count: 0,
+        inner: reader,
+    };
+    let state_err = |e: rmpv::decode::Error| ClientError::InvalidSnapshotError {
+        message: format!("Failed decoding {}: {}", STATE_FILE, e),
+    };
+
+    let header = rmpv::decode::read_value(&mut reader).map_err(state_err)?;
+    let last_index = match &header {
+        rmpv::Value::Map(m) => m
+            .iter()
+            .find(|(k, _)| k.as_str() == Some("LastIndex"))
+            .and_then(|(_, v)| v.as_u64()),
+        rmpv::Value::Array(a) => a.first().and_then(|v| v.as_u64()),
+        _ => None,
+    }
+    .ok_or_else(|| ClientError::InvalidSnapshotError {
+        message: format!("Invalid header in {}", STATE_FILE),
+    })?;
+
+    let mut records = BTreeMap::<String, u64>::new();
+    let mut ty = [0u8; 1];
+    loop {
+        if reader.read(&mut ty).map_err(invalid)? == 0 {
+            break;
+        }
+        rmpv::decode::read_value(&mut reader).map_err(state_err)?;
+
+        let kind = ty[0] & !IGNORE_UNKNOWN_TYPE_FLAG;
+        let name = match MESSAGE_TYPES.get(kind as usize) {
+            Some(n) => n.to_string(),
+            None => format!("Unknown({})", kind),
+        };
+        *records.entry(name).or_default() += 1;
+    }
+
+    Ok((last_index, records, reader.count))
+}
+
+fn invalid(e: io::Error) -> ClientError {
+    ClientError::InvalidSnapshotError {
+        message: e.to_string(),
+    }
+}
+
+/// A reader which computes the SHA256 checksum of everything read through it.
+struct HashingReader<R: Read> {
+    hasher: Sha256,
+    inner: R,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        HashingReader {
+            hasher: Sha256::new(),
+            inner,
+        }
+    }
+
+    /// Returns the hex encoded checksum of everything read.
+    fn finish(self) -> String {
+        hex::encode(self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// A reader which counts the number of bytes read through it.
+struct CountingReader<R: Read> {
+    count: u64,
+    inner: R,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
@@ -0,0 +1,771 @@
+//! An in-process fake Consul server for testing.
+//!
+//! The [TestServer] implements a subset of the Consul HTTP API entirely in
+//! memory and binds to a random local port, allowing a regular
+//! [ConsulClient] to talk to it without requiring a real Consul agent. It's
+//! intended for unit testing code built on top of this crate and is only
+//! available when the `testing` feature is enabled.
+//!
+//! The following endpoints are currently supported:
+//!
+//! * KV: reading, writing and deleting keys, including `cas`, `flags`,
+//!   `acquire`, `release`, `recurse`, `raw`, `keys` and `separator`
+//! * Sessions: create, destroy, info, list, node and renew
+//! * Catalog: register, deregister, datacenters, nodes, services, service
+//!   and node services
+//! * Agent: services, service registration and maintenance, checks and check
+//!   updates
+//! * Health: service health
+//! * Snapshot: backup and restore, where a backup returns the last archive
+//!   which was restored or an archive generated from the server's state
+//! * Status: leader and peers
+//! * Transactions: KV operations only
+//!
+//! All read endpoints support blocking queries using the `index` and `wait`
+//! query parameters and return the `X-Consul-Index` header. Other features,
+//! such as filtering and consistency modes, are accepted but ignored.
+//!
+//! ```
+//! use consulrs::{kv, testing::TestServer};
+//!
+//! # tokio_test::block_on(async {
+//! let server = TestServer::start().await.unwrap();
+//! let client = server.client().unwrap();
+//!
+//! kv::set(&client, "mykey", b"myvalue", None).await.unwrap();
+//! let res = kv::read_raw(&client, "mykey", None).await.unwrap();
+//! assert_eq!(res.response, b"myvalue");
+//! # })
+//! ```
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use hyper::{
+    header::CONTENT_TYPE, server::conn::Http, service::service_fn, Body, Method, Request, Response,
+    StatusCode,
+};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, sync::Notify, task::JoinHandle};
+
+use crate::{
+    api::GoDuration,
+    client::{ConsulClient, ConsulClientSettingsBuilder},
+    error::ClientError,
+};
+
+use self::store::{str_field, KVWrite, Store, Table, DATACENTER, NODE, NODE_ADDRESS};
+
+mod store;
+
+/// The default amount of time a blocking query waits for changes.
+const DEFAULT_WAIT: Duration = Duration::from_secs(300);
+
+/// The maximum amount of time a blocking query waits for changes.
+const MAX_WAIT: Duration = Duration::from_secs(600);
+
+/// The maximum size of a transaction request, matching Consul's default for
+/// `txn_max_req_len`.
+const TXN_MAX_REQ_LEN: usize = 512 * 1024;
+
+/// An in-process fake Consul server.
+///
+/// The server is started with [TestServer::start] and runs in the background
+/// on the current tokio runtime until it's dropped.
+pub struct TestServer {
+    address: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+/// The state shared between all connections to a [TestServer].
+#[derive(Debug, Default)]
+struct Shared {
+    notify: Notify,
+    store: Mutex<Store>,
+}
+
+impl Shared {
+    fn store(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Applies a write to the store and wakes any blocking queries.
+    fn write<T>(&self, f: impl FnOnce(&mut Store) -> T) -> T {
+        let result = f(&mut self.store());
+        self.notify.notify_waiters();
+        result
+    }
+
+    /// Waits until the given table has changed past the index requested by a
+    /// blocking query or the wait time has elapsed. Returns immediately if the
+    /// query isn't blocking.
+    async fn block(&self, table: Table, query: &Query) {
+        let index = match query.get("index").and_then(|i| i.parse::<u64>().ok()) {
+            Some(i) if i > 0 => i,
+            _ => return,
+        };
+        let wait = query
+            .get("wait")
+            .and_then(|w| w.parse::<GoDuration>().ok())
+            .map(Duration::from)
+            .unwrap_or(DEFAULT_WAIT)
+            .min(MAX_WAIT);
+
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            // The notification must be registered before checking the index
+            // to avoid missing a write which happens in between
+            let notified = self.notify.notified();
+            if self.store().table_index(table) > index {
+                return;
+            }
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl TestServer {
+    /// Starts a new [TestServer] listening on a random port on the loopback
+    /// interface.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub async fn start() -> std::io::Result<TestServer> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let shared = Arc::new(Shared::default());
+
+        info!("Starting Consul test server on {}", address);
+        let handle = tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((s, _)) => s,
+                    Err(e) => {
+                        error!("Test server failed to accept connection: {}", e);
+                        continue;
+                    }
+                };
+
+                let shared = shared.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req| {
+                        let shared = shared.clone();
+                        async move { Ok::<_, Infallible>(handle(shared, req).await) }
+                    });
+                    if let Err(e) = Http::new()
+                        .http1_only(true)
+                        .serve_connection(stream, service)
+                        .await
+                    {
+                        debug!("Test server connection closed with error: {}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(TestServer { address, handle })
+    }
+
+    /// Returns the address of this server (i.e. `http://127.0.0.1:1234`).
+    pub fn address(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// Returns a new [ConsulClient] configured to talk to this server.
+    pub fn client(&self) -> Result<ConsulClient, ClientError> {
+        ConsulClient::new(
+            ConsulClientSettingsBuilder::default()
+                .address(self.address())
+                .build()
+                .map_err(ClientError::from_builder)?,
+        )
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// The query parameters of a request.
+struct Query(HashMap<String, String>);
+
+impl Query {
+    fn parse(query: Option<&str>) -> Self {
+        Query(
+            url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+                .into_owned()
+                .collect(),
+        )
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|s| s.as_str())
+    }
+
+    /// Returns whether a flag (i.e. `?recurse`) is present and not `false`.
+    fn flag(&self, key: &str) -> bool {
+        matches!(self.get(key), Some(v) if v != "false")
+    }
+
+    fn u64(&self, key: &str) -> Result<Option<u64>, Rejection> {
+        match self.get(key) {
+            Some(v) => v
+                .parse()
+                .map(Some)
+                .map_err(|_| error(400, format!("Invalid value for {}: {}", key, v))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Routes a request to its handler.
+async fn handle(shared: Arc<Shared>, req: Request<Body>) -> Response<Body> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let query = Query::parse(req.uri().query());
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(b) => b.to_vec(),
+        Err(e) => return error(400, format!("Failed reading request body: {}", e)).into(),
+    };
+    debug!("Test server received {} {}", method, path);
+
+    let path = match path.strip_prefix("/v1/") {
+        Some(p) => p,
+        None => return error(404, format!("Unsupported path: {}", path)).into(),
+    };
+    let (group, rest) = path.split_once('/').unwrap_or((path, ""));
+    let result = match group {
+        "agent" => agent(&shared, &method, rest, &query, &body).await,
+        "catalog" => catalog(&shared, &method, rest, &query, &body).await,
+        "health" => health(&shared, &method, rest, &query).await,
+        "kv" => {
+            let key = percent_decode_str(rest).decode_utf8_lossy().into_owned();
+            kv(&shared, &method, &key, &query, body).await
+        }
+        "session" => session(&shared, &method, rest, &query, &body).await,
+        "snapshot" => snapshot(&shared, &method, body),
+        "status" => status(&method, rest),
+        "txn" => txn(&shared, &method, &body),
+        _ => Err(unsupported(&method, path)),
+    };
+
+    result.unwrap_or_else(Response::from)
+}
+
+/// An error response returned by a handler.
+struct Rejection {
+    index: u64,
+    message: String,
+    status: u16,
+}
+
+impl From<Rejection> for Response<Body> {
+    fn from(r: Rejection) -> Self {
+        response(r.status, r.index, "text/plain", r.message.into_bytes())
+    }
+}
+
+type HandlerResult = Result<Response<Body>, Rejection>;
+
+async fn kv(
+    shared: &Shared,
+    method: &Method,
+    key: &str,
+    query: &Query,
+    body: Vec<u8>,
+) -> HandlerResult {
+    match *method {
+        Method::GET => {
+            shared.block(Table::KV, query).await;
+            let store = shared.store();
+            let index = store.table_index(Table::KV);
+
+            if query.flag("keys") {
+                let keys = store.kv_keys(key, query.get("separator"));
+                return match keys.is_empty() {
+                    true => Err(not_found(index)),
+                    false => Ok(json_response(index, &keys)),
+                };
+            }
+
+            let entries: Vec<Value> = match query.flag("recurse") {
+                true => store
+                    .kv_list(key)
+                    .into_iter()
+                    .map(|(k, e)| e.to_json(k))
+                    .collect(),
+                false => store
+                    .kv_get(key)
+                    .map(|e| e.to_json(key))
+                    .into_iter()
+                    .collect(),
+            };
+            if entries.is_empty() {
+                return Err(not_found(index));
+            }
+
+            match query.flag("raw") {
+                true => {
+                    let value = store
+                        .kv_get(key)
+                        .map(|e| e.value.clone())
+                        .unwrap_or_default();
+                    Ok(response(200, index, "application/octet-stream", value))
+                }
+                false => Ok(json_response(index, &entries)),
+            }
+        }
+        Method::PUT => {
+            let opts = KVWrite {
+                acquire: query.get("acquire").map(String::from),
+                cas: query.u64("cas")?,
+                flags: query.u64("flags")?,
+                release: query.get("release").map(String::from),
+            };
+            let success = shared
+                .write(|s| s.kv_put(key, body, opts))
+                .map_err(|(c, m)| error(c, m))?;
+            Ok(json_response(shared.store().index, &success))
+        }
+        Method::DELETE => {
+            let cas = query.u64("cas")?;
+            let success = shared.write(|s| s.kv_delete(key, query.flag("recurse"), cas));
+            Ok(json_response(shared.store().index, &success))
+        }
+        _ => Err(unsupported(method, key)),
+    }
+}
+
+async fn session(
+    shared: &Shared,
+    method: &Method,
+    path: &str,
+    query: &Query,
+    body: &[u8],
+) -> HandlerResult {
+    let (op, arg) = path.split_once('/').unwrap_or((path, ""));
+    match (method, op) {
+        (&Method::PUT, "create") => {
+            let body = parse_body(body)?;
+            let id = shared
+                .write(|s| s.session_create(&body))
+                .map_err(|(c, m)| error(c, m))?;
+            Ok(json_response(shared.store().index, &json!({ "ID": id })))
+        }
+        (&Method::PUT, "destroy") => {
+            let success = shared.write(|s| s.session_destroy(arg));
+            Ok(json_response(shared.store().index, &success))
+        }
+        (&Method::PUT, "renew") => {
+            let store = shared.store();
+            match store.session_get(arg) {
+                Some(s) => Ok(json_response(store.index, &vec![s])),
+                None => Err(error(404, format!("Session id '{}' not found", arg))),
+            }
+        }
+        (&Method::GET, "info") => {
+            shared.block(Table::Sessions, query).await;
+            let store = shared.store();
+            let sessions: Vec<&Value> = store.session_get(arg).into_iter().collect();
+            Ok(json_response(store.table_index(Table::Sessions), &sessions))
+        }
+        (&Method::GET, "list") | (&Method::GET, "node") => {
+            shared.block(Table::Sessions, query).await;
+            let store = shared.store();
+            let node = Some(arg).filter(|_| op == "node");
+            Ok(json_response(
+                store.table_index(Table::Sessions),
+                &store.session_list(node),
+            ))
+        }
+        _ => Err(unsupported(method, path)),
+    }
+}
+
+async fn catalog(
+    shared: &Shared,
+    method: &Method,
+    path: &str,
+    query: &Query,
+    body: &[u8],
+) -> HandlerResult {
+    let (op, arg) = path.split_once('/').unwrap_or((path, ""));
+    match (method, op) {
+        (&Method::PUT, "register") => {
+            let body = parse_body(body)?;
+            shared
+                .write(|s| s.catalog_register(&body))
+                .map_err(|(c, m)| error(c, m))?;
+            Ok(json_response(shared.store().index, &true))
+        }
+        (&Method::PUT, "deregister") => {
+            let body = parse_body(body)?;
+            shared
+                .write(|s| s.catalog_deregister(&body))
+                .map_err(|(c, m)| error(c, m))?;
+            Ok(json_response(shared.store().index, &true))
+        }
+        (&Method::GET, "datacenters") => Ok(json_response(0, &[DATACENTER])),
+        (&Method::GET, _) => {
+            shared.block(Table::Catalog, query).await;
+            let store = shared.store();
+            let index = store.table_index(Table::Catalog);
+            let nodes = store.nodes();
+
+            let response = match op {
+                "nodes" => json!(nodes
+                    .iter()
+                    .map(|(name, n)| n.to_json(name))
+                    .collect::<Vec<_>>()),
+                "services" => {
+                    let mut services = serde_json::Map::new();
+                    for service in nodes.values().flat_map(|n| n.services.values()) {
+                        let name = str_field(service, "Service").unwrap_or_default();
+                        let tags = services.entry(name).or_insert_with(|| json!([]));
+                        for tag in service["Tags"].as_array().into_iter().flatten() {
+                            if let Some(t) = tags.as_array_mut().filter(|t| !t.contains(tag)) {
+                                t.push(tag.clone());
+                            }
+                        }
+                    }
+                    Value::Object(services)
+                }
+                "service" => {
+                    let mut services = Vec::new();
+                    for (name, node) in nodes.iter() {
+                        for s in node.services.values() {
+                            if str_field(s, "Service") == Some(arg) {
+                                services.push(catalog_service(name, node, s));
+                            }
+                        }
+                    }
+                    json!(services)
+                }
+                "node-services" => match nodes.get(arg) {
+                    Some(node) => json!({
+                        "Node": node.to_json(arg),
+                        "Services": node.services.values().collect::<Vec<_>>(),
+                    }),
+                    None => Value::Null,
+                },
+                _ => return Err(unsupported(method, path)),
+            };
+            Ok(json_response(index, &response))
+        }
+        _ => Err(unsupported(method, path)),
+    }
+}
+
+async fn agent(
+    shared: &Shared,
+    method: &Method,
+    path: &str,
+    query: &Query,
+    body: &[u8],
+) -> HandlerResult {
+    let parts: Vec<&str> = path.splitn(3, '/').collect();
+    let result = match (method, parts.as_slice()) {
+        (&Method::PUT, ["service", "register"]) => {
+            let body = parse_body(body)?;
+            shared.write(|s| s.agent_register_service(&body))
+        }
+        (&Method::PUT, ["service", "deregister", id]) => {
+            shared.write(|s| s.agent_deregister_service(id))
+        }
+        (&Method::PUT, ["service", "maintenance", id]) => {
+            let enable = query.flag("enable");
+            shared.write(|s| s.agent_maintenance(id, enable))
+        }
+        (&Method::PUT, ["check", "register"]) => {
+            let body = parse_body(body)?;
+            shared.write(|s| s.agent_register_check(&body))
+        }
+        (&Method::PUT, ["check", "deregister", id]) => {
+            shared.write(|s| s.agent_deregister_check(id))
+        }
+        (&Method::PUT, ["check", op @ ("pass" | "warn" | "fail"), id]) => {
+            let status = match *op {
+                "pass" => "passing",
+                "warn" => "warning",
+                _ => "critical",
+            };
+            shared.write(|s| s.agent_update_check(id, status, query.get("note")))
+        }
+        (&Method::PUT, ["check", "update", id]) => {
+            let body = parse_body(body)?;
+            let status = str_field(&body, "Status").unwrap_or("critical");
+            let output = str_field(&body, "Output");
+            shared.write(|s| s.agent_update_check(id, status, output))
+        }
+        (&Method::GET, _) => {
+            let store = shared.store();
+            let index = store.table_index(Table::Catalog);
+            let node = match store.nodes().get(NODE) {
+                Some(n) => n,
+                None => return Err(error(500, "Missing agent node")),
+            };
+            let service_checks = |id: &str| -> Vec<&Value> {
+                node.checks
+                    .values()
+                    .filter(|c| str_field(c, "ServiceID") == Some(id))
+                    .collect()
+            };
+            let checks_info = |service: &Value| -> Value {
+                let checks = service_checks(str_field(service, "ID").unwrap_or_default());
+                json!({
+                    "AggregatedStatus": aggregate_status(&checks),
+                    "Checks": checks,
+                    "Service": service,
+                })
+            };
+
+            let response = match parts.as_slice() {
+                ["services"] => json!(node.services),
+                ["service", id] => match node.services.get(*id) {
+                    Some(s) => s.clone(),
+                    None => return Err(error(404, format!("unknown service ID: {}", id))),
+                },
+                ["checks"] => json!(node.checks),
+                ["health", "service", rest] => match rest.split_once('/') {
+                    Some(("name", name)) => json!(node
+                        .services
+                        .values()
+                        .filter(|s| str_field(s, "Service") == Some(name))
+                        .map(checks_info)
+                        .collect::<Vec<_>>()),
+                    Some(("id", id)) => match node.services.get(id) {
+                        Some(s) => checks_info(s),
+                        None => return Err(error(404, format!("unknown service ID: {}", id))),
+                    },
+                    _ => return Err(unsupported(method, path)),
+                },
+                _ => return Err(unsupported(method, path)),
+            };
+            return Ok(json_response(index, &response));
+        }
+        _ => return Err(unsupported(method, path)),
+    };
+
+    result.map_err(|(c, m)| error(c, m))?;
+    Ok(response(
+        200,
+        shared.store().index,
+        "text/plain",
+        Vec::new(),
+    ))
+}
+
+async fn health(shared: &Shared, method: &Method, path: &str, query: &Query) -> HandlerResult {
+    let name = match (method, path.strip_prefix("service/")) {
+        (&Method::GET, Some(n)) => n,
+        _ => return Err(unsupported(method, path)),
+    };
+
+    shared.block(Table::Catalog, query).await;
+    let store = shared.store();
+    let mut entries = Vec::new();
+    for (node_name, node) in store.nodes().iter() {
+        for service in node.services.values() {
+            if str_field(service, "Service") != Some(name) {
+                continue;
+            }
+
+            let id = str_field(service, "ID").unwrap_or_default();
+            let checks: Vec<&Value> = node
+                .checks
+                .values()
+                .filter(|c| {
+                    let service_id = str_field(c, "ServiceID").unwrap_or_default();
+                    service_id.is_empty() || service_id == id
+                })
+                .collect();
+            if query.flag("passing") && aggregate_status(&checks) != "passing" {
+                continue;
+            }
+            entries.push(json!({
+                "Checks": checks,
+                "Node": node.to_json(node_name),
+                "Service": service,
+            }));
+        }
+    }
+
+    Ok(json_response(store.table_index(Table::Catalog), &entries))
+}
+
+fn snapshot(shared: &Shared, method: &Method, body: Vec<u8>) -> HandlerResult {
+    match *method {
+        Method::GET => {
+            let store = shared.store();
+            let archive = store.snapshot().map_err(|(c, m)| error(c, m))?;
+            Ok(response(200, store.index, "application/x-gzip", archive))
+        }
+        Method::PUT => {
+            shared.write(|s| s.snapshot_restore(body));
+            Ok(response(
+                200,
+                shared.store().index,
+                "text/plain",
+                Vec::new(),
+            ))
+        }
+        _ => Err(unsupported(method, "snapshot")),
+    }
+}
+
+fn txn(shared: &Shared, method: &Method, body: &[u8]) -> HandlerResult {
+    if *method != Method::PUT {
+        return Err(unsupported(method, "txn"));
+    }
+    if body.len() > TXN_MAX_REQ_LEN {
+        return Err(error(
+            413,
+            format!(
+                "Request body({} bytes) too large, max size: {} bytes",
+                body.len(),
+                TXN_MAX_REQ_LEN
+            ),
+        ));
+    }
+    let ops = match parse_body(body)? {
+        Value::Array(ops) => ops,
+        _ => {
+            return Err(error(
+                400,
+                "Failed to parse body: expected a list of operations",
+            ))
+        }
+    };
+    if ops.len() > 64 {
+        return Err(error(
+            413,
+            format!(
+                "Transaction contains too many operations ({} > 64)",
+                ops.len()
+            ),
+        ));
+    }
+
+    match shared.write(|s| s.txn(&ops)) {
+        Ok(results) => Ok(json_response(
+            shared.store().index,
+            &json!({ "Results": results, "Errors": null }),
+        )),
+        Err(errors) => {
+            let body = json!({ "Results": null, "Errors": errors }).to_string();
+            Err(Rejection {
+                index: shared.store().index,
+                message: body,
+                status: 409,
+            })
+        }
+    }
+}
+
+fn status(method: &Method, path: &str) -> HandlerResult {
+    let leader = format!("{}:8300", NODE_ADDRESS);
+    match (method, path) {
+        (&Method::GET, "leader") => Ok(json_response(0, &leader)),
+        (&Method::GET, "peers") => Ok(json_response(0, &[leader])),
+        _ => Err(unsupported(method, path)),
+    }
+}
+
+/// Returns the representation of a service used by the catalog endpoints.
+fn catalog_service(node_name: &str, node: &store::NodeEntry, service: &Value) -> Value {
+    json!({
+        "Address": node.address,
+        "CreateIndex": service["CreateIndex"],
+        "Datacenter": DATACENTER,
+        "ID": node.id,
+        "ModifyIndex": service["ModifyIndex"],
+        "Node": node_name,
+        "NodeMeta": node.meta,
+        "ServiceAddress": service["Address"],
+        "ServiceEnableTagOverride": service["EnableTagOverride"],
+        "ServiceID": service["ID"],
+        "ServiceMeta": service["Meta"],
+        "ServiceName": service["Service"],
+        "ServicePort": service["Port"],
+        "ServiceTags": service["Tags"],
+        "ServiceWeights": service["Weights"],
+        "TaggedAddresses": node.tagged_addresses,
+    })
+}
+
+/// Returns the worst status out of the given checks.
+fn aggregate_status(checks: &[&Value]) -> &'static str {
+    let statuses: Vec<&str> = checks
+        .iter()
+        .filter_map(|c| str_field(c, "Status"))
+        .collect();
+    if statuses.contains(&"critical") {
+        "critical"
+    } else if statuses.contains(&"warning") {
+        "warning"
+    } else {
+        "passing"
+    }
+}
+
+fn parse_body(body: &[u8]) -> Result<Value, Rejection> {
+    if body.is_empty() {
+        return Ok(json!({}));
+    }
+    serde_json::from_slice(body).map_err(|e| error(400, format!("Request decode failed: {}", e)))
+}
+
+fn response(status: u16, index: u64, content_type: &str, body: Vec<u8>) -> Response<Body> {
+    let mut res = Response::new(Body::from(body));
+    *res.status_mut() = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let headers = res.headers_mut();
+    if let Ok(v) = content_type.parse() {
+        headers.insert(CONTENT_TYPE, v);
+    }
+    headers.insert("X-Consul-Index", index.max(1).into());
+    headers.insert("X-Consul-KnownLeader", "true".parse().unwrap());
+    headers.insert("X-Consul-LastContact", 0.into());
+    res
+}
+
+fn json_response<T: Serialize + ?Sized>(index: u64, body: &T) -> Response<Body> {
+    match serde_json::to_vec(body) {
+        Ok(b) => response(200, index, "application/json", b),
+        Err(e) => error(500, format!("Failed encoding response: {}", e)).into(),
+    }
+}
+
+fn error(status: u16, message: impl Into<String>) -> Rejection {
+    Rejection {
+        index: 0,
+        message: message.into(),
+        status,
+    }
+}
+
+fn not_found(index: u64) -> Rejection {
+    Rejection {
+        index,
+        message: String::new(),
+        status: 404,
+    }
+}
+
+fn unsupported(method: &Method, path: &str) -> Rejection {
+    error(
+        404,
+        format!(
+            "Unsupported by the consulrs test server: {} {}",
+            method, path
+        ),
+    )
+}
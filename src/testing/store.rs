@@ -0,0 +1,999 @@
+use std::collections::BTreeMap;
+
+use flate2::{write::GzEncoder, Compression};
+use rand::Rng;
+use rmpv::Value as MsgValue;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::api::GoDuration;
+
+/// The name of the node which the test server's agent runs on.
+pub const NODE: &str = "consulrs-test";
+
+/// The address of the node which the test server's agent runs on.
+pub const NODE_ADDRESS: &str = "127.0.0.1";
+
+/// The name of the datacenter the test server belongs to.
+pub const DATACENTER: &str = "dc1";
+
+/// An error returned by the [Store], consisting of a status code and message.
+pub type StoreError = (u16, String);
+
+/// The tables which are tracked for blocking queries.
+#[derive(Clone, Copy, Debug)]
+pub enum Table {
+    Catalog,
+    KV,
+    Sessions,
+}
+
+/// A single entry in the KV store.
+#[derive(Clone, Debug)]
+pub struct KVEntry {
+    pub create_index: u64,
+    pub flags: u64,
+    pub lock_index: u64,
+    pub modify_index: u64,
+    pub session: Option<String>,
+    pub value: Vec<u8>,
+}
+
+impl KVEntry {
+    /// Returns the JSON representation of this entry as returned by Consul.
+    pub fn to_json(&self, key: &str) -> Value {
+        let value = match self.value.is_empty() {
+            true => Value::Null,
+            false => Value::String(base64::encode(&self.value)),
+        };
+        let mut obj = json!({
+            "CreateIndex": self.create_index,
+            "Flags": self.flags,
+            "Key": key,
+            "LockIndex": self.lock_index,
+            "ModifyIndex": self.modify_index,
+            "Value": value,
+        });
+        if let Some(s) = &self.session {
+            obj["Session"] = Value::String(s.clone());
+        }
+        obj
+    }
+}
+
+/// Options which modify how a key is written.
+#[derive(Debug, Default)]
+pub struct KVWrite {
+    pub acquire: Option<String>,
+    pub cas: Option<u64>,
+    pub flags: Option<u64>,
+    pub release: Option<String>,
+}
+
+/// A node registered in the catalog along with its services and checks.
+#[derive(Clone, Debug)]
+pub struct NodeEntry {
+    pub address: String,
+    pub checks: BTreeMap<String, Value>,
+    pub create_index: u64,
+    pub id: String,
+    pub meta: Value,
+    pub modify_index: u64,
+    pub services: BTreeMap<String, Value>,
+    pub tagged_addresses: Value,
+}
+
+impl NodeEntry {
+    /// Returns the JSON representation of this node as returned by Consul.
+    pub fn to_json(&self, name: &str) -> Value {
+        json!({
+            "Address": self.address,
+            "CreateIndex": self.create_index,
+            "Datacenter": DATACENTER,
+            "ID": self.id,
+            "Meta": self.meta,
+            "ModifyIndex": self.modify_index,
+            "Node": name,
+            "TaggedAddresses": self.tagged_addresses,
+        })
+    }
+}
+
+/// The in-memory state of a test server.
+///
+/// Every write increments the global index, which is recorded against the
+/// modified table so that blocking queries can determine whether the data they
+/// are watching has changed.
+#[derive(Debug)]
+pub struct Store {
+    pub index: u64,
+    catalog_index: u64,
+    kv: BTreeMap<String, KVEntry>,
+    kv_index: u64,
+    nodes: BTreeMap<String, NodeEntry>,
+    session_index: u64,
+    sessions: BTreeMap<String, Value>,
+    snapshot: Vec<u8>,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        let mut store = Store {
+            index: 1,
+            catalog_index: 1,
+            kv: BTreeMap::new(),
+            kv_index: 1,
+            nodes: BTreeMap::new(),
+            session_index: 1,
+            sessions: BTreeMap::new(),
+            snapshot: Vec::new(),
+        };
+
+        // Register the agent's own node along with its serf health check
+        let index = store.bump(Table::Catalog);
+        let mut node = NodeEntry {
+            address: NODE_ADDRESS.into(),
+            checks: BTreeMap::new(),
+            create_index: index,
+            id: uuid(),
+            meta: json!({}),
+            modify_index: index,
+            services: BTreeMap::new(),
+            tagged_addresses: json!({ "lan": NODE_ADDRESS, "wan": NODE_ADDRESS }),
+        };
+        node.checks.insert(
+            "serfHealth".into(),
+            json!({
+                "CheckID": "serfHealth",
+                "CreateIndex": index,
+                "ModifyIndex": index,
+                "Name": "Serf Health Status",
+                "Node": NODE,
+                "Notes": "",
+                "Output": "Agent alive and reachable",
+                "ServiceID": "",
+                "ServiceName": "",
+                "ServiceTags": [],
+                "Status": "passing",
+                "Type": "",
+            }),
+        );
+        store.nodes.insert(NODE.into(), node);
+        store
+    }
+}
+
+impl Store {
+    /// Returns the index of the last write to the given table.
+    pub fn table_index(&self, table: Table) -> u64 {
+        match table {
+            Table::Catalog => self.catalog_index,
+            Table::KV => self.kv_index,
+            Table::Sessions => self.session_index,
+        }
+    }
+
+    /// Increments the global index and records it against the given table.
+    fn bump(&mut self, table: Table) -> u64 {
+        self.index += 1;
+        match table {
+            Table::Catalog => self.catalog_index = self.index,
+            Table::KV => self.kv_index = self.index,
+            Table::Sessions => self.session_index = self.index,
+        }
+        self.index
+    }
+
+    /// Returns the entry at the given key.
+    pub fn kv_get(&self, key: &str) -> Option<&KVEntry> {
+        self.kv.get(key)
+    }
+
+    /// Returns all entries with keys starting with the given prefix.
+    pub fn kv_list(&self, prefix: &str) -> Vec<(&String, &KVEntry)> {
+        self.kv
+            .range(prefix.to_string()..)
+            .take_while(|(k, _)| k.starts_with(prefix))
+            .collect()
+    }
+
+    /// Returns all keys starting with the given prefix. If a separator is
+    /// given, keys are rolled up to the first occurrence of the separator
+    /// after the prefix.
+    pub fn kv_keys(&self, prefix: &str, separator: Option<&str>) -> Vec<String> {
+        let mut keys = Vec::<String>::new();
+        for (key, _) in self.kv_list(prefix) {
+            let key = match separator.filter(|s| !s.is_empty()) {
+                Some(sep) => match key[prefix.len()..].find(sep) {
+                    Some(i) => key[..prefix.len() + i + sep.len()].to_string(),
+                    None => key.clone(),
+                },
+                None => key.clone(),
+            };
+            if keys.last() != Some(&key) {
+                keys.push(key);
+            }
+        }
+        keys
+    }
+
+    /// Writes a value to the given key, returning whether the write succeeded.
+    pub fn kv_put(&mut self, key: &str, value: Vec<u8>, opts: KVWrite) -> Result<bool, StoreError> {
+        if opts.acquire.is_some() && opts.release.is_some() {
+            return Err((400, "Conflicting flags: acquire and release".into()));
+        }
+
+        let existing = self.kv.get(key);
+        if let Some(cas) = opts.cas {
+            let current = existing.map(|e| e.modify_index).unwrap_or(0);
+            if cas != current {
+                return Ok(false);
+            }
+        }
+
+        let mut lock_index = existing.map(|e| e.lock_index).unwrap_or(0);
+        let mut session = existing.and_then(|e| e.session.clone());
+        if let Some(s) = &opts.acquire {
+            if !self.sessions.contains_key(s) {
+                return Err((500, format!("invalid session \"{}\"", s)));
+            }
+            match &session {
+                Some(holder) if holder != s => return Ok(false),
+                Some(_) => {}
+                None => {
+                    lock_index += 1;
+                    session = Some(s.clone());
+                }
+            }
+        }
+        if let Some(s) = &opts.release {
+            match &session {
+                Some(holder) if holder == s => session = None,
+                _ => return Ok(false),
+            }
+        }
+
+        let create_index = existing.map(|e| e.create_index);
+        let flags = opts
+            .flags
+            .unwrap_or_else(|| existing.map(|e| e.flags).unwrap_or(0));
+        let index = self.bump(Table::KV);
+        self.kv.insert(
+            key.into(),
+            KVEntry {
+                create_index: create_index.unwrap_or(index),
+                flags,
+                lock_index,
+                modify_index: index,
+                session,
+                value,
+            },
+        );
+        Ok(true)
+    }
+
+    /// Deletes the given key, or all keys under it when `recurse` is set,
+    /// returning whether the delete succeeded.
+    pub fn kv_delete(&mut self, key: &str, recurse: bool, cas: Option<u64>) -> bool {
+        if let Some(cas) = cas {
+            let current = self.kv.get(key).map(|e| e.modify_index).unwrap_or(0);
+            if cas != current {
+                return false;
+            }
+        }
+
+        let keys: Vec<String> = match recurse {
+            true => self
+                .kv_list(key)
+                .into_iter()
+                .map(|(k, _)| k.clone())
+                .collect(),
+            false => vec![key.to_string()],
+        };
+        for k in keys.iter() {
+            self.kv.remove(k);
+        }
+        self.bump(Table::KV);
+        true
+    }
+
+    /// Applies the KV operations of a transaction, returning the result of
+    /// each operation. If any operation fails the store is rolled back and the
+    /// errors are returned instead.
+    pub fn txn(&mut self, ops: &[Value]) -> Result<Vec<Value>, Vec<Value>> {
+        let saved = (self.kv.clone(), self.index, self.kv_index);
+        let mut results = Vec::new();
+        let mut errors = Vec::new();
+        for (i, op) in ops.iter().enumerate() {
+            match self.txn_op(op) {
+                Ok(r) => results.extend(r),
+                Err(what) => errors.push(json!({ "OpIndex": i, "What": what })),
+            }
+        }
+
+        if errors.is_empty() {
+            return Ok(results);
+        }
+        let (kv, index, kv_index) = saved;
+        self.kv = kv;
+        self.index = index;
+        self.kv_index = kv_index;
+        Err(errors)
+    }
+
+    fn txn_op(&mut self, op: &Value) -> Result<Vec<Value>, String> {
+        let op = op
+            .get("KV")
+            .ok_or("Only KV operations are supported by the test server")?;
+        let verb = str_field(op, "Verb").unwrap_or_default();
+        let key = str_field(op, "Key").unwrap_or_default();
+        let index = op.get("Index").and_then(Value::as_u64).unwrap_or(0);
+        let session = str_field(op, "Session").map(String::from);
+        let value = match str_field(op, "Value") {
+            Some(v) => base64::decode(v).map_err(|e| e.to_string())?,
+            None => Vec::new(),
+        };
+        let opts = KVWrite {
+            flags: op.get("Flags").and_then(Value::as_u64),
+            ..Default::default()
+        };
+
+        let written = match verb {
+            "set" => self.kv_put(key, value, opts),
+            "cas" => self.kv_put(
+                key,
+                value,
+                KVWrite {
+                    cas: Some(index),
+                    ..opts
+                },
+            ),
+            "lock" => self.kv_put(
+                key,
+                value,
+                KVWrite {
+                    acquire: session,
+                    ..opts
+                },
+            ),
+            "unlock" => self.kv_put(
+                key,
+                value,
+                KVWrite {
+                    release: session,
+                    ..opts
+                },
+            ),
+            "delete" | "delete-tree" => {
+                self.kv_delete(key, verb == "delete-tree", None);
+                return Ok(Vec::new());
+            }
+            "delete-cas" => match self.kv_delete(key, false, Some(index)) {
+                true => return Ok(Vec::new()),
+                false => return Err(format!("failed to delete key {:?}, index is stale", key)),
+            },
+            _ => return self.txn_check(verb, key, index, session),
+        };
+        match written.map_err(|(_, m)| m)? {
+            true => Ok(self.txn_result(key, false).into_iter().collect()),
+            false => Err(format!("failed to {} key {:?}", verb, key)),
+        }
+    }
+
+    /// Applies the read-only operations of a transaction.
+    fn txn_check(
+        &self,
+        verb: &str,
+        key: &str,
+        index: u64,
+        session: Option<String>,
+    ) -> Result<Vec<Value>, String> {
+        let entry = self.kv_get(key);
+        let missing = || format!("key {:?} doesn't exist", key);
+        match verb {
+            "get" => self
+                .txn_result(key, true)
+                .map(|r| vec![r])
+                .ok_or_else(missing),
+            "get-tree" => Ok(self
+                .kv_list(key)
+                .into_iter()
+                .filter_map(|(k, _)| self.txn_result(k, true))
+                .collect()),
+            "check-index" => match entry.ok_or_else(missing)?.modify_index {
+                i if i == index => self
+                    .txn_result(key, false)
+                    .map(|r| vec![r])
+                    .ok_or_else(missing),
+                i => Err(format!("current modify index {} != {}", i, index)),
+            },
+            "check-session" => match entry.ok_or_else(missing)?.session == session {
+                true => self
+                    .txn_result(key, false)
+                    .map(|r| vec![r])
+                    .ok_or_else(missing),
+                false => Err(format!("key {:?} is not locked by the session", key)),
+            },
+            "check-not-exists" => match entry {
+                Some(_) => Err(format!("key {:?} exists", key)),
+                None => Ok(Vec::new()),
+            },
+            _ => Err(format!("unknown KV verb {:?}", verb)),
+        }
+    }
+
+    /// Returns the result of a transaction operation on the given key, which
+    /// only includes the value for reads.
+    fn txn_result(&self, key: &str, with_value: bool) -> Option<Value> {
+        let mut entry = self.kv_get(key)?.to_json(key);
+        if !with_value {
+            entry["Value"] = Value::Null;
+        }
+        Some(json!({ "KV": entry }))
+    }
+
+    /// Creates a new session from the given request body, returning its ID.
+    pub fn session_create(&mut self, body: &Value) -> Result<String, StoreError> {
+        let node = str_field(body, "Node").unwrap_or(NODE);
+        if !self.nodes.contains_key(node) {
+            return Err((500, format!("Missing node registration: {}", node)));
+        }
+
+        let id = uuid();
+        let index = self.bump(Table::Sessions);
+        let session = json!({
+            "Behavior": str_field(body, "Behavior").unwrap_or("release"),
+            "CreateIndex": index,
+            "ID": id,
+            "LockDelay": lock_delay(body.get("LockDelay")),
+            "ModifyIndex": index,
+            "Name": str_field(body, "Name").unwrap_or(""),
+            "Node": node,
+            "NodeChecks": body.get("NodeChecks").cloned().unwrap_or_else(|| json!(["serfHealth"])),
+            "ServiceChecks": body.get("ServiceChecks").cloned().unwrap_or(Value::Null),
+            "TTL": str_field(body, "TTL").unwrap_or(""),
+        });
+        self.sessions.insert(id.clone(), session);
+        Ok(id)
+    }
+
+    /// Destroys the given session, releasing or deleting any keys it holds
+    /// depending on the session's behavior.
+    pub fn session_destroy(&mut self, id: &str) -> bool {
+        let session = match self.sessions.remove(id) {
+            Some(s) => s,
+            None => return true,
+        };
+
+        let delete = str_field(&session, "Behavior") == Some("delete");
+        let held: Vec<String> = self
+            .kv
+            .iter()
+            .filter(|(_, e)| e.session.as_deref() == Some(id))
+            .map(|(k, _)| k.clone())
+            .collect();
+        if !held.is_empty() {
+            let index = self.bump(Table::KV);
+            for key in held {
+                if delete {
+                    self.kv.remove(&key);
+                } else if let Some(e) = self.kv.get_mut(&key) {
+                    e.session = None;
+                    e.modify_index = index;
+                }
+            }
+        }
+
+        self.bump(Table::Sessions);
+        true
+    }
+
+    /// Returns the session with the given ID.
+    pub fn session_get(&self, id: &str) -> Option<&Value> {
+        self.sessions.get(id)
+    }
+
+    /// Returns all sessions, optionally limited to those on the given node.
+    pub fn session_list(&self, node: Option<&str>) -> Vec<Value> {
+        self.sessions
+            .values()
+            .filter(|s| node.is_none() || str_field(s, "Node") == node)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns a snapshot archive of the store.
+    ///
+    /// Restoring a snapshot stores the archive as-is without changing any
+    /// other state, in which case the restored archive is returned. Otherwise
+    /// an archive in Consul's format is generated which contains the nodes,
+    /// KV entries and sessions in the store.
+    pub fn snapshot(&self) -> Result<Vec<u8>, StoreError> {
+        if !self.snapshot.is_empty() {
+            return Ok(self.snapshot.clone());
+        }
+
+        let mut state = Vec::new();
+        let mut record = |ty: Option<u8>, value: Vec<(&str, MsgValue)>| {
+            state.extend(ty);
+            let map = value.into_iter().map(|(k, v)| (k.into(), v)).collect();
+            rmpv::encode::write_value(&mut state, &MsgValue::Map(map))
+                .map_err(|e| (500, format!("Failed writing snapshot: {}", e)))
+        };
+        let archive_err = |e: std::io::Error| (500, format!("Failed writing snapshot: {}", e));
+
+        record(None, vec![("LastIndex", self.index.into())])?;
+        for (name, node) in self.nodes.iter() {
+            record(
+                Some(0),
+                vec![
+                    ("ID", node.id.as_str().into()),
+                    ("Node", name.as_str().into()),
+                    ("Address", node.address.as_str().into()),
+                ],
+            )?;
+        }
+        for (key, entry) in self.kv.iter() {
+            record(
+                Some(2),
+                vec![
+                    ("Key", key.as_str().into()),
+                    ("Value", entry.value.clone().into()),
+                    ("Flags", entry.flags.into()),
+                    (
+                        "Session",
+                        entry.session.as_deref().unwrap_or_default().into(),
+                    ),
+                    ("LockIndex", entry.lock_index.into()),
+                    ("CreateIndex", entry.create_index.into()),
+                    ("ModifyIndex", entry.modify_index.into()),
+                ],
+            )?;
+        }
+        for session in self.sessions.values() {
+            let fields = session
+                .as_object()
+                .map(|o| o.iter().map(|(k, v)| (k.as_str(), msgpack(v))).collect())
+                .unwrap_or_default();
+            record(Some(3), fields)?;
+        }
+        for (table, index) in [
+            ("nodes", self.catalog_index),
+            ("kvs", self.kv_index),
+            ("sessions", self.session_index),
+        ]
+        .iter()
+        {
+            record(
+                Some(16),
+                vec![("Key", (*table).into()), ("Value", (*index).into())],
+            )?;
+        }
+
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let meta = serde_json::to_vec(&json!({
+            "Version": 1,
+            "ID": format!("1-{}-{}", self.index, millis),
+            "Index": self.index,
+            "Term": 1,
+            "Configuration": {
+                "Servers": [{
+                    "Suffrage": 0,
+                    "ID": NODE,
+                    "Address": format!("{}:8300", NODE_ADDRESS),
+                }],
+            },
+            "ConfigurationIndex": 1,
+            "Size": state.len(),
+        }))
+        .map_err(|e| (500, format!("Failed writing snapshot: {}", e)))?;
+        let sums = format!(
+            "{}  meta.json\n{}  state.bin\n",
+            hex::encode(Sha256::digest(&meta)),
+            hex::encode(Sha256::digest(&state))
+        );
+
+        let mut archive = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (name, content) in [
+            ("meta.json", meta.as_slice()),
+            ("state.bin", state.as_slice()),
+            ("SHA256SUMS", sums.as_bytes()),
+        ]
+        .iter()
+        {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o600);
+            header.set_mtime((millis / 1000) as u64);
+            archive
+                .append_data(&mut header, name, *content)
+                .map_err(archive_err)?;
+        }
+        archive
+            .into_inner()
+            .and_then(|gz| gz.finish())
+            .map_err(archive_err)
+    }
+
+    /// Stores the given snapshot archive.
+    pub fn snapshot_restore(&mut self, data: Vec<u8>) {
+        self.index += 1;
+        self.snapshot = data;
+    }
+
+    /// Returns all nodes in the catalog.
+    pub fn nodes(&self) -> &BTreeMap<String, NodeEntry> {
+        &self.nodes
+    }
+
+    /// Registers a node, and optionally a service and checks, in the catalog.
+    pub fn catalog_register(&mut self, body: &Value) -> Result<(), StoreError> {
+        let name = str_field(body, "Node").ok_or((400, "Must provide node".to_string()))?;
+        let address =
+            str_field(body, "Address").ok_or((400, "Must provide address".to_string()))?;
+        let index = self.bump(Table::Catalog);
+
+        let node = self
+            .nodes
+            .entry(name.to_string())
+            .or_insert_with(|| NodeEntry {
+                address: address.to_string(),
+                checks: BTreeMap::new(),
+                create_index: index,
+                id: uuid(),
+                meta: json!({}),
+                modify_index: index,
+                services: BTreeMap::new(),
+                tagged_addresses: json!({}),
+            });
+        node.address = address.to_string();
+        node.modify_index = index;
+        if let Some(m) = body.get("NodeMeta").filter(|m| !m.is_null()) {
+            node.meta = m.clone();
+        }
+        if let Some(t) = body.get("TaggedAddresses").filter(|t| !t.is_null()) {
+            node.tagged_addresses = t.clone();
+        }
+
+        if let Some(service) = body.get("Service").filter(|s| !s.is_null()) {
+            let service = normalize_service(service, index);
+            let id = str_field(&service, "ID").unwrap_or_default().to_string();
+            node.services.insert(id, service);
+        }
+
+        let mut checks: Vec<Value> = Vec::new();
+        if let Some(c) = body.get("Check").filter(|c| !c.is_null()) {
+            checks.push(c.clone());
+        }
+        if let Some(Value::Array(c)) = body.get("Checks") {
+            checks.extend(c.iter().cloned());
+        }
+        for check in checks {
+            let check = normalize_check(&check, name, node, index);
+            let id = str_field(&check, "CheckID").unwrap_or_default().to_string();
+            node.checks.insert(id, check);
+        }
+
+        Ok(())
+    }
+
+    /// Removes a node, or one of its services or checks, from the catalog.
+    pub fn catalog_deregister(&mut self, body: &Value) -> Result<(), StoreError> {
+        let name = str_field(body, "Node").ok_or((400, "Must provide node".to_string()))?;
+        let service = str_field(body, "ServiceID");
+        let check = str_field(body, "CheckID");
+
+        match (service, check) {
+            (Some(s), _) => {
+                if let Some(node) = self.nodes.get_mut(name) {
+                    node.services.remove(s);
+                    node.checks
+                        .retain(|_, c| str_field(c, "ServiceID") != Some(s));
+                }
+            }
+            (None, Some(c)) => {
+                if let Some(node) = self.nodes.get_mut(name) {
+                    node.checks.remove(c);
+                }
+            }
+            (None, None) => {
+                self.nodes.remove(name);
+            }
+        }
+
+        self.bump(Table::Catalog);
+        Ok(())
+    }
+
+    /// Registers a service, along with any checks it defines, on the agent.
+    pub fn agent_register_service(&mut self, body: &Value) -> Result<(), StoreError> {
+        let name = str_field(body, "Name").ok_or((400, "Missing service name".to_string()))?;
+        let id = str_field(body, "ID").unwrap_or(name).to_string();
+
+        let mut service = body.clone();
+        if let Some(obj) = service.as_object_mut() {
+            obj.remove("Check");
+            obj.remove("Checks");
+            obj.remove("Name");
+            obj.insert("ID".into(), Value::String(id.clone()));
+            obj.insert("Service".into(), Value::String(name.into()));
+        }
+
+        let mut checks: Vec<Value> = Vec::new();
+        if let Some(c) = body.get("Check").filter(|c| !c.is_null()) {
+            checks.push(c.clone());
+        }
+        if let Some(Value::Array(c)) = body.get("Checks") {
+            checks.extend(c.iter().cloned());
+        }
+        let count = checks.len();
+
+        let index = self.bump(Table::Catalog);
+        let node = self
+            .nodes
+            .get_mut(NODE)
+            .ok_or((500, "Missing agent node".to_string()))?;
+        node.services
+            .insert(id.clone(), normalize_service(&service, index));
+        for (i, mut check) in checks.into_iter().enumerate() {
+            if let Some(obj) = check.as_object_mut() {
+                let check_id = match count {
+                    1 => format!("service:{}", id),
+                    _ => format!("service:{}:{}", id, i + 1),
+                };
+                obj.entry("CheckID").or_insert(Value::String(check_id));
+                obj.entry("Name")
+                    .or_insert(Value::String(format!("Service '{}' check", name)));
+                obj.insert("ServiceID".into(), Value::String(id.clone()));
+            }
+            let check = normalize_check(&check, NODE, node, index);
+            let check_id = str_field(&check, "CheckID").unwrap_or_default().to_string();
+            node.checks.insert(check_id, check);
+        }
+
+        Ok(())
+    }
+
+    /// Removes a service, along with its checks, from the agent.
+    pub fn agent_deregister_service(&mut self, id: &str) -> Result<(), StoreError> {
+        let node = self
+            .nodes
+            .get_mut(NODE)
+            .ok_or((500, "Missing agent node".to_string()))?;
+        if node.services.remove(id).is_none() {
+            return Err((404, format!("Unknown service ID \"{}\"", id)));
+        }
+        node.checks
+            .retain(|_, c| str_field(c, "ServiceID") != Some(id));
+        self.bump(Table::Catalog);
+        Ok(())
+    }
+
+    /// Registers a check on the agent.
+    pub fn agent_register_check(&mut self, body: &Value) -> Result<(), StoreError> {
+        let name = str_field(body, "Name").ok_or((400, "Missing check name".to_string()))?;
+        let mut check = body.clone();
+        if let Some(obj) = check.as_object_mut() {
+            let id = str_field(body, "ID").unwrap_or(name).to_string();
+            obj.remove("ID");
+            obj.insert("CheckID".into(), Value::String(id));
+        }
+
+        let index = self.bump(Table::Catalog);
+        let node = self
+            .nodes
+            .get_mut(NODE)
+            .ok_or((500, "Missing agent node".to_string()))?;
+        if let Some(s) = str_field(&check, "ServiceID") {
+            if !node.services.contains_key(s) {
+                return Err((500, format!("ServiceID \"{}\" does not exist", s)));
+            }
+        }
+        let check = normalize_check(&check, NODE, node, index);
+        let id = str_field(&check, "CheckID").unwrap_or_default().to_string();
+        node.checks.insert(id, check);
+        Ok(())
+    }
+
+    /// Removes a check from the agent.
+    pub fn agent_deregister_check(&mut self, id: &str) -> Result<(), StoreError> {
+        let node = self
+            .nodes
+            .get_mut(NODE)
+            .ok_or((500, "Missing agent node".to_string()))?;
+        if node.checks.remove(id).is_none() {
+            return Err((404, format!("Unknown check ID \"{}\"", id)));
+        }
+        self.bump(Table::Catalog);
+        Ok(())
+    }
+
+    /// Updates the status and output of a check on the agent.
+    pub fn agent_update_check(
+        &mut self,
+        id: &str,
+        status: &str,
+        output: Option<&str>,
+    ) -> Result<(), StoreError> {
+        let index = self.bump(Table::Catalog);
+        let node = self
+            .nodes
+            .get_mut(NODE)
+            .ok_or((500, "Missing agent node".to_string()))?;
+        let check = node
+            .checks
+            .get_mut(id)
+            .ok_or((404, format!("Unknown check ID \"{}\"", id)))?;
+        check["Status"] = Value::String(status.into());
+        check["Output"] = Value::String(output.unwrap_or_default().into());
+        check["ModifyIndex"] = json!(index);
+        Ok(())
+    }
+
+    /// Enables or disables maintenance mode for a service on the agent.
+    pub fn agent_maintenance(&mut self, id: &str, enable: bool) -> Result<(), StoreError> {
+        let index = self.bump(Table::Catalog);
+        let node = self
+            .nodes
+            .get_mut(NODE)
+            .ok_or((500, "Missing agent node".to_string()))?;
+        if !node.services.contains_key(id) {
+            return Err((404, format!("Unknown service ID \"{}\"", id)));
+        }
+
+        let check_id = format!("_service_maintenance:{}", id);
+        if enable {
+            let check = json!({
+                "CheckID": check_id,
+                "Name": "Service Maintenance Mode",
+                "Notes": "Maintenance mode is enabled for this service",
+                "ServiceID": id,
+                "Status": "critical",
+            });
+            let check = normalize_check(&check, NODE, node, index);
+            node.checks.insert(check_id, check);
+        } else {
+            node.checks.remove(&check_id);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the string value of a field in a JSON object.
+pub fn str_field<'a>(value: &'a Value, field: &str) -> Option<&'a str> {
+    value.get(field).and_then(|v| v.as_str())
+}
+
+/// Fills in the defaults Consul sets on a registered service.
+fn normalize_service(service: &Value, index: u64) -> Value {
+    let mut service = service.clone();
+    let obj = match service.as_object_mut() {
+        Some(o) => o,
+        None => return service,
+    };
+
+    let name = obj
+        .get("Service")
+        .or_else(|| obj.get("Name"))
+        .cloned()
+        .unwrap_or_else(|| Value::String(String::new()));
+    obj.remove("Name");
+    obj.insert("Service".into(), name.clone());
+    obj.entry("ID").or_insert(name);
+    set_default(obj, "Address", json!(""));
+    set_default(obj, "Datacenter", json!(DATACENTER));
+    set_default(obj, "EnableTagOverride", json!(false));
+    set_default(obj, "Meta", json!({}));
+    set_default(obj, "Port", json!(0));
+    set_default(obj, "Tags", json!([]));
+    set_default(obj, "Weights", json!({ "Passing": 1, "Warning": 1 }));
+    obj.insert("CreateIndex".into(), json!(index));
+    obj.insert("ModifyIndex".into(), json!(index));
+    service
+}
+
+/// Fills in the defaults Consul sets on a registered check.
+fn normalize_check(check: &Value, node_name: &str, node: &NodeEntry, index: u64) -> Value {
+    let mut check = check.clone();
+    let obj = match check.as_object_mut() {
+        Some(o) => o,
+        None => return check,
+    };
+
+    let service_id = obj
+        .get("ServiceID")
+        .and_then(|s| s.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let service = node.services.get(&service_id);
+    let ty = ["HTTP", "TCP", "TTL", "GRPC", "Args", "AliasService"]
+        .iter()
+        .find(|k| obj.get(**k).map(|v| !v.is_null()).unwrap_or(false))
+        .map(|k| match *k {
+            "Args" => "script".to_string(),
+            "AliasService" => "alias".to_string(),
+            k => k.to_lowercase(),
+        })
+        .unwrap_or_default();
+
+    set_default(obj, "Name", json!(""));
+    set_default(obj, "Notes", json!(""));
+    set_default(obj, "Output", json!(""));
+    set_default(obj, "Status", json!("critical"));
+    set_default(obj, "Type", json!(ty));
+    obj.insert("Node".into(), json!(node_name));
+    obj.insert("ServiceID".into(), json!(service_id));
+    obj.insert(
+        "ServiceName".into(),
+        service
+            .and_then(|s| s.get("Service"))
+            .cloned()
+            .unwrap_or_else(|| json!("")),
+    );
+    obj.insert(
+        "ServiceTags".into(),
+        service
+            .and_then(|s| s.get("Tags"))
+            .cloned()
+            .unwrap_or_else(|| json!([])),
+    );
+    obj.insert("CreateIndex".into(), json!(index));
+    obj.insert("ModifyIndex".into(), json!(index));
+    check
+}
+
+/// Sets a field on a JSON object if it's missing or null.
+fn set_default(obj: &mut Map<String, Value>, key: &str, value: Value) {
+    match obj.get(key) {
+        Some(v) if !v.is_null() => {}
+        _ => {
+            obj.insert(key.into(), value);
+        }
+    }
+}
+
+/// Converts a session lock delay into nanoseconds, defaulting to 15 seconds.
+fn lock_delay(value: Option<&Value>) -> u64 {
+    const DEFAULT: u64 = 15_000_000_000;
+    match value {
+        Some(Value::Number(n)) => n.as_u64().unwrap_or(DEFAULT),
+        Some(Value::String(s)) => s
+            .parse::<GoDuration>()
+            .map(|d| d.as_duration().as_nanos() as u64)
+            .unwrap_or(DEFAULT),
+        _ => DEFAULT,
+    }
+}
+
+/// Generates a random UUID.
+fn uuid() -> String {
+    let b: [u8; 16] = rand::thread_rng().gen();
+    format!(
+        "{:02x}{:02x}{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+        b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7], b[8], b[9], b[10], b[11], b[12], b[13],
+        b[14], b[15]
+    )
+}
+
+/// Converts a JSON value into its msgpack equivalent.
+fn msgpack(value: &Value) -> MsgValue {
+    match value {
+        Value::Null => MsgValue::Nil,
+        Value::Bool(b) => MsgValue::from(*b),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(u), _) => MsgValue::from(u),
+            (_, Some(i)) => MsgValue::from(i),
+            _ => MsgValue::from(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => MsgValue::from(s.as_str()),
+        Value::Array(a) => MsgValue::Array(a.iter().map(msgpack).collect()),
+        Value::Object(o) => MsgValue::Map(
+            o.iter()
+                .map(|(k, v)| (MsgValue::from(k.as_str()), msgpack(v)))
+                .collect(),
+        ),
+    }
+}
@@ -0,0 +1,149 @@
+//! Providers for supplying ACL tokens to a [ConsulClient][crate::client::ConsulClient].
+//!
+//! By default a client sends the static `token` found in its
+//! [ConsulClientSettings][crate::client::ConsulClientSettings]. When a
+//! [TokenProvider] is configured instead, it's consulted before every request
+//! which allows tokens to be rotated without rebuilding the client.
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::error::ClientError;
+
+/// A source of ACL tokens which is consulted before every request.
+///
+/// Returning `Ok(None)` sends the request without a token.
+pub trait TokenProvider: fmt::Debug + Send + Sync {
+    /// Returns the token to use for the next request.
+    fn token(&self) -> Result<Option<String>, ClientError>;
+}
+
+/// A [TokenProvider] which reads the token from a file.
+///
+/// Since tokens are requested from within async code, the file is checked for
+/// changes at most once per refresh interval (one second by default) and the
+/// previously read token is returned in between. The file is only re-read when
+/// its modification time or size changes. Leading and trailing
+/// whitespace is trimmed and an empty file results in no token being sent.
+/// This mirrors the behavior of `CONSUL_HTTP_TOKEN_FILE` in the Consul CLI.
+#[derive(Debug)]
+pub struct FileTokenProvider {
+    cache: Mutex<Option<CachedToken>>,
+    path: PathBuf,
+    refresh_interval: Duration,
+}
+
+#[derive(Debug)]
+struct CachedToken {
+    checked: Instant,
+    len: u64,
+    modified: Option<SystemTime>,
+    token: Option<String>,
+}
+
+impl FileTokenProvider {
+    /// Creates a new [FileTokenProvider] which reads the token from the file
+    /// at the given path.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileTokenProvider {
+            cache: Mutex::new(None),
+            path: path.into(),
+            refresh_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Sets the minimum time between checking the file for changes.
+    pub fn with_refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    /// Returns the path of the token file.
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    fn read_error(&self, source: std::io::Error) -> ClientError {
+        ClientError::FileReadError {
+            source,
+            path: self.path.display().to_string(),
+        }
+    }
+}
+
+impl TokenProvider for FileTokenProvider {
+    fn token(&self) -> Result<Option<String>, ClientError> {
+        let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(c) = cache.as_mut() {
+            if c.checked.elapsed() < self.refresh_interval {
+                return Ok(c.token.clone());
+            }
+        }
+
+        let meta = fs::metadata(&self.path).map_err(|e| self.read_error(e))?;
+        let modified = meta.modified().ok();
+        if let Some(c) = cache.as_mut() {
+            if c.modified.is_some() && c.modified == modified && c.len == meta.len() {
+                c.checked = Instant::now();
+                return Ok(c.token.clone());
+            }
+        }
+
+        debug!("Reading consul ACL token from {}", self.path.display());
+        let content = fs::read_to_string(&self.path).map_err(|e| self.read_error(e))?;
+        let token = match content.trim() {
+            "" => None,
+            t => Some(t.to_string()),
+        };
+        *cache = Some(CachedToken {
+            checked: Instant::now(),
+            len: meta.len(),
+            modified,
+            token: token.clone(),
+        });
+
+        Ok(token)
+    }
+}
+
+/// A [TokenProvider] which calls the given function to obtain a token.
+///
+/// The function is called before every request and should therefore be cheap,
+/// caching the token itself if necessary.
+pub struct FnTokenProvider<F>
+where
+    F: Fn() -> Result<Option<String>, ClientError> + Send + Sync,
+{
+    func: F,
+}
+
+impl<F> FnTokenProvider<F>
+where
+    F: Fn() -> Result<Option<String>, ClientError> + Send + Sync,
+{
+    /// Creates a new [FnTokenProvider] which calls the given function.
+    pub fn new(func: F) -> Self {
+        FnTokenProvider { func }
+    }
+}
+
+impl<F> fmt::Debug for FnTokenProvider<F>
+where
+    F: Fn() -> Result<Option<String>, ClientError> + Send + Sync,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FnTokenProvider").finish_non_exhaustive()
+    }
+}
+
+impl<F> TokenProvider for FnTokenProvider<F>
+where
+    F: Fn() -> Result<Option<String>, ClientError> + Send + Sync,
+{
+    fn token(&self) -> Result<Option<String>, ClientError> {
+        (self.func)()
+    }
+}
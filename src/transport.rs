@@ -0,0 +1,423 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use http::{Request, Response, Uri};
+use hyper::Body;
+use rustify::{clients::reqwest::Client as HTTPClient, errors::ClientError as RestClientError};
+
+pub use self::record::{
+    Fixture, Interaction, RecordedBody, RecordedRequest, RecordedResponse, RecordingTransport,
+    ReplayTransport,
+};
+
+mod record;
+
+/// A backend capable of sending HTTP requests to Consul.
+///
+/// This is a re-export of [rustify::client::Client]. Any type implementing it
+/// can be used as the backend of a [ConsulClient][crate::client::ConsulClient]
+/// through [ConsulClient::with_transport][crate::client::ConsulClient::with_transport],
+/// allowing alternative HTTP clients, mocks or recording transports to be
+/// plugged in. Implementations only need to provide `send`, which takes a
+/// fully formed [Request] and returns the raw [Response], and `base`, which
+/// returns the base URL requests are built against.
+pub use rustify::client::Client as Transport;
+
+/// A [Transport] which is also capable of streaming request and response
+/// bodies.
+///
+/// Streaming is used for endpoints which transfer large payloads, such as
+/// [snapshots][crate::snapshot], so that they never have to be held in memory
+/// in their entirety. The returned [Response] has its body left unread.
+#[async_trait]
+pub trait StreamTransport: Transport {
+    /// Sends the given [Request], streaming its body, and returns the
+    /// [Response] as soon as its headers have been received.
+    async fn send_stream(&self, req: Request<Body>) -> Result<Response<Body>, RestClientError>;
+}
+
+#[async_trait]
+impl StreamTransport for HTTPClient {
+    #[instrument(skip(self, req), err)]
+    async fn send_stream(&self, req: Request<Body>) -> Result<Response<Body>, RestClientError> {
+        let (parts, body) = req.into_parts();
+        let url = parts.uri.to_string();
+        let method = parts.method.to_string();
+        let request = self
+            .http
+            .request(parts.method, url.as_str())
+            .headers(parts.headers)
+            .body(reqwest::Body::wrap_stream(body))
+            .build()
+            .map_err(|e| RestClientError::ReqwestBuildError { source: e })?;
+
+        let response =
+            self.http
+                .execute(request)
+                .await
+                .map_err(|e| RestClientError::RequestError {
+                    source: e.into(),
+                    url,
+                    method,
+                })?;
+
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = response.status();
+        *res.headers_mut() = response.headers().clone();
+        *res.body_mut() = Body::wrap_stream(response.bytes_stream());
+        Ok(res)
+    }
+}
+
+/// Reads the entire body of a streamed [Response] into memory.
+pub(crate) async fn buffer(res: Response<Body>) -> Result<Response<Vec<u8>>, RestClientError> {
+    let (parts, body) = res.into_parts();
+    let body = hyper::body::to_bytes(body)
+        .await
+        .map_err(|e| RestClientError::ResponseError { source: e.into() })?;
+    Ok(Response::from_parts(parts, body.to_vec()))
+}
+
+/// A transport which routes requests to the first healthy address out of a
+/// list of Consul addresses.
+///
+/// Each address is backed by its own [Transport], which allows mixing HTTP(S)
+/// addresses with Unix domain sockets. Requests are always built against the
+/// base URL of the first transport. When a request is sent, it's rewritten to
+/// target the first address which is currently considered healthy. If a
+/// connection can't be established, the address is marked as failed and the
+/// request is retried against the next healthy address. Only failures to
+/// connect cause a failover, since the request can't have reached Consul.
+/// Errors after a connection is established (i.e. timeouts or reset
+/// connections) are returned as-is, as the request may already have been
+/// applied, as are responses from Consul, including error responses.
+///
+/// Failed addresses are periodically re-probed in the background using the
+/// `status/leader` endpoint and are returned to the pool once they respond.
+/// If every address has failed, all addresses are tried in order.
+///
+/// Streamed requests are only sent to the first healthy address since their
+/// body can't be replayed against another address.
+pub struct FailoverTransport {
+    base: String,
+    probe_interval: Duration,
+    targets: Vec<Arc<Target>>,
+    version: String,
+}
+
+/// An address which requests can be routed to.
+struct Target {
+    address: String,
+    base: String,
+    failed_at: Mutex<Option<Instant>>,
+    http: Box<dyn StreamTransport>,
+    probing: AtomicBool,
+}
+
+impl Target {
+    fn failed_at(&self) -> MutexGuard<'_, Option<Instant>> {
+        self.failed_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.failed_at().is_none()
+    }
+
+    fn mark_failed(&self) {
+        let mut failed_at = self.failed_at();
+        if failed_at.is_none() {
+            warn!("Marking Consul address {} as failed", self.address);
+            *failed_at = Some(Instant::now());
+        }
+    }
+
+    fn mark_healthy(&self) {
+        let mut failed_at = self.failed_at();
+        if failed_at.is_some() {
+            info!("Consul address {} is healthy again", self.address);
+            *failed_at = None;
+        }
+    }
+
+    /// Returns whether a failed target is due to be probed again.
+    fn should_probe(&self, interval: Duration) -> bool {
+        match *self.failed_at() {
+            Some(t) => t.elapsed() >= interval && !self.probing.load(Ordering::SeqCst),
+            None => false,
+        }
+    }
+}
+
+impl FailoverTransport {
+    /// Creates a new [FailoverTransport] which routes requests between the
+    /// given addresses, in order of preference, using the transport paired
+    /// with each address.
+    ///
+    /// The `version` is the API version prefix (i.e. `v1`) used when probing
+    /// failed addresses.
+    pub fn new(
+        targets: Vec<(String, Box<dyn StreamTransport>)>,
+        version: &str,
+        probe_interval: Duration,
+    ) -> Self {
+        let targets = targets
+            .into_iter()
+            .map(|(address, http)| {
+                Arc::new(Target {
+                    address,
+                    base: normalize(http.base()),
+                    failed_at: Mutex::new(None),
+                    http,
+                    probing: AtomicBool::new(false),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        FailoverTransport {
+            base: targets.first().map(|t| t.base.clone()).unwrap_or_default(),
+            probe_interval,
+            targets,
+            version: version.into(),
+        }
+    }
+
+    /// Returns the addresses which are currently considered healthy.
+    pub fn healthy_addresses(&self) -> Vec<String> {
+        self.targets
+            .iter()
+            .filter(|t| t.is_healthy())
+            .map(|t| t.address.clone())
+            .collect()
+    }
+
+    /// Spawns a background probe for every failed address which is due.
+    fn probe(&self) {
+        for target in self.targets.iter() {
+            if !target.should_probe(self.probe_interval) {
+                continue;
+            }
+
+            target.probing.store(true, Ordering::SeqCst);
+            let target = target.clone();
+            let url = format!("{}/{}/status/leader", target.base, self.version);
+            tokio::spawn(async move {
+                debug!("Probing failed Consul address {}", target.address);
+                let mut req = Request::new(Vec::new());
+                if let Ok(uri) = Uri::from_str(&url) {
+                    *req.uri_mut() = uri;
+                    match target.http.send(req).await {
+                        Ok(r) if r.status().is_success() => target.mark_healthy(),
+                        _ => *target.failed_at() = Some(Instant::now()),
+                    }
+                }
+                target.probing.store(false, Ordering::SeqCst);
+            });
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for FailoverTransport {
+    fn base(&self) -> &str {
+        self.base.as_str()
+    }
+
+    #[instrument(skip(self, req), err)]
+    async fn send(&self, req: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, RestClientError> {
+        self.probe();
+
+        // Prefer healthy addresses, falling back to all addresses if none are
+        let mut targets: Vec<&Arc<Target>> =
+            self.targets.iter().filter(|t| t.is_healthy()).collect();
+        if targets.is_empty() {
+            warn!("No healthy Consul addresses available, trying all addresses");
+            targets = self.targets.iter().collect();
+        }
+
+        let mut last_err = None;
+        for target in targets {
+            let req = rewrite(&req, &self.base, &target.base);
+            debug!("Routing request to {}", target.address);
+            match target.http.send(req).await {
+                Ok(r) => return Ok(r),
+                Err(e) if is_connect_error(&e) => {
+                    target.mark_failed();
+                    last_err = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_err.unwrap_or(RestClientError::ResponseError {
+            source: anyhow::anyhow!("No Consul addresses were configured"),
+        }))
+    }
+}
+
+#[async_trait]
+impl StreamTransport for FailoverTransport {
+    #[instrument(skip(self, req), err)]
+    async fn send_stream(&self, req: Request<Body>) -> Result<Response<Body>, RestClientError> {
+        self.probe();
+
+        let target = self
+            .targets
+            .iter()
+            .find(|t| t.is_healthy())
+            .or_else(|| self.targets.first())
+            .ok_or_else(|| RestClientError::ResponseError {
+                source: anyhow::anyhow!("No Consul addresses were configured"),
+            })?;
+
+        let (mut parts, body) = req.into_parts();
+        parts.uri = rewrite_uri(&parts.uri, &self.base, &target.base);
+        debug!("Routing streamed request to {}", target.address);
+        match target
+            .http
+            .send_stream(Request::from_parts(parts, body))
+            .await
+        {
+            Err(e) if is_connect_error(&e) => {
+                target.mark_failed();
+                Err(e)
+            }
+            res => res,
+        }
+    }
+}
+
+/// Returns whether the given error was caused by failing to connect to the
+/// address, in which case the request was never sent.
+///
+/// Connection failures are reported by reqwest as a connect error and by the
+/// [UnixTransport] as the I/O error from connecting to the socket.
+fn is_connect_error(error: &RestClientError) -> bool {
+    match error {
+        RestClientError::RequestError { source, .. } => {
+            match source.downcast_ref::<reqwest::Error>() {
+                Some(e) => e.is_connect(),
+                None => source.is::<std::io::Error>(),
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Normalizes an address so it can be matched against the URL of a request.
+fn normalize(address: &str) -> String {
+    match url::Url::parse(address) {
+        Ok(u) => u.as_str().trim_end_matches('/').to_string(),
+        Err(_) => address.trim_end_matches('/').to_string(),
+    }
+}
+
+/// Returns a copy of the given [Request] with the base address swapped out.
+fn rewrite(req: &Request<Vec<u8>>, from: &str, to: &str) -> Request<Vec<u8>> {
+    let mut new = Request::new(req.body().clone());
+    *new.method_mut() = req.method().clone();
+    *new.headers_mut() = req.headers().clone();
+    *new.version_mut() = req.version();
+
+    *new.uri_mut() = rewrite_uri(req.uri(), from, to);
+    new
+}
+
+/// Returns a copy of the given [Uri] with the base address swapped out.
+fn rewrite_uri(uri: &Uri, from: &str, to: &str) -> Uri {
+    match uri.to_string().strip_prefix(from) {
+        Some(rest) => Uri::from_str(&format!("{}{}", to, rest)).unwrap_or_else(|_| uri.clone()),
+        None => uri.clone(),
+    }
+}
+
+/// A transport which sends requests over a Unix domain socket.
+///
+/// Consul agents can be configured to expose the HTTP API on a Unix domain
+/// socket (i.e. `unix:///var/run/consul.sock`). A new connection is opened for
+/// every request. Since the host portion of the URL is meaningless when using
+/// a socket, requests are built against a base URL of `http://localhost`.
+#[cfg(unix)]
+pub struct UnixTransport {
+    base: String,
+    path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixTransport {
+    /// Creates a new [UnixTransport] which connects to the socket at the given
+    /// path. The path may optionally be prefixed with `unix://`.
+    pub fn new(path: &str) -> Self {
+        UnixTransport {
+            base: String::from("http://localhost"),
+            path: path.trim_start_matches("unix://").into(),
+        }
+    }
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl Transport for UnixTransport {
+    fn base(&self) -> &str {
+        self.base.as_str()
+    }
+
+    #[instrument(skip(self, req), err)]
+    async fn send(&self, req: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, RestClientError> {
+        let (parts, body) = req.into_parts();
+        let res = self
+            .send_stream(Request::from_parts(parts, Body::from(body)))
+            .await?;
+        buffer(res).await
+    }
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl StreamTransport for UnixTransport {
+    #[instrument(skip(self, req), err)]
+    async fn send_stream(&self, req: Request<Body>) -> Result<Response<Body>, RestClientError> {
+        let url_err = req.uri().to_string();
+        let method_err = req.method().to_string();
+        let request_err = |e: anyhow::Error| RestClientError::RequestError {
+            source: e,
+            url: url_err.clone(),
+            method: method_err.clone(),
+        };
+
+        debug!("Connecting to socket at {}", self.path.display());
+        let stream = tokio::net::UnixStream::connect(&self.path)
+            .await
+            .map_err(|e| request_err(e.into()))?;
+        let (mut sender, conn) = hyper::client::conn::handshake(stream)
+            .await
+            .map_err(|e| request_err(e.into()))?;
+        tokio::spawn(async move {
+            if let Err(e) = conn.await {
+                debug!("Socket connection closed with error: {}", e);
+            }
+        });
+
+        // The Host header is required by HTTP/1.1 and is normally derived from
+        // the URL by the HTTP client
+        let (mut parts, body) = req.into_parts();
+        if let Some(host) = parts.uri.authority().map(|a| a.to_string()) {
+            if let Ok(v) = http::HeaderValue::from_str(&host) {
+                parts.headers.entry(http::header::HOST).or_insert(v);
+            }
+        }
+
+        sender
+            .send_request(Request::from_parts(parts, body))
+            .await
+            .map_err(|e| request_err(e.into()))
+    }
+}
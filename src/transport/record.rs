@@ -0,0 +1,386 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+};
+
+use async_trait::async_trait;
+use http::{HeaderMap, Request, Response, StatusCode};
+use rustify::errors::ClientError as RestClientError;
+use serde::{Deserialize, Serialize};
+
+use super::Transport;
+use crate::error::ClientError;
+
+/// The value recorded in place of an ACL token.
+pub const REDACTED: &str = "<redacted>";
+
+/// A collection of recorded request/response pairs.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Fixture {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Fixture {
+    /// Loads a [Fixture] from the JSON file at the given path.
+    pub fn load(path: impl AsRef<Path>) -> Result<Fixture, ClientError> {
+        let path = path.as_ref();
+        let content = fs::read(path).map_err(|e| ClientError::FileReadError {
+            source: e,
+            path: path.display().to_string(),
+        })?;
+        serde_json::from_slice(&content)
+            .map_err(|e| ClientError::JsonDeserializeError { source: e })
+    }
+
+    /// Saves this [Fixture] as JSON to the file at the given path.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ClientError> {
+        let path = path.as_ref();
+        let content = serde_json::to_vec_pretty(self)
+            .map_err(|e| ClientError::JsonSerializeError { source: e })?;
+        fs::write(path, content).map_err(|e| ClientError::FileWriteError {
+            source: e,
+            path: path.display().to_string(),
+        })
+    }
+}
+
+/// A single recorded request and the response that was returned for it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+/// A recorded HTTP request.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: RecordedBody,
+}
+
+impl RecordedRequest {
+    /// Records the given [Request], redacting any ACL tokens.
+    fn from_request(req: &Request<Vec<u8>>) -> Self {
+        let mut headers = headers(req.headers());
+        if let Some(token) = headers.get_mut("x-consul-token") {
+            *token = REDACTED.into();
+        }
+
+        RecordedRequest {
+            method: req.method().to_string(),
+            path: req.uri().path().into(),
+            query: req
+                .uri()
+                .query()
+                .filter(|q| !q.is_empty())
+                .map(redact_query),
+            headers,
+            body: RecordedBody::new(req.body()),
+        }
+    }
+
+    /// Returns whether this recorded request matches the given [Request].
+    ///
+    /// Requests are matched on their method, path, query parameters and body.
+    /// Headers are ignored.
+    fn matches(&self, req: &Request<Vec<u8>>) -> bool {
+        self.method == req.method().as_str()
+            && self.path == req.uri().path()
+            && query_pairs(self.query.as_deref()) == query_pairs(req.uri().query())
+            && self.body.bytes().as_deref() == Some(req.body().as_slice())
+    }
+}
+
+/// A recorded HTTP response.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: RecordedBody,
+}
+
+impl RecordedResponse {
+    fn from_response(res: &Response<Vec<u8>>) -> Self {
+        RecordedResponse {
+            status: res.status().as_u16(),
+            headers: headers(res.headers()),
+            body: RecordedBody::new(res.body()),
+        }
+    }
+
+    fn to_response(&self) -> Result<Response<Vec<u8>>, RestClientError> {
+        let body = self
+            .body
+            .bytes()
+            .ok_or_else(|| RestClientError::GenericError {
+                source: anyhow::anyhow!("Fixture contains an invalid Base64 encoded body"),
+            })?;
+
+        let mut res = Response::new(body);
+        *res.status_mut() = StatusCode::from_u16(self.status)
+            .map_err(|e| RestClientError::GenericError { source: e.into() })?;
+        for (key, value) in self.headers.iter() {
+            if let (Ok(k), Ok(v)) = (
+                http::header::HeaderName::from_bytes(key.as_bytes()),
+                http::HeaderValue::from_str(value),
+            ) {
+                res.headers_mut().insert(k, v);
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// A recorded request or response body.
+///
+/// Bodies which are valid UTF-8 are stored as-is to keep fixtures readable,
+/// all other bodies are stored Base64 encoded.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordedBody {
+    #[default]
+    Empty,
+    Text(String),
+    Base64(String),
+}
+
+impl RecordedBody {
+    fn new(body: &[u8]) -> Self {
+        if body.is_empty() {
+            return RecordedBody::Empty;
+        }
+        match std::str::from_utf8(body) {
+            Ok(s) => RecordedBody::Text(s.into()),
+            Err(_) => RecordedBody::Base64(base64::encode(body)),
+        }
+    }
+
+    /// Returns the raw bytes of this body, or [None] if it can't be decoded.
+    fn bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            RecordedBody::Empty => Some(Vec::new()),
+            RecordedBody::Text(s) => Some(s.as_bytes().to_vec()),
+            RecordedBody::Base64(s) => base64::decode(s).ok(),
+        }
+    }
+}
+
+/// A [Transport] which records all requests sent through it, along with the
+/// responses that were returned, to a fixture file.
+///
+/// The recorder wraps another [Transport] and sits alongside the
+/// [EndpointMiddleware][crate::api::EndpointMiddleware], capturing requests
+/// after the middleware has been applied. Recording happens at the transport
+/// level so that error responses are captured as well. Interactions are kept
+/// in memory and written to the fixture file by [RecordingTransport::finish],
+/// or when the transport is dropped, so recording never blocks on file I/O
+/// while requests are in flight. Any ACL tokens found in the `X-Consul-Token`
+/// header or `token` query parameter are redacted.
+///
+/// The resulting fixture can be served using a [ReplayTransport].
+pub struct RecordingTransport<T: Transport> {
+    fixture: Mutex<Fixture>,
+    http: T,
+    path: PathBuf,
+    unsaved: AtomicBool,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    /// Creates a new [RecordingTransport] which sends requests using the given
+    /// [Transport] and records them to the fixture file at the given path.
+    /// Any existing file is overwritten.
+    pub fn new(http: T, path: impl Into<PathBuf>) -> Self {
+        RecordingTransport {
+            fixture: Mutex::new(Fixture::default()),
+            http,
+            path: path.into(),
+            unsaved: AtomicBool::new(false),
+        }
+    }
+
+    /// Writes all interactions recorded so far to the fixture file.
+    ///
+    /// This is also done when the transport is dropped, although any error is
+    /// only logged in that case.
+    pub fn finish(&self) -> Result<(), ClientError> {
+        let fixture = self.lock();
+        debug!(
+            "Saving {} interactions to {}",
+            fixture.interactions.len(),
+            self.path.display()
+        );
+        fixture.save(&self.path)?;
+        self.unsaved.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Returns a copy of all interactions recorded so far.
+    pub fn fixture(&self) -> Fixture {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Fixture> {
+        self.fixture.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[async_trait]
+impl<T: Transport> Transport for RecordingTransport<T> {
+    fn base(&self) -> &str {
+        self.http.base()
+    }
+
+    #[instrument(skip(self, req), err)]
+    async fn send(&self, req: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, RestClientError> {
+        let request = RecordedRequest::from_request(&req);
+        let res = self.http.send(req).await?;
+
+        debug!("Recording {} {}", request.method, request.path);
+        self.lock().interactions.push(Interaction {
+            request,
+            response: RecordedResponse::from_response(&res),
+        });
+        self.unsaved.store(true, Ordering::SeqCst);
+
+        Ok(res)
+    }
+}
+
+impl<T: Transport> Drop for RecordingTransport<T> {
+    fn drop(&mut self) {
+        if self.unsaved.load(Ordering::SeqCst) {
+            if let Err(e) = self.finish() {
+                error!("Failed to save fixture to {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// A [Transport] which serves responses from a recorded [Fixture].
+///
+/// Each incoming request is matched against the recorded interactions on its
+/// method, path, query parameters and body. Interactions are served in the
+/// order they were recorded and each one is only served once, which allows
+/// replaying a sequence of identical requests that returned different
+/// responses (i.e. blocking queries). A request without a matching
+/// interaction fails with a [RestClientError::GenericError].
+pub struct ReplayTransport {
+    base: String,
+    interactions: Mutex<Vec<(Interaction, bool)>>,
+}
+
+impl ReplayTransport {
+    /// Creates a new [ReplayTransport] which serves the given [Fixture].
+    pub fn new(fixture: Fixture) -> Self {
+        ReplayTransport {
+            base: String::from("http://replay"),
+            interactions: Mutex::new(
+                fixture
+                    .interactions
+                    .into_iter()
+                    .map(|i| (i, false))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Creates a new [ReplayTransport] which serves the fixture file at the
+    /// given path.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        Ok(ReplayTransport::new(Fixture::load(path)?))
+    }
+
+    /// Returns the interactions which haven't been served yet.
+    pub fn remaining(&self) -> Vec<Interaction> {
+        self.lock()
+            .iter()
+            .filter(|(_, served)| !served)
+            .map(|(i, _)| i.clone())
+            .collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<(Interaction, bool)>> {
+        self.interactions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[async_trait]
+impl Transport for ReplayTransport {
+    fn base(&self) -> &str {
+        self.base.as_str()
+    }
+
+    #[instrument(skip(self, req), err)]
+    async fn send(&self, req: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, RestClientError> {
+        let mut interactions = self.lock();
+        let found = interactions
+            .iter_mut()
+            .find(|(i, served)| !served && i.request.matches(&req));
+
+        match found {
+            Some((interaction, served)) => {
+                debug!("Replaying {} {}", req.method(), req.uri());
+                *served = true;
+                interaction.response.to_response()
+            }
+            None => {
+                error!("No recorded interaction for {} {}", req.method(), req.uri());
+                Err(RestClientError::GenericError {
+                    source: anyhow::anyhow!(
+                        "No recorded interaction matches request: {} {}",
+                        req.method(),
+                        req.uri()
+                    ),
+                })
+            }
+        }
+    }
+}
+
+/// Converts a [HeaderMap] into a map of strings, skipping non UTF-8 values.
+fn headers(map: &HeaderMap) -> BTreeMap<String, String> {
+    map.iter()
+        .filter_map(|(k, v)| v.to_str().ok().map(|v| (k.to_string(), v.to_string())))
+        .collect()
+}
+
+/// Returns the sorted query pairs of the given query string.
+fn query_pairs(query: Option<&str>) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> =
+        url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+            .into_owned()
+            .map(|(k, v)| match k.as_str() {
+                "token" => (k, REDACTED.into()),
+                _ => (k, v),
+            })
+            .collect();
+    pairs.sort();
+    pairs
+}
+
+/// Redacts the `token` query parameter from the given query string.
+fn redact_query(query: &str) -> String {
+    url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(url::form_urlencoded::parse(query.as_bytes()).map(
+            |(k, v)| match k.as_ref() {
+                "token" => (k, REDACTED.into()),
+                _ => (k, v),
+            },
+        ))
+        .finish()
+}
@@ -0,0 +1,111 @@
+use crate::{
+    api::{
+        self,
+        txn::{
+            common::{KVTxnOp, TxnOp},
+            requests::{CreateTransactionRequest, CreateTransactionRequestBuilder},
+            responses::{TxnResponse, TxnResult},
+        },
+        ApiResponse,
+    },
+    client::Client,
+    error::ClientError,
+};
+
+/// The maximum number of operations Consul accepts in a single transaction.
+pub const MAX_OPERATIONS: usize = 64;
+
+/// The maximum size in bytes of a transaction request, matching Consul's
+/// default for `txn_max_req_len`.
+pub const MAX_REQUEST_SIZE: usize = 512 * 1024;
+
+/// The approximate overhead of a single operation in a transaction, excluding
+/// its key and value.
+pub(crate) const OP_OVERHEAD: usize = 64;
+
+/// Executes the given operations atomically.
+///
+/// If any of the operations fail then none of them are applied and a
+/// [ClientError::TransactionError] is returned containing the errors reported
+/// by Consul. Consul rejects transactions with more than [MAX_OPERATIONS]
+/// operations.
+///
+/// See [CreateTransactionRequest]
+#[instrument(skip(client, ops, opts), err)]
+pub async fn execute(
+    client: &impl Client,
+    ops: &[TxnOp],
+    opts: Option<&mut CreateTransactionRequestBuilder>,
+) -> Result<ApiResponse<Vec<TxnResult>>, ClientError> {
+    let mut t = CreateTransactionRequest::builder();
+    let bytes =
+        serde_json::to_vec(ops).map_err(|e| ClientError::JsonSerializeError { source: e })?;
+    let endpoint = opts
+        .unwrap_or(&mut t)
+        .operations(bytes)
+        .build()
+        .map_err(ClientError::from_builder)?;
+
+    // Consul responds with a 409 containing the errors when rolling back
+    let res = match api::exec_with_result(client, endpoint).await {
+        Ok(r) => r,
+        Err(ClientError::CasConflict { message }) => {
+            let errors = message
+                .as_deref()
+                .and_then(|m| serde_json::from_str::<TxnResponse>(m).ok())
+                .and_then(|r| r.errors);
+            return Err(match errors {
+                Some(errors) => ClientError::TransactionError { errors },
+                None => ClientError::CasConflict { message },
+            });
+        }
+        Err(e) => return Err(e),
+    };
+
+    Ok(ApiResponse {
+        response: res.response.results.unwrap_or_default(),
+        age: res.age,
+        cache: res.cache,
+        content_hash: res.content_hash,
+        default_acl_policy: res.default_acl_policy,
+        index: res.index,
+        known_leader: res.known_leader,
+        last_contact: res.last_contact,
+        query_backend: res.query_backend,
+    })
+}
+
+/// Returns the approximate size of an operation writing the given key and
+/// value once it's encoded in a transaction.
+pub(crate) fn op_size(key: &str, value: &[u8]) -> usize {
+    key.len() + value.len().div_ceil(3) * 4 + OP_OVERHEAD
+}
+
+/// Splits groups of operations into batches which each fit in a transaction.
+///
+/// Each operation is paired with its size as returned by [op_size]. A batch
+/// holds at most [MAX_OPERATIONS] operations whose sizes add up to at most
+/// `budget`, unless a single group is larger than that. The operations of a
+/// group are always placed in the same batch.
+pub(crate) fn batches(
+    budget: usize,
+    groups: impl IntoIterator<Item = Vec<(usize, KVTxnOp)>>,
+) -> Vec<Vec<KVTxnOp>> {
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut size = 0;
+    for group in groups {
+        let cost = group.iter().map(|(s, _)| s).sum::<usize>();
+        if !batch.is_empty() && (size + cost > budget || batch.len() + group.len() > MAX_OPERATIONS)
+        {
+            batches.push(std::mem::take(&mut batch));
+            size = 0;
+        }
+        size += cost;
+        batch.extend(group.into_iter().map(|(_, op)| op));
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
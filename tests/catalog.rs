@@ -91,3 +91,25 @@ async fn test_services(client: &impl Client) {
     let res = catalog::services(client, None).await;
     assert!(res.is_ok());
 }
+
+#[cfg(feature = "testing")]
+mod testing {
+    use super::common;
+    use consulrs::catalog;
+    use test_log::test;
+
+    #[test(tokio::test)]
+    async fn test_catalog() {
+        let (_server, client) = common::test_server().await;
+
+        catalog::register(&client, "node1", "10.0.0.1", None)
+            .await
+            .unwrap();
+        let res = catalog::nodes(&client, None).await.unwrap();
+        assert_eq!(res.response.len(), 2);
+
+        catalog::deregister(&client, "node1", None).await.unwrap();
+        let res = catalog::nodes(&client, None).await.unwrap();
+        assert_eq!(res.response.len(), 1);
+    }
+}
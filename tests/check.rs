@@ -2,6 +2,7 @@ mod common;
 
 use common::{ConsulServer, ConsulServerHelper, CountingServer};
 use consulrs::{api::check::requests::RegisterCheckRequest, check, client::Client};
+use std::time::Duration;
 use test_log::test;
 
 #[test]
@@ -53,7 +54,7 @@ async fn test_register(client: &impl Client, name: &str) {
     let res = check::register(
         client,
         name,
-        Some(RegisterCheckRequest::builder().ttl("10m")),
+        Some(RegisterCheckRequest::builder().ttl(Duration::from_secs(600))),
     )
     .await;
     assert!(res.is_ok());
@@ -0,0 +1,238 @@
+mod common;
+
+use common::MockTransport;
+use consulrs::{
+    api::{
+        features::Blocking,
+        kv::requests::{ReadKeyRequest, ReadKeysRequest},
+        CacheStatus, Features, QueryBackend,
+    },
+    client::{Client, ConsulClient, ConsulClientSettingsBuilder, RetryPolicy},
+    error::ClientError,
+    kv,
+};
+use std::time::Duration;
+use test_log::test;
+
+/// A predicate used to check the variant of a [ClientError].
+type ErrorCheck = fn(&ClientError) -> bool;
+
+#[test(tokio::test)]
+async fn test_timeout() {
+    let client = ConsulClient::with_transport(
+        ConsulClientSettingsBuilder::default()
+            .read_timeout(Duration::from_millis(50))
+            .retry(RetryPolicy::disabled())
+            .build()
+            .unwrap(),
+        MockTransport {
+            body: "[]",
+            delay: Some(Duration::from_millis(200)),
+            ..Default::default()
+        },
+    );
+
+    let res = kv::keys(&client, "test", None).await;
+    assert!(matches!(res, Err(ClientError::TimeoutError { .. })));
+    assert!(res.unwrap_err().is_retryable());
+
+    // Blocking queries have their deadline extended by their wait time
+    let blocking = Blocking {
+        index: 1,
+        wait: Some(Duration::from_secs(1).into()),
+    };
+    assert_eq!(
+        blocking.max_wait(),
+        Duration::from_millis(1062) + Duration::from_micros(500)
+    );
+    let res = kv::keys(
+        &client,
+        "test",
+        Some(
+            ReadKeysRequest::builder()
+                .features(Features::builder().blocking(blocking).build().unwrap()),
+        ),
+    )
+    .await;
+    assert!(res.is_ok());
+}
+
+#[test(tokio::test)]
+async fn test_response_headers() {
+    let transport = MockTransport {
+        body: "true",
+        headers: vec![
+            ("Age", b"30"),
+            ("X-Cache", b"HIT"),
+            ("X-Consul-Index", b"42"),
+            ("X-Consul-KnownLeader", b"true"),
+            ("X-Consul-LastContact", b"150"),
+            ("X-Consul-Query-Backend", b"streaming"),
+        ],
+        ..Default::default()
+    };
+    let client = ConsulClient::with_transport(
+        ConsulClientSettingsBuilder::default().build().unwrap(),
+        transport,
+    );
+
+    let res = kv::set(&client, "test", b"test", None).await.unwrap();
+    assert_eq!(res.age, Some(Duration::from_secs(30)));
+    assert_eq!(res.cache, Some(CacheStatus::Hit));
+    assert_eq!(res.index, Some(42));
+    assert_eq!(res.known_leader, Some(true));
+    assert_eq!(res.last_contact, Some(Duration::from_millis(150)));
+    assert_eq!(res.query_backend, Some(QueryBackend::Streaming));
+
+    // Unknown informational values don't fail the response
+    let transport = MockTransport {
+        body: "true",
+        headers: vec![
+            ("X-Cache", b"STALE"),
+            ("X-Consul-Query-Backend", b"materialized-view"),
+        ],
+        ..Default::default()
+    };
+    let client = ConsulClient::with_transport(
+        ConsulClientSettingsBuilder::default().build().unwrap(),
+        transport,
+    );
+    let res = kv::set(&client, "test", b"test", None).await.unwrap();
+    assert_eq!(res.cache, Some(CacheStatus::Other("STALE".into())));
+    assert_eq!(
+        res.query_backend,
+        Some(QueryBackend::Other("materialized-view".into()))
+    );
+
+    let transport = MockTransport {
+        body: "true",
+        headers: vec![("X-Consul-Index", b"abc")],
+        ..Default::default()
+    };
+    let client = ConsulClient::with_transport(
+        ConsulClientSettingsBuilder::default().build().unwrap(),
+        transport,
+    );
+    let res = kv::set(&client, "test", b"test", None).await;
+    assert!(matches!(res, Err(ClientError::HeaderParseError { .. })));
+
+    let transport = MockTransport {
+        body: "true",
+        headers: vec![("X-Consul-Index", b"\xff")],
+        ..Default::default()
+    };
+    let client = ConsulClient::with_transport(
+        ConsulClientSettingsBuilder::default().build().unwrap(),
+        transport,
+    );
+    let res = kv::set(&client, "test", b"test", None).await;
+    assert!(matches!(res, Err(ClientError::HeaderDecodeError { .. })));
+}
+
+#[test(tokio::test)]
+async fn test_typed_errors() {
+    let cases: Vec<(u16, &str, ErrorCheck)> = vec![
+        (403, "ACL not found", |e| {
+            matches!(e, ClientError::ACLNotFound { .. })
+        }),
+        (403, "Permission denied", |e| {
+            matches!(e, ClientError::PermissionDenied { .. })
+        }),
+        (404, "", |e| matches!(e, ClientError::NotFound { .. })),
+        (409, "", |e| matches!(e, ClientError::CasConflict { .. })),
+        (
+            409,
+            r#"{"Errors":[{"OpIndex":0,"What":"Permission denied"}]}"#,
+            |e| matches!(e, ClientError::CasConflict { .. }),
+        ),
+        (404, "Permission denied", |e| {
+            matches!(e, ClientError::NotFound { .. })
+        }),
+        (500, "No cluster leader", |e| {
+            matches!(e, ClientError::NoLeader { code: 500, .. })
+        }),
+        (503, "No cluster leader", |e| {
+            matches!(e, ClientError::NoLeader { code: 503, .. })
+        }),
+        (500, "Unexpected", |e| {
+            matches!(e, ClientError::APIError { code: 500, .. })
+        }),
+    ];
+
+    for (status, body, check) in cases {
+        let transport = MockTransport {
+            body,
+            status: Some(status),
+            ..Default::default()
+        };
+        let client = ConsulClient::with_transport(
+            ConsulClientSettingsBuilder::default()
+                .retry(RetryPolicy::disabled())
+                .build()
+                .unwrap(),
+            transport,
+        );
+        let err = kv::read(&client, "test", None).await.unwrap_err();
+        assert!(check(&err), "{} {}: {:?}", status, body, err);
+        assert_eq!(err.status_code(), Some(status));
+    }
+
+    let transport = MockTransport {
+        headers: vec![("Retry-After", b"2")],
+        status: Some(429),
+        ..Default::default()
+    };
+    let client = ConsulClient::with_transport(
+        ConsulClientSettingsBuilder::default()
+            .retry(RetryPolicy::disabled())
+            .build()
+            .unwrap(),
+        transport,
+    );
+    let err = kv::read(&client, "test", None).await.unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::RateLimited {
+            retry_after: Some(d)
+        } if d == Duration::from_secs(2)
+    ));
+    assert!(err.is_retryable());
+}
+
+#[test(tokio::test)]
+async fn test_invalid_input() {
+    let transport = MockTransport {
+        body: "true",
+        ..Default::default()
+    };
+    let client = ConsulClient::with_transport(
+        ConsulClientSettingsBuilder::default()
+            .token("invalid\ntoken")
+            .build()
+            .unwrap(),
+        transport,
+    );
+    let res = kv::set(&client, "test", b"test", None).await;
+    assert!(matches!(res, Err(ClientError::InvalidTokenError { .. })));
+
+    let transport = MockTransport {
+        body: "[]",
+        ..Default::default()
+    };
+    let client = ConsulClient::with_transport(
+        ConsulClientSettingsBuilder::default().build().unwrap(),
+        transport,
+    );
+    let features = Features::builder().cached("max-age=\n").build().unwrap();
+    let res = kv::read(
+        &client,
+        "test",
+        Some(ReadKeyRequest::builder().features(features)),
+    )
+    .await;
+    assert!(matches!(
+        res,
+        Err(ClientError::InvalidHeaderValueError { .. })
+    ));
+    assert!(client.http().requests.lock().unwrap().is_empty());
+}
@@ -1,6 +1,8 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Mutex, time::Duration};
 
 use async_trait::async_trait;
+#[cfg(feature = "testing")]
+use consulrs::testing::TestServer;
 use consulrs::{
     api::{
         check::common::AgentServiceCheckBuilder,
@@ -9,12 +11,15 @@ use consulrs::{
     catalog,
     client::{Client, ConsulClient, ConsulClientSettingsBuilder},
     service,
+    transport::Transport,
 };
 pub use dockertest_server::servers::hashi::{
     counting::{CountingServer, CountingServerConfig},
     ConsulServer, ConsulServerConfig,
 };
 use dockertest_server::Test;
+use http::{Request, Response};
+use rustify::errors::ClientError as RestClientError;
 
 pub const CHECK_NAME: &str = "health";
 pub const CONSUL_PORT: u32 = 9201;
@@ -22,6 +27,7 @@ pub const COUNTING_PORT: u32 = 9200;
 pub const SERVICE_NAME: &str = "counting";
 pub const VERSION: &str = "1.9.9";
 
+#[allow(dead_code)]
 #[async_trait]
 pub trait ConsulServerHelper {
     /// Returns a [ConsulClient] configured to connect to the [ConsulServer].
@@ -48,6 +54,7 @@ impl ConsulServerHelper for ConsulServer {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct TestService {
     pub name: String,
@@ -98,7 +105,7 @@ pub async fn setup(client: &impl Client, counting: &CountingServer) -> TestServi
                 .check(
                     AgentServiceCheckBuilder::default()
                         .name(CHECK_NAME)
-                        .interval("1s")
+                        .interval(Duration::from_secs(1))
                         .http(url)
                         .status("passing")
                         .build()
@@ -114,3 +121,63 @@ pub async fn setup(client: &impl Client, counting: &CountingServer) -> TestServi
         check: CHECK_NAME.into(),
     }
 }
+
+/// Starts an in-process [TestServer] and returns it along with a client
+/// connected to it. The server is stopped when it's dropped.
+#[cfg(feature = "testing")]
+#[allow(dead_code)]
+pub async fn test_server() -> (TestServer, ConsulClient) {
+    let server = TestServer::start().await.unwrap();
+    let client = server.client().unwrap();
+    (server, client)
+}
+
+/// A [Transport] which records request paths and tokens and always returns
+/// the same status, body and headers, or a connection error if `error` is set.
+#[allow(dead_code)]
+#[derive(Default)]
+pub struct MockTransport {
+    pub body: &'static str,
+    pub delay: Option<Duration>,
+    pub error: bool,
+    pub headers: Vec<(&'static str, &'static [u8])>,
+    pub requests: Mutex<Vec<String>>,
+    pub status: Option<u16>,
+    pub tokens: Mutex<Vec<Option<String>>>,
+}
+
+#[async_trait]
+impl Transport for MockTransport {
+    fn base(&self) -> &str {
+        "http://mock"
+    }
+
+    async fn send(&self, req: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, RestClientError> {
+        if let Some(d) = self.delay {
+            tokio::time::sleep(d).await;
+        }
+        self.requests
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", req.method(), req.uri().path()));
+        self.tokens.lock().unwrap().push(
+            req.headers()
+                .get("X-Consul-Token")
+                .map(|v| v.to_str().unwrap().to_string()),
+        );
+        if self.error {
+            return Err(RestClientError::RequestError {
+                source: anyhow::anyhow!("connection refused"),
+                url: req.uri().to_string(),
+                method: req.method().to_string(),
+            });
+        }
+        let mut res = Response::new(self.body.as_bytes().to_vec());
+        *res.status_mut() = http::StatusCode::from_u16(self.status.unwrap_or(200)).unwrap();
+        for (k, v) in self.headers.iter() {
+            res.headers_mut()
+                .insert(*k, http::HeaderValue::from_bytes(v).unwrap());
+        }
+        Ok(res)
+    }
+}
@@ -0,0 +1,20 @@
+use consulrs::{api::GoDuration, error::ClientError};
+use std::time::Duration;
+
+#[test]
+fn test_duration_parse() {
+    let parse = |s: &str| s.parse::<GoDuration>().map(Duration::from);
+    assert_eq!(parse("1h2m3.5s").unwrap(), Duration::from_millis(3_723_500));
+    assert_eq!(parse("250us").unwrap(), Duration::from_micros(250));
+
+    // Invalid and out of range durations fail instead of panicking
+    for value in ["", "10", "1d", "99999999999999999999h", "1e400s"] {
+        assert!(matches!(
+            parse(value),
+            Err(ClientError::DurationParseError { .. })
+        ));
+    }
+    let max = format!("{}s", u64::MAX / 2);
+    assert!(parse(&format!("{}{}", max, max)).is_err());
+    assert!(serde_json::from_str::<GoDuration>(r#""99999999999999999999h""#).is_err());
+}
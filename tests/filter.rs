@@ -0,0 +1,162 @@
+use consulrs::{
+    api::{health::requests::ListNodesForServiceRequest, Features},
+    filter::{selector, Selector},
+};
+
+#[test]
+fn test_expressions() {
+    let cases = vec![
+        (
+            selector("Service.Port").eq(8080_u64),
+            "Service.Port == 8080",
+        ),
+        (
+            selector("Checks.Status").ne("critical"),
+            r#"Checks.Status != "critical""#,
+        ),
+        (
+            selector("Service.Tags").is_in("web"),
+            r#""web" in Service.Tags"#,
+        ),
+        (
+            selector("Service.Tags").not_in("web"),
+            r#""web" not in Service.Tags"#,
+        ),
+        (
+            selector("Service.Tags").contains("web"),
+            r#"Service.Tags contains "web""#,
+        ),
+        (
+            selector("Service.Tags").not_contains("web"),
+            r#"Service.Tags not contains "web""#,
+        ),
+        (
+            selector("Node").matches(r"^web-\d+$"),
+            r#"Node matches "^web-\\d+$""#,
+        ),
+        (
+            selector("Node").not_matches("^db"),
+            r#"Node not matches "^db""#,
+        ),
+        (selector("Service.Tags").is_empty(), "Service.Tags is empty"),
+        (
+            selector("Service.Tags").is_not_empty(),
+            "Service.Tags is not empty",
+        ),
+        (
+            Selector::new("Service")
+                .field("Meta")
+                .key("some-key")
+                .eq(true),
+            r#"Service.Meta["some-key"] == true"#,
+        ),
+    ];
+
+    for (expr, expected) in cases {
+        assert_eq!(expr.to_string(), expected);
+    }
+}
+
+#[test]
+fn test_escaping() {
+    let expr = selector("Meta")
+        .key("a\"b")
+        .eq("quote \" backslash \\ newline \n");
+    assert_eq!(
+        expr.to_string(),
+        r#"Meta["a\"b"] == "quote \" backslash \\ newline \n""#
+    );
+}
+
+#[test]
+fn test_precedence() {
+    let a = || selector("A").eq(1_i64);
+    let b = || selector("B").eq(2_i64);
+    let c = || selector("C").eq(3_i64);
+
+    assert_eq!(
+        a().and(b()).or(c()).to_string(),
+        "A == 1 and B == 2 or C == 3"
+    );
+    assert_eq!(
+        a().and(b().or(c())).to_string(),
+        "A == 1 and (B == 2 or C == 3)"
+    );
+    assert_eq!((!a().or(b())).to_string(), "not (A == 1 or B == 2)");
+    assert_eq!((!a()).and(b()).to_string(), "not A == 1 and B == 2");
+}
+
+#[test]
+fn test_into_filter() {
+    let features = Features::builder()
+        .filter(selector("Service").eq("web"))
+        .build()
+        .unwrap();
+    assert_eq!(features.filter.as_deref(), Some(r#"Service == "web""#));
+
+    let request = ListNodesForServiceRequest::builder()
+        .service("web")
+        .filter(selector("Service.Tags").contains("primary"))
+        .build()
+        .unwrap();
+    assert_eq!(
+        request.filter.as_deref(),
+        Some(r#"Service.Tags contains "primary""#)
+    );
+}
+
+#[cfg(feature = "testing")]
+mod testing {
+    use consulrs::{
+        api::service::common::AgentServiceBuilder, error::ClientError, filter::selector,
+    };
+    use test_log::test;
+
+    #[test]
+    fn test_filter_evaluate() {
+        let service = AgentServiceBuilder::default()
+            .service("web")
+            .port(8080_u64)
+            .tags(vec!["primary".to_string()])
+            .meta(
+                vec![("env".to_string(), "prod".to_string())]
+                    .into_iter()
+                    .collect::<std::collections::HashMap<_, _>>(),
+            )
+            .build()
+            .unwrap();
+
+        let matching = vec![
+            selector("Service").eq("web"),
+            selector("Port").eq(8080_u64),
+            selector("Tags").contains("primary"),
+            selector("Tags").is_in("primary"),
+            selector("Meta").key("env").eq("prod"),
+            selector("Meta").is_in("env"),
+            selector("Service").matches("^w.b$"),
+            selector("Address").is_empty(),
+            selector("Tags")
+                .contains("secondary")
+                .or(selector("Service").ne("db")),
+            !selector("Port").eq(80_u64),
+        ];
+        for expr in matching {
+            assert!(expr.evaluate(&service).unwrap(), "{}", expr);
+        }
+
+        let failing = vec![
+            selector("Service").eq("db"),
+            selector("Tags").is_empty(),
+            selector("Meta").key("env").eq("dev"),
+            selector("Service")
+                .eq("web")
+                .and(selector("Tags").not_contains("primary")),
+        ];
+        for expr in failing {
+            assert!(!expr.evaluate(&service).unwrap(), "{}", expr);
+        }
+
+        let res = selector("Tags").matches("primary").evaluate(&service);
+        assert!(matches!(res, Err(ClientError::FilterError { .. })));
+    }
+}
@@ -1,7 +1,12 @@
 mod common;
 
 use common::{ConsulServer, ConsulServerHelper};
-use consulrs::{api::kv::common::KVPair, api::kv::requests, client::Client, kv};
+use consulrs::{
+    api::kv::common::KVPair,
+    api::kv::requests,
+    client::{Client, ConsulClient, ConsulClientSettingsBuilder},
+    kv,
+};
 use serde::{Deserialize, Serialize};
 use std::convert::TryInto;
 use test_log::test;
@@ -27,9 +32,35 @@ fn test() {
         test_delete(&client, key).await;
         test_json(&client, key).await;
         test_roundtrip_bytes(&client, key).await;
+        test_default_datacenter(&server, key).await;
+        test_tree(&client).await;
     });
 }
 
+async fn test_default_datacenter(server: &ConsulServer, key: &str) {
+    let client = ConsulClient::new(
+        ConsulClientSettingsBuilder::default()
+            .address(server.external_url())
+            .datacenter("missing")
+            .build()
+            .unwrap(),
+    )
+    .unwrap();
+
+    // The client-wide default is applied to requests
+    let res = kv::read(&client, key, None).await;
+    assert!(res.is_err());
+
+    // A per-request datacenter overrides the client-wide default
+    let res = kv::read(
+        &client,
+        key,
+        Some(requests::ReadKeyRequestBuilder::default().dc("dc1")),
+    )
+    .await;
+    assert!(res.is_ok());
+}
+
 async fn test_delete(client: &impl Client, key: &str) {
     let res = kv::delete(client, key, None).await;
     assert!(res.is_ok());
@@ -101,3 +132,909 @@ async fn test_read_recurse(client: &impl Client, key: &str) {
     .await;
     assert!(res.is_ok());
 }
+
+async fn test_tree(client: &impl Client) {
+    kv::set(client, "tree/a/b", b"value", None).await.unwrap();
+
+    let res = kv::tree(client, "tree", None).await;
+    assert!(res.is_ok());
+    assert!(res.unwrap().response.get("a/b").is_some());
+
+    let res = kv::list_folders(client, "tree", None).await;
+    assert_eq!(res.unwrap().response, vec!["tree/a/"]);
+
+    let res = kv::copy_tree(client, "tree/a", "tree/c", None).await;
+    assert!(res.is_ok());
+
+    let res = kv::delete_tree(client, "tree", None).await;
+    assert!(res.is_ok());
+}
+
+#[cfg(feature = "testing")]
+mod testing {
+    use super::common;
+    use async_trait::async_trait;
+    use consulrs::{
+        api::{
+            kv::{
+                common::KVExportEntry,
+                requests::{ReadKeyRequest, ReadKeysRequest, SetKeyRequest},
+            },
+            Features,
+        },
+        client::{Client, ConsulClient, RetryPolicy},
+        codec::{self, Codec},
+        config_watch::{self, ConfigWatchOptions},
+        error::ClientError,
+        kv::{self, chunked::ChunkOptions, ImportOptions, KvStore, UpdateOptions},
+        testing::TestServer,
+        transport::Transport,
+    };
+    use http::{Request, Response};
+    use rustify::errors::ClientError as RestClientError;
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use test_log::test;
+
+    /// A [Transport] which forwards requests to a [TestServer] and writes another
+    /// chunked value to `blob` just before the given transaction is sent, as if
+    /// another client raced it.
+    struct RacingTransport {
+        client: ConsulClient,
+        race_at: usize,
+        txns: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Transport for RacingTransport {
+        fn base(&self) -> &str {
+            self.client.http().base()
+        }
+
+        async fn send(&self, req: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, RestClientError> {
+            if req.uri().path() == "/v1/txn"
+                && self.txns.fetch_add(1, Ordering::SeqCst) == self.race_at
+            {
+                kv::chunked::set(&self.client, "blob", b"other", None)
+                    .await
+                    .unwrap();
+            }
+            self.client.http().send(req).await
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn test_kv() {
+        let (_server, client) = common::test_server().await;
+
+        kv::set(&client, "test/a", b"a", None).await.unwrap();
+        kv::set(&client, "test/b/c", b"c", None).await.unwrap();
+
+        let res = kv::read_raw(&client, "test/a", None).await.unwrap();
+        assert_eq!(res.response, b"a");
+
+        let res = kv::read(
+            &client,
+            "test",
+            Some(ReadKeyRequest::builder().recurse(true)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.response.len(), 2);
+
+        let res = kv::keys(
+            &client,
+            "test/",
+            Some(ReadKeysRequest::builder().separator("/")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.response, vec!["test/a", "test/b/"]);
+
+        kv::delete(&client, "test/a", None).await.unwrap();
+        let res = kv::read(&client, "test/a", None).await;
+        assert!(matches!(res, Err(ClientError::NotFound { .. })));
+    }
+
+    #[test(tokio::test)]
+    async fn test_kv_cas() {
+        let (_server, client) = common::test_server().await;
+
+        kv::set(&client, "test", b"1", None).await.unwrap();
+        let index = kv::read(&client, "test", None).await.unwrap().response[0].modify_index;
+
+        let res = kv::set(
+            &client,
+            "test",
+            b"2",
+            Some(SetKeyRequest::builder().cas(index + 1)),
+        )
+        .await
+        .unwrap();
+        assert!(!res.response);
+
+        let res = kv::set(
+            &client,
+            "test",
+            b"2",
+            Some(SetKeyRequest::builder().cas(index)),
+        )
+        .await
+        .unwrap();
+        assert!(res.response);
+    }
+
+    #[test(tokio::test)]
+    async fn test_kv_blocking() {
+        let (server, client) = common::test_server().await;
+
+        kv::set(&client, "test", b"1", None).await.unwrap();
+        let res = kv::read(&client, "test", None).await.unwrap();
+        let index = res.index.unwrap();
+
+        let writer = server.client().unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            kv::set(&writer, "test", b"2", None).await.unwrap();
+        });
+
+        let mut features = Features::builder();
+        features.blocking(consulrs::api::features::Blocking {
+            index,
+            wait: Some(Duration::from_secs(5).into()),
+        });
+        let res = kv::read_raw(
+            &client,
+            "test",
+            Some(
+                consulrs::api::kv::requests::ReadRawKeyRequest::builder()
+                    .features(features.build().unwrap()),
+            ),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.response, b"2");
+    }
+
+    #[test(tokio::test)]
+    async fn test_kv_export_import() {
+        let (_server, client) = common::test_server().await;
+
+        assert!(kv::export(&client, "app/", None)
+            .await
+            .unwrap()
+            .response
+            .is_empty());
+        for i in 0..100 {
+            kv::set(&client, &format!("app/key{:03}", i), b"old", None)
+                .await
+                .unwrap();
+        }
+        let mut entries = kv::export(&client, "app/", None).await.unwrap().response;
+        assert_eq!(entries.len(), 100);
+        let json = serde_json::to_value(&entries[0]).unwrap();
+        assert_eq!(json["key"], "app/key000");
+        assert_eq!(json["flags"], 0);
+        assert_eq!(json["value"], "b2xk");
+
+        // Change one key, add 70 more and drop the last 10 for pruning
+        entries.truncate(90);
+        entries[0].value = b"new".as_ref().into();
+        entries.extend((100..170).map(|i| KVExportEntry {
+            key: format!("app/key{:03}", i),
+            flags: 42,
+            value: b"added".as_ref().into(),
+        }));
+
+        let plan = kv::import(
+            &client,
+            &entries,
+            Some(ImportOptions::builder().prune("app/").dry_run(true)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(plan.added.len(), 70);
+        assert_eq!(plan.changed, vec!["app/key000".to_string()]);
+        assert_eq!(plan.deleted.len(), 10);
+        assert_eq!(plan.unchanged.len(), 89);
+        assert_eq!(
+            kv::keys(&client, "app/", None)
+                .await
+                .unwrap()
+                .response
+                .len(),
+            100
+        );
+
+        let applied = kv::import(
+            &client,
+            &entries,
+            Some(ImportOptions::builder().prune("app/")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(applied, plan);
+        let res = kv::read(&client, "app/key150", None).await.unwrap();
+        assert_eq!(res.response[0].flags, 42);
+        let res = kv::read_raw(&client, "app/key000", None).await.unwrap();
+        assert_eq!(res.response, b"new");
+        assert_eq!(
+            kv::keys(&client, "app/", None)
+                .await
+                .unwrap()
+                .response
+                .len(),
+            160
+        );
+
+        let again = kv::import(&client, &entries, None).await.unwrap();
+        assert!(again.is_empty());
+        assert_eq!(again.unchanged.len(), 160);
+
+        // Large values are split across transactions by size and keys are
+        // normalized like any other request
+        let large = (0..8)
+            .map(|i| KVExportEntry {
+                key: format!("/large{}/key", i),
+                flags: 0,
+                value: vec![i as u8; 100_000].as_slice().into(),
+            })
+            .collect::<Vec<_>>();
+        let plan = kv::import(&client, &large, None).await.unwrap();
+        assert_eq!(plan.added.len(), 8);
+        assert_eq!(plan.added[0], "large0/key");
+        let res = kv::read_raw(&client, "large7/key", None).await.unwrap();
+        assert_eq!(res.response, vec![7u8; 100_000]);
+
+        let invalid = vec![KVExportEntry {
+            key: "app/../key".into(),
+            flags: 0,
+            value: b"value".as_ref().into(),
+        }];
+        assert!(matches!(
+            kv::import(&client, &invalid, None).await,
+            Err(ClientError::InvalidKeyError { .. })
+        ));
+        assert!(matches!(
+            kv::export(&client, "app/..", None).await,
+            Err(ClientError::InvalidKeyError { .. })
+        ));
+    }
+
+    #[test(tokio::test)]
+    async fn test_kv_tree() {
+        let (_server, client) = common::test_server().await;
+
+        for key in [
+            "app/a",
+            "app/db/host",
+            "app/db/port",
+            "app/web/",
+            "application/x",
+        ]
+        .iter()
+        {
+            kv::set(
+                &client,
+                key,
+                key.as_bytes(),
+                Some(SetKeyRequest::builder().flags(7u64)),
+            )
+            .await
+            .unwrap();
+        }
+
+        let root = kv::tree(&client, "app", None).await.unwrap().response;
+        assert_eq!(root.path, "app/");
+        assert_eq!(root.len(), 4);
+        assert_eq!(root.keys.keys().collect::<Vec<_>>(), vec!["a"]);
+        assert_eq!(root.get("db/port").unwrap().key, "app/db/port");
+        assert_eq!(root.folder("db").unwrap().path, "app/db/");
+        assert!(root.folder("web/").unwrap().entry.is_some());
+        assert!(kv::tree(&client, "missing", None)
+            .await
+            .unwrap()
+            .response
+            .is_empty());
+
+        let folders = kv::list_folders(&client, "app", None).await.unwrap();
+        assert_eq!(folders.response, vec!["app/db/", "app/web/"]);
+
+        let copied = kv::copy_tree(&client, "app/db", "copy/db", None)
+            .await
+            .unwrap();
+        assert_eq!(copied, vec!["copy/db/host", "copy/db/port"]);
+        let res = kv::read(&client, "copy/db/host", None).await.unwrap();
+        assert_eq!(res.response[0].flags, 7);
+
+        kv::move_tree(&client, "copy", "moved", None).await.unwrap();
+        assert!(kv::tree(&client, "copy", None)
+            .await
+            .unwrap()
+            .response
+            .is_empty());
+        let res = kv::read_raw(&client, "moved/db/port", None).await.unwrap();
+        assert_eq!(res.response, b"app/db/port");
+
+        // Deleting a folder leaves keys which only share its name untouched
+        kv::delete_tree(&client, "app", None).await.unwrap();
+        assert!(kv::tree(&client, "app", None)
+            .await
+            .unwrap()
+            .response
+            .is_empty());
+        assert!(kv::read(&client, "application/x", None).await.is_ok());
+
+        // Prefixes are normalized, so they can't escape the folder
+        assert!(matches!(
+            kv::delete_tree(&client, "application/..", None).await,
+            Err(ClientError::InvalidKeyError { .. })
+        ));
+        assert!(matches!(
+            kv::tree(&client, "./application", None).await,
+            Err(ClientError::InvalidKeyError { .. })
+        ));
+        assert!(matches!(
+            kv::list_folders(&client, "a//b", None).await,
+            Err(ClientError::InvalidKeyError { .. })
+        ));
+        let folders = kv::list_folders(&client, "/moved", None).await.unwrap();
+        assert_eq!(folders.response, vec!["moved/db/"]);
+
+        // Trees can't be copied or moved into themselves
+        for (src, dst) in [
+            ("moved", "moved"),
+            ("moved", "moved/db"),
+            ("moved/db", "moved"),
+        ] {
+            assert!(matches!(
+                kv::copy_tree(&client, src, dst, None).await,
+                Err(ClientError::InvalidKeyError { .. })
+            ));
+            assert!(matches!(
+                kv::move_tree(&client, src, dst, None).await,
+                Err(ClientError::InvalidKeyError { .. })
+            ));
+        }
+        assert!(matches!(
+            kv::copy_tree(&client, "", "backup", None).await,
+            Err(ClientError::InvalidKeyError { .. })
+        ));
+        let folders = kv::list_folders(&client, "moved", None).await.unwrap();
+        assert_eq!(folders.response, vec!["moved/db/"]);
+        assert!(kv::tree(&client, "backup", None)
+            .await
+            .unwrap()
+            .response
+            .is_empty());
+
+        // The root of the store can't be deleted or moved by accident
+        for prefix in ["", "/"] {
+            assert!(matches!(
+                kv::delete_tree(&client, prefix, None).await,
+                Err(ClientError::InvalidKeyError { .. })
+            ));
+            assert!(matches!(
+                kv::move_tree(&client, prefix, "moved", None).await,
+                Err(ClientError::InvalidKeyError { .. })
+            ));
+        }
+        assert!(kv::read(&client, "application/x", None).await.is_ok());
+
+        // Large trees are moved using several transactions limited by size
+        for i in 0..8 {
+            kv::set(&client, &format!("large/key{}", i), &[i; 100_000], None)
+                .await
+                .unwrap();
+        }
+        let moved = kv::move_tree(&client, "large", "larger", None)
+            .await
+            .unwrap();
+        assert_eq!(moved.len(), 8);
+        let res = kv::read_raw(&client, "larger/key7", None).await.unwrap();
+        assert_eq!(res.response, vec![7u8; 100_000]);
+        assert!(kv::tree(&client, "large", None)
+            .await
+            .unwrap()
+            .response
+            .is_empty());
+    }
+
+    #[test(tokio::test)]
+    async fn test_kv_update() {
+        let (_server, client) = common::test_server().await;
+
+        // A missing key is created
+        let res = kv::update(
+            &client,
+            "counter",
+            |v| {
+                assert!(v.is_none());
+                b"a".to_vec()
+            },
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.response, b"a");
+
+        // Flags of an existing key are preserved
+        kv::set(
+            &client,
+            "counter",
+            b"a",
+            Some(SetKeyRequest::builder().flags(3u64)),
+        )
+        .await
+        .unwrap();
+        kv::update(
+            &client,
+            "counter",
+            |v| [v.unwrap(), b"b".to_vec()].concat(),
+            None,
+        )
+        .await
+        .unwrap();
+        let res = kv::read(&client, "counter", None).await.unwrap();
+        assert_eq!(res.response[0].flags, 3);
+
+        // Concurrent updates are retried until they all succeed
+        let retry = RetryPolicy::builder()
+            .max_attempts(50u32)
+            .initial_backoff(Duration::from_millis(1))
+            .build()
+            .unwrap();
+        let mut opts = vec![UpdateOptions::builder().retry(retry).clone(); 10];
+        let updates = opts.iter_mut().map(|o| {
+            kv::update_json::<u64, _>(
+                &client,
+                "json",
+                |pair| pair.map(|p| p.value + 1).unwrap_or(1),
+                Some(o),
+            )
+        });
+        let results = futures::future::join_all(updates).await;
+        assert!(results.iter().all(|r| r.is_ok()));
+        let res = kv::read_json::<u64, _>(&client, "json", None)
+            .await
+            .unwrap();
+        assert_eq!(res.response.value, 10);
+    }
+
+    #[test(tokio::test)]
+    async fn test_kv_validation() {
+        let server = TestServer::start().await.unwrap();
+        let mut client = server.client().unwrap();
+
+        // Special characters are encoded in the request path
+        let key = "special/a b?c#d%e+f;g";
+        kv::set(&client, key, b"value", None).await.unwrap();
+        let res = kv::read_raw(&client, key, None).await.unwrap();
+        assert_eq!(res.response, b"value");
+        let res = kv::keys(&client, "special/", None).await.unwrap();
+        assert_eq!(res.response, vec![key]);
+
+        // Leading slashes are removed
+        kv::set(&client, "/leading", b"value", None).await.unwrap();
+        let res = kv::read(&client, "leading", None).await.unwrap();
+        assert_eq!(res.response[0].key, "leading");
+        assert_eq!(kv::normalize_key("//folder/").unwrap(), "folder/");
+
+        // Keys which would address a different key are rejected
+        for key in ["", "a//b", "a/./b", "a/../b", ".."] {
+            assert!(matches!(
+                kv::set(&client, key, b"value", None).await,
+                Err(ClientError::InvalidKeyError { .. })
+            ));
+        }
+        assert!(matches!(
+            kv::read(&client, "a//b", None).await,
+            Err(ClientError::InvalidKeyError { .. })
+        ));
+
+        // Values over the limit are rejected before they're sent
+        let value = vec![0u8; 512 * 1024 + 1];
+        assert!(matches!(
+            kv::set(&client, "large", &value, None).await,
+            Err(ClientError::ValueTooLargeError { size, max, .. }) if size == value.len() && max == 512 * 1024
+        ));
+        client.settings.kv_max_value_size = 4;
+        kv::set(&client, "small", b"1234", None).await.unwrap();
+        assert!(matches!(
+            kv::set_json(&client, "small", &"1234", None).await,
+            Err(ClientError::ValueTooLargeError {
+                size: 6,
+                max: 4,
+                ..
+            })
+        ));
+    }
+
+    #[test(tokio::test)]
+    async fn test_kv_chunked() {
+        let (server, client) = common::test_server().await;
+
+        // Values over the limit are split into chunks
+        let value = (0..1_200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        assert!(matches!(
+            kv::set(&client, "blob", &value, None).await,
+            Err(ClientError::ValueTooLargeError { .. })
+        ));
+        let manifest = kv::chunked::set(&client, "blob", &value, None)
+            .await
+            .unwrap();
+        assert_eq!(manifest.chunks, 5);
+        assert_eq!(manifest.size, value.len());
+        let res = kv::chunked::read(&client, "blob", None).await.unwrap();
+        assert_eq!(res.response, value);
+        let res = kv::keys(&client, "blob/", None).await.unwrap();
+        assert_eq!(res.response.len(), 5);
+
+        // The chunks of the previous value are deleted when it's replaced
+        let mut opts = ChunkOptions::builder();
+        opts.chunk_size(1000usize);
+        let value = vec![7u8; 2500];
+        let manifest = kv::chunked::set(&client, "blob", &value, Some(&mut opts))
+            .await
+            .unwrap();
+        assert_eq!(manifest.chunks, 3);
+        let res = kv::keys(&client, "blob/", None).await.unwrap();
+        assert_eq!(res.response.len(), 3);
+        assert!(res.response[0].starts_with(&manifest.folder("blob")));
+        let res = kv::chunked::read(&client, "blob", None).await.unwrap();
+        assert_eq!(res.response, value);
+
+        // Rewriting the same value with a different chunk size doesn't mix up
+        // the chunks of both
+        opts.chunk_size(2500usize);
+        let rewritten = kv::chunked::set(&client, "blob", &value, Some(&mut opts))
+            .await
+            .unwrap();
+        assert_eq!(rewritten.chunks, 1);
+        let res = kv::chunked::read(&client, "blob", None).await.unwrap();
+        assert_eq!(res.response, value);
+        let res = kv::keys(&client, "blob/", None).await.unwrap();
+        assert_eq!(res.response, vec![rewritten.chunk_key("blob", 0)]);
+        opts.chunk_size(1000usize);
+        let manifest = kv::chunked::set(&client, "blob", &value, Some(&mut opts))
+            .await
+            .unwrap();
+        let res = kv::chunked::read(&client, "blob", None).await.unwrap();
+        assert_eq!(res.response, value);
+        let res = kv::keys(&client, "blob/", None).await.unwrap();
+        assert_eq!(res.response.len(), 3);
+
+        // Corrupt and missing chunks are detected
+        kv::set(&client, &manifest.chunk_key("blob", 1), &[0u8; 1000], None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            kv::chunked::read(&client, "blob", None).await,
+            Err(ClientError::InvalidChunkedValueError { .. })
+        ));
+        kv::delete(&client, &manifest.chunk_key("blob", 2), None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            kv::chunked::read(&client, "blob", None).await,
+            Err(ClientError::InvalidChunkedValueError { .. })
+        ));
+
+        // The manifest and chunks are deleted together
+        kv::chunked::delete(&client, "blob", None).await.unwrap();
+        assert!(matches!(
+            kv::chunked::read(&client, "blob", None).await,
+            Err(ClientError::NotFound { .. })
+        ));
+        assert!(kv::keys(&client, "blob", None).await.is_err());
+        kv::chunked::delete(&client, "blob", None).await.unwrap();
+
+        // Empty values don't have any chunks
+        kv::chunked::set(&client, "empty", b"", None).await.unwrap();
+        let res = kv::chunked::read(&client, "empty", None).await.unwrap();
+        assert!(res.response.is_empty());
+
+        // Chunks are limited to fit in a transaction once they're encoded
+        let value = vec![1u8; 1_200_000];
+        let mut opts = ChunkOptions::builder();
+        opts.chunk_size(1_000_000usize);
+        let manifest = kv::chunked::set(&client, "big", &value, Some(&mut opts))
+            .await
+            .unwrap();
+        assert!(manifest.chunk_size < 512 * 1024 / 4 * 3);
+        let res = kv::chunked::read(&client, "big", None).await.unwrap();
+        assert_eq!(res.response, value);
+
+        // The chunks written by a failed write are deleted again
+        let racing = ConsulClient::with_transport(
+            client.settings().clone(),
+            RacingTransport {
+                client: server.client().unwrap(),
+                race_at: 4,
+                txns: AtomicUsize::new(0),
+            },
+        );
+        let value = (0..1_200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        assert!(matches!(
+            kv::chunked::set(&racing, "blob", &value, None).await,
+            Err(ClientError::TransactionError { .. })
+        ));
+        let res = kv::chunked::read(&client, "blob", None).await.unwrap();
+        assert_eq!(res.response, b"other");
+        let res = kv::keys(&client, "blob/", None).await.unwrap();
+        assert_eq!(res.response.len(), 1);
+    }
+
+    #[test(tokio::test)]
+    async fn test_kv_store() {
+        let (_server, client) = common::test_server().await;
+
+        let app = KvStore::new(&client, "apps/web").unwrap();
+        assert_eq!(app.prefix(), "apps/web/");
+        let db = app.scoped("db/").unwrap();
+        assert_eq!(db.prefix(), "apps/web/db/");
+
+        // Keys are joined to the prefix
+        db.put("host", b"10.0.0.1").await.unwrap();
+        db.put_as::<_, codec::Json>("port", &5432).await.unwrap();
+        let res = kv::read_raw(&client, "apps/web/db/host", None)
+            .await
+            .unwrap();
+        assert_eq!(res.response, b"10.0.0.1");
+        let res = db.get("host").await.unwrap();
+        assert_eq!(res.response.unwrap().key, "apps/web/db/host");
+        let res = db.get_as::<u16, codec::Json>("port").await.unwrap();
+        assert_eq!(res.response.unwrap().value, 5432);
+        let res = app.list("").await.unwrap();
+        assert_eq!(res.response, vec!["db/host", "db/port"]);
+
+        // Missing keys are returned as `None`
+        assert!(db.get("missing").await.unwrap().response.is_none());
+        assert!(app.list("missing/").await.unwrap().response.is_empty());
+
+        // Malformed keys are rejected before reaching Consul
+        for key in ["", "/host", "db//host", "../host", "db/./host"] {
+            assert!(matches!(
+                app.get(key).await,
+                Err(ClientError::InvalidKeyError { .. })
+            ));
+        }
+        assert!(matches!(
+            app.scoped("/db"),
+            Err(ClientError::InvalidKeyError { .. })
+        ));
+        assert!(matches!(
+            app.scoped(".."),
+            Err(ClientError::InvalidKeyError { .. })
+        ));
+        assert!(matches!(
+            app.list("db/..").await,
+            Err(ClientError::InvalidKeyError { .. })
+        ));
+        assert!(matches!(
+            KvStore::new(&client, "apps//web"),
+            Err(ClientError::InvalidKeyError { .. })
+        ));
+
+        // Watching returns once the key changes
+        let res = db.watch("host", 0, None).await.unwrap();
+        let index = res.index.unwrap();
+        let (res, _) = tokio::join!(
+            db.watch("host", index, Some(Duration::from_secs(5))),
+            async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                db.put("host", b"10.0.0.2").await.unwrap();
+            }
+        );
+        let res = res.unwrap();
+        assert!(res.index.unwrap() > index);
+        assert_eq!(res.response.unwrap().modify_index, res.index.unwrap());
+
+        // Deleting a watched key returns `None`
+        let index = res.index.unwrap();
+        let (res, _) = tokio::join!(
+            db.watch("host", index, Some(Duration::from_secs(5))),
+            async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                db.delete("host").await.unwrap();
+            }
+        );
+        let res = res.unwrap();
+        assert!(res.response.is_none());
+        assert!(res.index.unwrap() > index);
+    }
+
+    #[test(tokio::test)]
+    async fn test_kv_codecs() {
+        #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+        struct Config {
+            name: String,
+            replicas: u32,
+        }
+
+        async fn roundtrip<C: Codec>(client: &impl Client, key: &str) {
+            let config = Config {
+                name: "web".into(),
+                replicas: 3,
+            };
+            kv::set_as::<_, C>(client, key, &config, None)
+                .await
+                .unwrap();
+            let res = kv::read_as::<Config, C>(client, key, None).await.unwrap();
+            assert_eq!(res.response.key, key);
+            assert_eq!(res.response.value, config);
+        }
+
+        let (_server, client) = common::test_server().await;
+
+        roundtrip::<codec::Json>(&client, "json").await;
+        #[cfg(feature = "msgpack")]
+        roundtrip::<codec::MessagePack>(&client, "msgpack").await;
+        #[cfg(feature = "toml")]
+        roundtrip::<codec::Toml>(&client, "toml").await;
+        #[cfg(feature = "yaml")]
+        roundtrip::<codec::Yaml>(&client, "yaml").await;
+
+        // Values which can't be decoded return the codec's error
+        kv::set(&client, "invalid", b"{", None).await.unwrap();
+        let res = kv::read_as::<Config, codec::Json>(&client, "invalid", None).await;
+        assert!(matches!(res, Err(ClientError::JsonDeserializeError { .. })));
+        #[cfg(feature = "yaml")]
+        {
+            let res = kv::read_as::<Config, codec::Yaml>(&client, "invalid", None).await;
+            assert!(matches!(res, Err(ClientError::YamlDeserializeError { .. })));
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn test_config_watch() {
+        #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+        #[serde(default)]
+        struct Config {
+            name: String,
+            version: String,
+            db: Database,
+        }
+
+        #[derive(Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+        #[serde(default)]
+        struct Database {
+            hosts: Vec<String>,
+            replicas: u32,
+        }
+
+        impl Default for Config {
+            fn default() -> Self {
+                Config {
+                    name: "app".into(),
+                    version: "1.0".into(),
+                    db: Database::default(),
+                }
+            }
+        }
+
+        let (_server, client) = common::test_server().await;
+
+        // Defaults are used when the prefix doesn't exist
+        let res = config_watch::load::<Config>(&client, "apps/web", None)
+            .await
+            .unwrap();
+        assert_eq!(res.response, Config::default());
+
+        // Keys are merged over the defaults with string fields kept as-is
+        kv::set(&client, "apps/web/version", b"2", None)
+            .await
+            .unwrap();
+        kv::set(&client, "apps/web/db/replicas", b"3", None)
+            .await
+            .unwrap();
+        kv::set(&client, "apps/web/db/hosts", br#"["10.0.0.1"]"#, None)
+            .await
+            .unwrap();
+        let res = config_watch::load::<Config>(&client, "apps/web/", None)
+            .await
+            .unwrap();
+        assert_eq!(res.response.name, "app");
+        assert_eq!(res.response.version, "2");
+        assert_eq!(res.response.db.replicas, 3);
+        assert_eq!(res.response.db.hosts, vec!["10.0.0.1"]);
+
+        // Changes are debounced and published to the receiver
+        let mut opts = ConfigWatchOptions::builder();
+        opts.debounce(Duration::from_millis(200))
+            .wait(Duration::from_secs(1));
+        let (watcher, mut rx) = config_watch::watch::<Config>(&client, "apps/web", Some(&mut opts))
+            .await
+            .unwrap();
+        assert_eq!(rx.borrow().value.db.replicas, 3);
+        let updates = async {
+            kv::set(&client, "apps/web/db/replicas", b"4", None)
+                .await
+                .unwrap();
+            kv::set(&client, "apps/web/db/replicas", b"5", None)
+                .await
+                .unwrap();
+            rx.changed().await.unwrap();
+            assert_eq!(rx.borrow().value.db.replicas, 5);
+            assert!(rx.borrow().error.is_none());
+
+            // Invalid configuration is reported while keeping the last value
+            kv::set(&client, "apps/web/db/replicas", b"many", None)
+                .await
+                .unwrap();
+            rx.changed().await.unwrap();
+            assert_eq!(rx.borrow().value.db.replicas, 5);
+            assert!(matches!(
+                rx.borrow().error,
+                Some(ClientError::JsonDeserializeError { .. })
+            ));
+
+            kv::delete(&client, "apps/web/db/replicas", None)
+                .await
+                .unwrap();
+            rx.changed().await.unwrap();
+            assert_eq!(rx.borrow().value.db.replicas, 0);
+            assert!(rx.borrow().error.is_none());
+            drop(rx);
+        };
+
+        // The watcher stops once the receiver is dropped
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            futures::future::join(watcher.run(&client), updates),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[test(tokio::test)]
+    async fn test_config_watch_decode() {
+        #[derive(Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+        #[serde(default)]
+        struct Config {
+            token: Option<String>,
+            labels: HashMap<String, String>,
+            port: Option<u16>,
+        }
+
+        let (_server, client) = common::test_server().await;
+        kv::set(&client, "apps/api/token", b"123", None)
+            .await
+            .unwrap();
+        kv::set(&client, "apps/api/labels/version", b"2", None)
+            .await
+            .unwrap();
+        kv::set(&client, "apps/api/labels/enabled", b"true", None)
+            .await
+            .unwrap();
+        kv::set(&client, "apps/api/labels/owner", b"null", None)
+            .await
+            .unwrap();
+        kv::set(&client, "apps/api/port", b"8080", None)
+            .await
+            .unwrap();
+
+        // Values which look like JSON are kept as strings in string fields and
+        // the prefix is normalized
+        let res = config_watch::load::<Config>(&client, "/apps/api", None)
+            .await
+            .unwrap();
+        assert_eq!(res.response.token.as_deref(), Some("123"));
+        assert_eq!(res.response.labels["version"], "2");
+        assert_eq!(res.response.labels["enabled"], "true");
+        assert_eq!(res.response.labels["owner"], "null");
+        assert_eq!(res.response.port, Some(8080));
+
+        let (_, rx) = config_watch::watch::<Config>(&client, "/apps/api/", None)
+            .await
+            .unwrap();
+        assert_eq!(rx.borrow().value, res.response);
+
+        // Invalid prefixes are rejected
+        let res = config_watch::load::<Config>(&client, "apps/../api", None).await;
+        assert!(matches!(res, Err(ClientError::InvalidKeyError { .. })));
+    }
+}
@@ -0,0 +1,191 @@
+mod common;
+
+use common::MockTransport;
+use consulrs::{
+    client::{Client, ConsulClient, ConsulClientSettingsBuilder, RetryPolicy},
+    error::ClientError,
+    kv, service,
+};
+use std::time::{Duration, Instant};
+use test_log::test;
+
+/// Returns a client which sends requests to the given transport, making up to
+/// three attempts with a short backoff.
+fn mock_client(transport: MockTransport) -> ConsulClient<MockTransport> {
+    ConsulClient::with_transport(
+        ConsulClientSettingsBuilder::default()
+            .retry(
+                RetryPolicy::builder()
+                    .initial_backoff(Duration::from_millis(1))
+                    .jitter(false)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap(),
+        transport,
+    )
+}
+
+#[test]
+fn test_retry_backoff() {
+    let policy = RetryPolicy::builder()
+        .initial_backoff(Duration::from_millis(100))
+        .max_backoff(Duration::from_millis(500))
+        .jitter(false)
+        .build()
+        .unwrap();
+
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(200));
+    assert_eq!(policy.backoff(3), Duration::from_millis(400));
+    assert_eq!(policy.backoff(4), Duration::from_millis(500));
+    assert_eq!(policy.backoff(100), Duration::from_millis(500));
+
+    let policy = RetryPolicy::builder()
+        .initial_backoff(Duration::from_millis(100))
+        .build()
+        .unwrap();
+    assert!(policy.backoff(1) <= Duration::from_millis(100));
+}
+
+#[test]
+fn test_retry_retryable() {
+    let policy = RetryPolicy::default();
+
+    let err = ClientError::APIError {
+        code: 500,
+        message: Some("No cluster leader".into()),
+    };
+    assert!(policy.is_retryable(&err));
+
+    let err = ClientError::APIError {
+        code: 404,
+        message: None,
+    };
+    assert!(!policy.is_retryable(&err));
+    assert!(!policy.is_retryable(&ClientError::EmptyResponseError));
+
+    let err = ClientError::NoLeader {
+        code: 500,
+        message: None,
+    };
+    assert!(policy.is_retryable(&err));
+    assert!(err.is_retryable());
+    assert!(!ClientError::NotFound {
+        index: None,
+        message: None
+    }
+    .is_retryable());
+}
+
+#[test(tokio::test)]
+async fn test_retry_get() {
+    // Server errors are retried until the attempts are exhausted
+    let client = mock_client(MockTransport {
+        body: "No cluster leader",
+        status: Some(500),
+        ..Default::default()
+    });
+    let res = kv::keys(&client, "test", None).await;
+    assert!(matches!(
+        res,
+        Err(ClientError::RetriesExhaustedError { attempts: 3, .. })
+    ));
+    assert_eq!(client.http().requests.lock().unwrap().len(), 3);
+
+    // Connection failures are retried as well
+    let client = mock_client(MockTransport {
+        error: true,
+        ..Default::default()
+    });
+    let res = kv::keys(&client, "test", None).await;
+    assert!(matches!(
+        res,
+        Err(ClientError::RetriesExhaustedError { attempts: 3, .. })
+    ));
+    assert_eq!(client.http().requests.lock().unwrap().len(), 3);
+
+    // Errors outside of the retry codes are returned immediately
+    let client = mock_client(MockTransport {
+        status: Some(403),
+        ..Default::default()
+    });
+    let res = kv::keys(&client, "test", None).await;
+    assert!(matches!(res, Err(ClientError::PermissionDenied { .. })));
+    assert_eq!(client.http().requests.lock().unwrap().len(), 1);
+}
+
+#[test(tokio::test)]
+async fn test_retry_put() {
+    // Writes which aren't idempotent are never retried
+    let client = mock_client(MockTransport {
+        status: Some(500),
+        ..Default::default()
+    });
+    let res = kv::set(&client, "test", b"test", None).await;
+    assert!(matches!(res, Err(ClientError::APIError { code: 500, .. })));
+    assert_eq!(client.http().requests.lock().unwrap().len(), 1);
+
+    let client = mock_client(MockTransport {
+        error: true,
+        ..Default::default()
+    });
+    let res = kv::set(&client, "test", b"test", None).await;
+    assert!(matches!(res, Err(ClientError::RestClientError { .. })));
+    assert_eq!(client.http().requests.lock().unwrap().len(), 1);
+
+    // Writes marked as idempotent are retried
+    let client = mock_client(MockTransport {
+        status: Some(503),
+        ..Default::default()
+    });
+    let res = service::register(&client, "test", None).await;
+    assert!(matches!(
+        res,
+        Err(ClientError::RetriesExhaustedError { attempts: 3, .. })
+    ));
+    assert_eq!(
+        *client.http().requests.lock().unwrap(),
+        vec!["PUT /v1/agent/service/register".to_string(); 3]
+    );
+}
+
+#[test(tokio::test)]
+async fn test_retry_after() {
+    let client = ConsulClient::with_transport(
+        ConsulClientSettingsBuilder::default()
+            .retry(
+                RetryPolicy::builder()
+                    .max_attempts(2u32)
+                    .initial_backoff(Duration::from_millis(1))
+                    .jitter(false)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap(),
+        MockTransport {
+            status: Some(429),
+            headers: vec![("Retry-After", b"1")],
+            ..Default::default()
+        },
+    );
+
+    let start = Instant::now();
+    let res = kv::keys(&client, "test", None).await;
+    assert!(start.elapsed() >= Duration::from_secs(1));
+    match res {
+        Err(ClientError::RetriesExhaustedError { attempts, source }) => {
+            assert_eq!(attempts, 2);
+            assert!(matches!(
+                *source,
+                ClientError::RateLimited {
+                    retry_after: Some(d)
+                } if d == Duration::from_secs(1)
+            ));
+        }
+        r => panic!("Unexpected result: {:?}", r.map(|r| r.response)),
+    }
+    assert_eq!(client.http().requests.lock().unwrap().len(), 2);
+}
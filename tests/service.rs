@@ -51,3 +51,74 @@ async fn test_read(client: &impl Client, name: &str) {
     let res = service::read(client, name, None).await;
     assert!(res.is_ok());
 }
+
+#[cfg(feature = "testing")]
+mod testing {
+    use super::common;
+    use consulrs::{
+        api::{
+            check::{common::AgentServiceCheckBuilder, requests::RegisterCheckRequest},
+            service::requests::RegisterServiceRequest,
+        },
+        catalog, check, health, service,
+    };
+    use std::time::Duration;
+    use test_log::test;
+
+    #[test(tokio::test)]
+    async fn test_services() {
+        let (_server, client) = common::test_server().await;
+
+        service::register(
+            &client,
+            "my_service",
+            Some(
+                RegisterServiceRequest::builder().port(1234_u64).check(
+                    AgentServiceCheckBuilder::default()
+                        .name("health_check")
+                        .ttl(Duration::from_secs(10))
+                        .status("passing")
+                        .build()
+                        .unwrap(),
+                ),
+            ),
+        )
+        .await
+        .unwrap();
+
+        let res = service::list(&client, None).await.unwrap();
+        assert!(res.response.contains_key("my_service"));
+
+        let res = catalog::nodes_with_service(&client, "my_service", None)
+            .await
+            .unwrap();
+        assert_eq!(res.response[0].service_port, Some(1234));
+
+        let res = health::list_nodes_for_service(&client, "my_service", None)
+            .await
+            .unwrap();
+        assert_eq!(res.response[0].checks.len(), 2);
+
+        check::fail(&client, "service:my_service", None)
+            .await
+            .unwrap();
+        let res = service::health(&client, "my_service", None).await.unwrap();
+        assert_eq!(res.response[0].aggregated_status, "critical");
+
+        check::register(
+            &client,
+            "other",
+            Some(RegisterCheckRequest::builder().ttl(Duration::from_secs(10))),
+        )
+        .await
+        .unwrap();
+        let res = check::list(&client, None).await.unwrap();
+        assert!(res.response.contains_key("other"));
+
+        service::deregister(&client, "my_service", None)
+            .await
+            .unwrap();
+        let res = service::list(&client, None).await.unwrap();
+        assert!(res.response.is_empty());
+    }
+}
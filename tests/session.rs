@@ -2,6 +2,7 @@ mod common;
 
 use common::{ConsulServer, ConsulServerHelper};
 use consulrs::{api::session::requests::CreateSessionRequest, client::Client, session};
+use std::time::Duration;
 use test_log::test;
 
 #[test]
@@ -22,7 +23,11 @@ fn test() {
 }
 
 async fn test_create(client: &impl Client) -> String {
-    let res = session::create(client, Some(CreateSessionRequest::builder().ttl("10m"))).await;
+    let res = session::create(
+        client,
+        Some(CreateSessionRequest::builder().ttl(Duration::from_secs(600))),
+    )
+    .await;
     assert!(res.is_ok());
 
     res.unwrap().response.id.clone()
@@ -52,3 +57,69 @@ async fn test_renew(client: &impl Client, name: &str) {
     let res = session::renew(client, name, None).await;
     assert!(res.is_ok());
 }
+
+#[cfg(feature = "testing")]
+mod testing {
+    use super::common;
+    use consulrs::{
+        api::{kv::requests::SetKeyRequest, session::requests::CreateSessionRequest},
+        kv, session,
+    };
+    use std::time::Duration;
+    use test_log::test;
+
+    #[test(tokio::test)]
+    async fn test_session_locks() {
+        let (_server, client) = common::test_server().await;
+
+        let id = session::create(&client, Some(CreateSessionRequest::builder().name("test")))
+            .await
+            .unwrap()
+            .response
+            .id;
+
+        let res = kv::set(
+            &client,
+            "lock",
+            b"",
+            Some(SetKeyRequest::builder().acquire(&id)),
+        )
+        .await
+        .unwrap();
+        assert!(res.response);
+        let res = kv::read(&client, "lock", None).await.unwrap();
+        assert_eq!(res.response[0].session.as_deref(), Some(id.as_str()));
+
+        let res = session::list(&client, None).await.unwrap();
+        assert_eq!(res.response.len(), 1);
+
+        session::delete(&client, &id, None).await.unwrap();
+        let res = kv::read(&client, "lock", None).await.unwrap();
+        assert!(res.response[0].session.is_none());
+    }
+
+    #[test(tokio::test)]
+    async fn test_session_durations() {
+        let (_server, client) = common::test_server().await;
+
+        let id = session::create(
+            &client,
+            Some(
+                CreateSessionRequest::builder()
+                    .lock_delay(Duration::from_secs(30))
+                    .ttl(Duration::from_secs(90)),
+            ),
+        )
+        .await
+        .unwrap()
+        .response
+        .id;
+
+        let res = session::read(&client, &id, None).await.unwrap();
+        assert_eq!(
+            res.response[0].lock_delay,
+            Some(Duration::from_secs(30).into())
+        );
+        assert_eq!(res.response[0].ttl, Some("1m30s".parse().unwrap()));
+    }
+}
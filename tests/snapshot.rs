@@ -2,6 +2,7 @@ mod common;
 
 use common::{ConsulServer, ConsulServerHelper};
 use consulrs::{client::Client, snapshot};
+use sha2::Digest;
 use test_log::test;
 
 #[test]
@@ -27,3 +28,233 @@ async fn test_restore(client: &impl Client, snapshot: &[u8]) {
     let res = snapshot::restore(client, snapshot, None).await;
     assert!(res.is_ok());
 }
+
+#[test]
+fn test_snapshot_retention() {
+    let names: Vec<String> = vec![
+        "consul-20211018T100000Z-1.snap",
+        "consul-20211018T120000Z-2.snap",
+        "consul-20211019T090000Z-3.snap",
+        "consul-20211019T100000Z-4.snap",
+        "consul-20211019T110000Z-5.snap",
+        "consul-20211025T090000Z-6.snap",
+        "consul-20211025T100000Z-7.snap",
+        "other-20211025T100000Z-8.snap",
+        "consul-latest.snap",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect();
+    let policy = |last: usize, daily: usize, weekly: usize| {
+        let mut expired = snapshot::Retention::builder()
+            .keep_last(last)
+            .keep_daily(daily)
+            .keep_weekly(weekly)
+            .build()
+            .unwrap()
+            .expired("consul", &names);
+        expired.sort();
+        expired
+            .into_iter()
+            .map(|n| n.rsplit('-').next().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        policy(2, 0, 0),
+        vec!["1.snap", "2.snap", "3.snap", "4.snap", "5.snap"]
+    );
+    assert_eq!(
+        policy(0, 2, 0),
+        vec!["1.snap", "2.snap", "3.snap", "4.snap", "6.snap"]
+    );
+    assert_eq!(
+        policy(1, 0, 2),
+        vec!["1.snap", "2.snap", "3.snap", "4.snap", "6.snap"]
+    );
+    assert_eq!(
+        policy(0, 3, 0),
+        vec!["1.snap", "3.snap", "4.snap", "6.snap"]
+    );
+    assert_eq!(
+        policy(0, 0, 0),
+        vec!["1.snap", "2.snap", "3.snap", "4.snap", "5.snap", "6.snap"]
+    );
+}
+
+#[test]
+fn test_snapshot_record_types() {
+    // A header followed by records for the peering types, a known type with
+    // the ignore flag set and a type which isn't known
+    let mut state = Vec::new();
+    let header = rmpv::Value::Map(vec![("LastIndex".into(), 10.into())]);
+    rmpv::encode::write_value(&mut state, &header).unwrap();
+    for ty in [36u8, 37, 0x80 | 2, 0x80 | 120] {
+        state.push(ty);
+        rmpv::encode::write_value(&mut state, &rmpv::Value::Nil).unwrap();
+    }
+
+    let meta = br#"{"ID":"1-10-0","Index":10,"Term":1,"Version":1}"#;
+    let sums = format!(
+        "{}  meta.json\n{}  state.bin\n",
+        hex::encode(sha2::Sha256::digest(meta)),
+        hex::encode(sha2::Sha256::digest(&state))
+    );
+    let mut archive = tar::Builder::new(flate2::write::GzEncoder::new(
+        Vec::new(),
+        flate2::Compression::default(),
+    ));
+    for (name, content) in [
+        ("meta.json", &meta[..]),
+        ("state.bin", state.as_slice()),
+        ("SHA256SUMS", sums.as_bytes()),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o600);
+        archive.append_data(&mut header, name, content).unwrap();
+    }
+    let archive = archive.into_inner().unwrap().finish().unwrap();
+
+    let info = snapshot::inspect(&archive).unwrap();
+    assert_eq!(info.last_index, 10);
+    assert_eq!(info.records["PeeringDelete"], 1);
+    assert_eq!(info.records["PeeringTerminateByID"], 1);
+    assert_eq!(info.records["KVS"], 1);
+    assert_eq!(info.records["Unknown(120)"], 1);
+    assert_eq!(info.total_records(), 4);
+}
+
+#[cfg(feature = "testing")]
+mod testing {
+    use super::common;
+    use consulrs::{
+        error::ClientError,
+        kv,
+        snapshot::{self, Sink},
+    };
+    use std::{sync::Arc, time::Duration};
+    use test_log::test;
+
+    #[test(tokio::test)]
+    async fn test_snapshot_stream() {
+        let (_server, client) = common::test_server().await;
+
+        // Large enough to require multiple chunks in both directions
+        let data: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        snapshot::restore_from(&client, std::io::Cursor::new(data.clone()), None)
+            .await
+            .unwrap();
+
+        let mut backup = Vec::new();
+        let res = snapshot::backup_to(&client, &mut backup, None)
+            .await
+            .unwrap();
+        assert_eq!(res.response, data.len() as u64);
+        assert!(res.index.unwrap() > 1);
+        assert!(backup == data);
+
+        let res = snapshot::backup(&client, None).await.unwrap();
+        assert!(res.response == data);
+    }
+
+    #[test(tokio::test)]
+    async fn test_snapshot_inspect() {
+        let (_server, client) = common::test_server().await;
+
+        kv::set(&client, "test1", b"value1", None).await.unwrap();
+        kv::set(&client, "test2", b"value2", None).await.unwrap();
+        let res = snapshot::backup(&client, None).await.unwrap();
+        let info = snapshot::inspect(&res.response).unwrap();
+        assert_eq!(info.meta.index, res.index.unwrap());
+        assert_eq!(info.last_index, res.index.unwrap());
+        assert_eq!(info.records["KVS"], 2);
+        assert_eq!(info.records["Register"], 1);
+        assert_eq!(info.records["Index"], 3);
+        assert_eq!(info.total_records(), 6);
+
+        // Flip a byte in the state without updating its checksum
+        let mut tampered = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(res.response.as_slice()));
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut header = entry.header().clone();
+            let name = entry.path().unwrap().into_owned();
+            let mut content = Vec::new();
+            std::io::Read::read_to_end(&mut entry, &mut content).unwrap();
+            if name.to_str() == Some("state.bin") {
+                let last = content.len() - 1;
+                content[last] ^= 0xff;
+            }
+            tampered
+                .append_data(&mut header, name, content.as_slice())
+                .unwrap();
+        }
+        let tampered = tampered.into_inner().unwrap().finish().unwrap();
+        assert!(matches!(
+            snapshot::inspect(&tampered),
+            Err(ClientError::SnapshotChecksumError { file }) if file == "state.bin"
+        ));
+
+        assert!(matches!(
+            snapshot::inspect(b"not a snapshot"),
+            Err(ClientError::InvalidSnapshotError { .. })
+        ));
+    }
+
+    #[test(tokio::test)]
+    async fn test_snapshot_agent() {
+        let (_server, client) = common::test_server().await;
+        let dir = std::env::temp_dir().join(format!("consulrs-agent-{}", std::process::id()));
+        let spool = dir.join("spool");
+        let sink = Arc::new(snapshot::LocalSink::new(dir.join("sink")));
+
+        let agent = snapshot::Agent::builder()
+            .lock_key("snapshot/leader")
+            .retention(
+                snapshot::Retention::builder()
+                    .keep_last(1usize)
+                    .keep_daily(0usize)
+                    .keep_weekly(0usize)
+                    .build()
+                    .unwrap(),
+            )
+            .sink(sink.clone())
+            .spool_dir(&spool)
+            .build()
+            .unwrap();
+        let other = snapshot::Agent::builder()
+            .lock_key("snapshot/leader")
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+
+        let saved = agent.snapshot(&client).await.unwrap().unwrap();
+        assert!(saved.name.starts_with("consul-"));
+        assert!(saved
+            .name
+            .ends_with(&format!("-{}.snap", saved.info.meta.index)));
+        assert!(saved.pruned.is_empty());
+        assert!(other.snapshot(&client).await.unwrap().is_none());
+
+        // Names only have a resolution of a second
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        kv::set(&client, "test", b"value", None).await.unwrap();
+        let next = agent.snapshot(&client).await.unwrap().unwrap();
+        assert_eq!(next.pruned, vec![saved.name]);
+        assert_eq!(sink.list().await.unwrap(), vec![next.name.clone()]);
+        let data = std::fs::read(dir.join("sink").join(&next.name)).unwrap();
+        // Snapshots are streamed through the spool directory without leaving
+        // anything behind
+        assert_eq!(std::fs::read_dir(&spool).unwrap().count(), 0);
+        // The lock key is stored alongside the written key
+        assert_eq!(snapshot::inspect(&data).unwrap().records["KVS"], 2);
+
+        agent.release(&client).await.unwrap();
+        assert!(other.snapshot(&client).await.unwrap().is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use common::MockTransport;
+use consulrs::{
+    client::{Client, ConsulClient, ConsulClientSettingsBuilder},
+    kv,
+    token::{FileTokenProvider, FnTokenProvider, TokenProvider},
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use test_log::test;
+
+#[test(tokio::test)]
+async fn test_token_provider() {
+    let path = std::env::temp_dir().join(format!("consulrs-token-{}", std::process::id()));
+    std::fs::write(&path, "first\n").unwrap();
+    let client = ConsulClient::with_transport(
+        ConsulClientSettingsBuilder::default()
+            .token_file(path.to_str().unwrap())
+            .build()
+            .unwrap(),
+        MockTransport {
+            body: "true",
+            ..Default::default()
+        },
+    );
+
+    // The file is only checked for changes once per refresh interval
+    kv::set(&client, "test", b"test", None).await.unwrap();
+    std::fs::write(&path, "second-token").unwrap();
+    kv::set(&client, "test", b"test", None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    kv::set(&client, "test", b"test", None).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        client.http().tokens.lock().unwrap().as_slice(),
+        &[
+            Some("first".to_string()),
+            Some("first".to_string()),
+            Some("second-token".to_string())
+        ]
+    );
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let counter = count.clone();
+    let provider = FnTokenProvider::new(move || {
+        let n = counter.fetch_add(1, Ordering::SeqCst);
+        Ok(Some(format!("token-{}", n)))
+    });
+    let client = ConsulClient::with_transport(
+        ConsulClientSettingsBuilder::default()
+            .token_provider(Arc::new(provider))
+            .build()
+            .unwrap(),
+        MockTransport {
+            body: "true",
+            ..Default::default()
+        },
+    );
+
+    kv::set(&client, "test", b"test", None).await.unwrap();
+    kv::set(&client, "test", b"test", None).await.unwrap();
+    assert_eq!(
+        client.http().tokens.lock().unwrap().as_slice(),
+        &[Some("token-0".to_string()), Some("token-1".to_string())]
+    );
+}
+
+#[test]
+fn test_file_token_provider() {
+    let path = std::env::temp_dir().join(format!("consulrs-token-file-{}", std::process::id()));
+    std::fs::write(&path, "first").unwrap();
+
+    // A zero interval checks the file on every call
+    let provider = FileTokenProvider::new(&path).with_refresh_interval(Duration::ZERO);
+    assert_eq!(provider.token().unwrap(), Some("first".to_string()));
+    std::fs::write(&path, " \n").unwrap();
+    assert_eq!(provider.token().unwrap(), None);
+
+    let provider = FileTokenProvider::new(&path).with_refresh_interval(Duration::from_secs(60));
+    assert_eq!(provider.token().unwrap(), None);
+    std::fs::write(&path, "second").unwrap();
+    assert_eq!(provider.token().unwrap(), None);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test(tokio::test)]
+async fn test_token_precedence() {
+    let path = std::env::temp_dir().join(format!("consulrs-token-env-{}", std::process::id()));
+    std::fs::write(&path, "file").unwrap();
+    std::env::set_var("CONSUL_HTTP_TOKEN_FILE", &path);
+
+    let token = |settings: &mut ConsulClientSettingsBuilder| {
+        let client = ConsulClient::with_transport(
+            settings.build().unwrap(),
+            MockTransport {
+                body: "true",
+                ..Default::default()
+            },
+        );
+        async move {
+            kv::set(&client, "test", b"test", None).await.unwrap();
+            let tokens = client.http().tokens.lock().unwrap();
+            tokens[0].clone()
+        }
+    };
+
+    // An explicit token takes precedence over both token files
+    let res = token(ConsulClientSettingsBuilder::default().token("explicit")).await;
+    assert_eq!(res.as_deref(), Some("explicit"));
+    let res = token(
+        ConsulClientSettingsBuilder::default()
+            .token("explicit")
+            .token_file(path.to_str().unwrap()),
+    )
+    .await;
+    assert_eq!(res.as_deref(), Some("explicit"));
+
+    // The token file from the environment is used when nothing else is set
+    let res = token(&mut ConsulClientSettingsBuilder::default()).await;
+    assert_eq!(res.as_deref(), Some("file"));
+
+    // The token from the environment takes precedence over its token file
+    std::env::set_var("CONSUL_HTTP_TOKEN", "env");
+    let res = token(&mut ConsulClientSettingsBuilder::default()).await;
+    assert_eq!(res.as_deref(), Some("env"));
+
+    std::env::remove_var("CONSUL_HTTP_TOKEN");
+    std::env::remove_var("CONSUL_HTTP_TOKEN_FILE");
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,243 @@
+mod common;
+
+use common::MockTransport;
+use consulrs::{
+    catalog,
+    client::{Client, ConsulClient, ConsulClientSettingsBuilder, RetryPolicy},
+    error::ClientError,
+    kv,
+    transport::Fixture,
+};
+use rustify::errors::ClientError as RestClientError;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use test_log::test;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// Responds to a single HTTP request with the given JSON body.
+async fn respond(mut socket: impl AsyncRead + AsyncWrite + Unpin, body: &str) {
+    let mut buf = [0; 4096];
+    let _ = socket.read(&mut buf).await;
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    let _ = socket.write_all(response.as_bytes()).await;
+}
+
+/// Starts a minimal HTTP server which responds to every request with the given
+/// JSON body and returns its address.
+async fn stub_server(body: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            respond(socket, body).await;
+        }
+    });
+    address
+}
+
+/// Starts a minimal HTTP server bound to a Unix domain socket which responds
+/// to every request with the given JSON body and returns its address.
+#[cfg(unix)]
+async fn stub_socket_server(body: &'static str) -> String {
+    let path = std::env::temp_dir().join(format!("consulrs-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            respond(socket, body).await;
+        }
+    });
+    format!("unix://{}", path.display())
+}
+
+#[test(tokio::test)]
+async fn test_custom_transport() {
+    let transport = MockTransport {
+        body: "true",
+        ..Default::default()
+    };
+    let client = ConsulClient::with_transport(
+        ConsulClientSettingsBuilder::default().build().unwrap(),
+        transport,
+    );
+
+    let res = kv::set(&client, "test", b"test", None).await;
+    assert!(res.is_ok());
+    assert!(res.unwrap().response);
+    assert_eq!(
+        client.http().requests.lock().unwrap().as_slice(),
+        &["PUT /v1/kv/test".to_string()]
+    );
+}
+
+#[test(tokio::test)]
+async fn test_failover() {
+    let healthy = stub_server(r#"["dc1"]"#).await;
+    let client = ConsulClient::new(
+        ConsulClientSettingsBuilder::default()
+            .address("http://127.0.0.1:1")
+            .addresses(vec![healthy.clone()])
+            .retry(RetryPolicy::disabled())
+            .build()
+            .unwrap(),
+    )
+    .unwrap();
+
+    let res = catalog::datacenters(&client, None).await;
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap().response, vec!["dc1".to_string()]);
+    assert_eq!(client.http().healthy_addresses(), vec![healthy]);
+}
+
+#[test(tokio::test)]
+async fn test_address_validation() {
+    // Addresses without a scheme default to http://
+    let healthy = stub_server(r#"["dc1"]"#).await;
+    let client = ConsulClient::new(
+        ConsulClientSettingsBuilder::default()
+            .address(healthy.trim_start_matches("http://"))
+            .retry(RetryPolicy::disabled())
+            .build()
+            .unwrap(),
+    )
+    .unwrap();
+    let res = catalog::datacenters(&client, None).await;
+    assert_eq!(res.unwrap().response, vec!["dc1".to_string()]);
+    assert_eq!(client.http().healthy_addresses(), vec![healthy.clone()]);
+
+    // Any other invalid address is rejected when the client is built
+    for address in ["ftp://127.0.0.1:8500", "http://", "http://local host:8500"] {
+        let res = ConsulClient::new(
+            ConsulClientSettingsBuilder::default()
+                .address(healthy.clone())
+                .addresses(vec![healthy.clone(), address.to_string()])
+                .build()
+                .unwrap(),
+        );
+        assert!(matches!(res, Err(ClientError::InvalidAddressError { .. })));
+    }
+}
+
+#[test(tokio::test)]
+async fn test_failover_after_connect() {
+    // The first address accepts the request and then drops the connection
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let dropping = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = socket.read(&mut buf).await;
+        }
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let other = format!("http://{}", listener.local_addr().unwrap());
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let count = accepted.clone();
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            count.fetch_add(1, Ordering::SeqCst);
+            respond(socket, "true").await;
+        }
+    });
+
+    let client = ConsulClient::new(
+        ConsulClientSettingsBuilder::default()
+            .addresses(vec![dropping.clone(), other])
+            .retry(RetryPolicy::disabled())
+            .build()
+            .unwrap(),
+    )
+    .unwrap();
+
+    // The write may have been applied, so it isn't sent to another address
+    let res = kv::set(&client, "test", b"test", None).await;
+    assert!(res.is_err());
+    assert_eq!(accepted.load(Ordering::SeqCst), 0);
+    assert!(client.http().healthy_addresses().contains(&dropping));
+}
+
+#[test(tokio::test)]
+async fn test_record_replay() {
+    let path = std::env::temp_dir().join(format!("consulrs-{}.json", std::process::id()));
+    let settings = || {
+        ConsulClientSettingsBuilder::default()
+            .token("secret")
+            .build()
+            .unwrap()
+    };
+
+    let transport = MockTransport {
+        body: "true",
+        ..Default::default()
+    };
+    let client = ConsulClient::with_transport(settings(), transport).record(&path);
+    let res = kv::set(&client, "test", b"test", None).await;
+    assert!(res.is_ok());
+    assert!(!path.exists());
+    client.http().finish().unwrap();
+
+    let fixture = Fixture::load(&path).unwrap();
+    assert_eq!(fixture.interactions.len(), 1);
+    let request = &fixture.interactions[0].request;
+    assert_eq!(request.path, "/v1/kv/test");
+    assert_eq!(request.headers["x-consul-token"], "<redacted>");
+    assert!(!std::fs::read_to_string(&path).unwrap().contains("secret"));
+
+    let client = ConsulClient::replay(settings(), &path).unwrap();
+    let res = kv::set(&client, "test", b"test", None).await;
+    assert!(res.is_ok());
+    assert!(res.unwrap().response);
+    assert!(client.http().remaining().is_empty());
+
+    let res = kv::set(&client, "other", b"test", None).await;
+    assert!(matches!(
+        res,
+        Err(ClientError::RestClientError {
+            source: RestClientError::GenericError { .. }
+        })
+    ));
+
+    // Interactions which weren't saved are written when the client is dropped
+    let transport = MockTransport {
+        body: "true",
+        ..Default::default()
+    };
+    let client = ConsulClient::with_transport(settings(), transport).record(&path);
+    kv::set(&client, "first", b"test", None).await.unwrap();
+    kv::set(&client, "second", b"test", None).await.unwrap();
+    drop(client);
+    assert_eq!(Fixture::load(&path).unwrap().interactions.len(), 2);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test(tokio::test)]
+async fn test_unix_socket() {
+    let address = stub_socket_server(r#"["dc1"]"#).await;
+    let client = ConsulClient::new(
+        ConsulClientSettingsBuilder::default()
+            .address(address)
+            .build()
+            .unwrap(),
+    )
+    .unwrap();
+
+    let res = catalog::datacenters(&client, None).await;
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap().response, vec!["dc1".to_string()]);
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use common::{ConsulServer, ConsulServerHelper, MockTransport};
+use consulrs::{
+    api::txn::common::{KVTxnOp, TxnOp},
+    client::{Client, ConsulClient, ConsulClientSettingsBuilder},
+    error::ClientError,
+    kv, txn,
+};
+use test_log::test;
+
+#[test]
+fn test() {
+    let test = common::new_test();
+    test.run(|instance| async move {
+        let server: ConsulServer = instance.server();
+        let client = server.client();
+
+        test_execute(&client).await;
+        test_rollback(&client).await;
+    });
+}
+
+async fn test_execute(client: &impl Client) {
+    let ops: Vec<TxnOp> = vec![
+        KVTxnOp::set("txn/a", b"1").into(),
+        KVTxnOp::cas("txn/b", b"2", 0).into(),
+    ];
+    let res = txn::execute(client, &ops, None).await;
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap().response.len(), 2);
+}
+
+async fn test_rollback(client: &impl Client) {
+    let ops: Vec<TxnOp> = vec![
+        KVTxnOp::set("txn/a", b"3").into(),
+        KVTxnOp::cas("txn/b", b"4", 0).into(),
+    ];
+    let res = txn::execute(client, &ops, None).await;
+    assert!(matches!(res, Err(ClientError::TransactionError { .. })));
+
+    let res = kv::read_raw(client, "txn/a", None).await;
+    assert_eq!(res.unwrap().response, b"1");
+}
+
+#[test(tokio::test)]
+async fn test_rollback_errors() {
+    // Errors mentioning a known message are still reported as transaction
+    // errors
+    let client = ConsulClient::with_transport(
+        ConsulClientSettingsBuilder::default().build().unwrap(),
+        MockTransport {
+            body: r#"{"Errors":[{"OpIndex":1,"What":"Permission denied"}]}"#,
+            status: Some(409),
+            ..Default::default()
+        },
+    );
+    let ops: Vec<TxnOp> = vec![KVTxnOp::set("txn/a", b"1").into()];
+    match txn::execute(&client, &ops, None).await {
+        Err(ClientError::TransactionError { errors }) => {
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].op_index, 1);
+            assert_eq!(errors[0].what, "Permission denied");
+        }
+        r => panic!("Unexpected result: {:?}", r.map(|r| r.response)),
+    }
+}
+
+#[cfg(feature = "testing")]
+mod testing {
+    use super::common;
+    use consulrs::{
+        api::txn::common::{KVTxnOp, TxnOp},
+        error::ClientError,
+        kv, txn,
+    };
+    use test_log::test;
+
+    #[test(tokio::test)]
+    async fn test_txn() {
+        let (_server, client) = common::test_server().await;
+
+        let ops: Vec<TxnOp> = vec![
+            KVTxnOp::set("txn/a", b"1").into(),
+            KVTxnOp::cas("txn/b", b"2", 0).into(),
+        ];
+        let res = txn::execute(&client, &ops, None).await.unwrap();
+        assert_eq!(res.response.len(), 2);
+        assert_eq!(res.response[0].kv.as_ref().unwrap().key, "txn/a");
+
+        // The failed check rolls back the write before it
+        let ops: Vec<TxnOp> = vec![
+            KVTxnOp::set("txn/a", b"3").into(),
+            KVTxnOp::check_index("txn/b", 1).into(),
+        ];
+        match txn::execute(&client, &ops, None).await {
+            Err(ClientError::TransactionError { errors }) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].op_index, 1);
+            }
+            r => panic!("Expected a transaction error: {:?}", r),
+        }
+        let res = kv::read_raw(&client, "txn/a", None).await.unwrap();
+        assert_eq!(res.response, b"1");
+    }
+}